@@ -0,0 +1,75 @@
+//! Compares copying and splicing request data through a mounted [`PassthroughFs`].
+//!
+//! `cargo bench --bench splice` writes a file through the mount and reads a file
+//! that the mount has not cached yet, once per configuration. It needs FUSE and
+//! skips itself without it.
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use rusfuse::testing::mount_temp_with_builder;
+use rusfuse::{PassthroughFs, SpliceConfig};
+
+const BLOCK: usize = 1 << 20;
+const BLOCKS: usize = 256;
+
+fn main() {
+    let source = std::env::temp_dir().join(format!("rusfuse-bench-{}", std::process::id()));
+    fs::create_dir_all(&source).unwrap();
+    let configs = [
+        ("copy", SpliceConfig::default()),
+        ("splice", SpliceConfig::all()),
+    ];
+    for &(name, splice) in configs.iter() {
+        fill(&source.join("input"));
+        let file_system = PassthroughFs::new(&source).unwrap();
+        let mount =
+            match mount_temp_with_builder(file_system, move |builder| builder.splice(splice)) {
+                Some(mount) => mount,
+                None => break,
+            };
+        let write = time(|| {
+            let mut file = File::create(mount.path().join("output")).unwrap();
+            let block = vec![0x5a; BLOCK];
+            for _ in 0..BLOCKS {
+                file.write_all(&block).unwrap();
+            }
+            file.sync_all().unwrap();
+        });
+        let read = time(|| {
+            let mut file = File::open(mount.path().join("input")).unwrap();
+            let mut block = vec![0; BLOCK];
+            for _ in 0..BLOCKS {
+                file.read_exact(&mut block).unwrap();
+            }
+        });
+        drop(mount);
+        println!(
+            "{:<6}  write {:>8.1} MiB/s  read {:>8.1} MiB/s",
+            name,
+            throughput(write),
+            throughput(read)
+        );
+    }
+    let _ = fs::remove_dir_all(&source);
+}
+
+fn fill(path: &Path) {
+    let mut file = File::create(path).unwrap();
+    let block = vec![0xa5; BLOCK];
+    for _ in 0..BLOCKS {
+        file.write_all(&block).unwrap();
+    }
+}
+
+fn time<F: FnOnce()>(f: F) -> Duration {
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
+
+fn throughput(elapsed: Duration) -> f64 {
+    (BLOCK * BLOCKS) as f64 / (1 << 20) as f64 / elapsed.as_secs_f64()
+}
@@ -0,0 +1,345 @@
+//! Compares the layout of the structs shared with libfuse against its installed headers.
+//!
+//! A small C program prints the sizes, offsets and flag values seen by the C compiler
+//! next to the ones of the Rust mirrors in `src/fuse.rs`; `src/fuse/abi.rs` includes
+//! the result and fails to build, or `cargo test` fails, when they differ. Without the
+//! headers or when cross compiling the check is skipped.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// `(C struct, Rust struct, [(C field, Rust field)])`
+type Struct = (
+    &'static str,
+    &'static str,
+    &'static [(&'static str, &'static str)],
+);
+
+const STRUCTS: &[Struct] = &[
+    (
+        "fuse_args",
+        "FuseArgs",
+        &[
+            ("argc", "argc"),
+            ("argv", "argv"),
+            ("allocated", "allocated"),
+        ],
+    ),
+    (
+        "fuse_conn_info",
+        "FuseConnInfo",
+        &[
+            ("proto_major", "proto_major"),
+            ("proto_minor", "proto_minor"),
+            ("max_write", "max_write"),
+            ("max_read", "max_read"),
+            ("max_readahead", "max_readahead"),
+            ("capable", "capable"),
+            ("want", "want"),
+            ("max_background", "max_background"),
+            ("congestion_threshold", "congestion_threshold"),
+            ("time_gran", "time_gran"),
+            ("reserved", "reserved"),
+        ],
+    ),
+    (
+        "fuse_loop_config",
+        "FuseLoopConfig",
+        &[
+            ("clone_fd", "clone_fd"),
+            ("max_idle_threads", "max_idle_threads"),
+        ],
+    ),
+    (
+        "fuse_buf",
+        "FuseBuf",
+        &[
+            ("size", "size"),
+            ("flags", "flags"),
+            ("mem", "mem"),
+            ("fd", "fd"),
+            ("pos", "pos"),
+        ],
+    ),
+    (
+        "fuse_bufvec",
+        "FuseBufvec",
+        &[
+            ("count", "count"),
+            ("idx", "idx"),
+            ("off", "off"),
+            ("buf", "buf"),
+        ],
+    ),
+    (
+        "fuse_file_info",
+        "FuseFileInfo",
+        &[
+            ("flags", "flags"),
+            ("fh", "fh"),
+            ("lock_owner", "lock_owner"),
+            ("poll_events", "poll_events"),
+        ],
+    ),
+    (
+        "fuse_forget_data",
+        "FuseForgetData",
+        &[("ino", "ino"), ("nlookup", "nlookup")],
+    ),
+    (
+        "fuse_ctx",
+        "FuseCtx",
+        &[
+            ("uid", "uid"),
+            ("gid", "gid"),
+            ("pid", "pid"),
+            ("umask", "umask"),
+        ],
+    ),
+    (
+        "fuse_entry_param",
+        "FuseEntryParam",
+        &[
+            ("ino", "ino"),
+            ("generation", "generation"),
+            ("attr", "attr"),
+            ("attr_timeout", "attr_timeout"),
+            ("entry_timeout", "entry_timeout"),
+        ],
+    ),
+    (
+        "fuse_lowlevel_ops",
+        "FuseLowLevelOps",
+        &[
+            ("init", "init"),
+            ("destroy", "destroy"),
+            ("lookup", "lookup"),
+            ("forget", "forget"),
+            ("getattr", "getattr"),
+            ("setattr", "setattr"),
+            ("readlink", "readlink"),
+            ("mknod", "mknod"),
+            ("mkdir", "mkdir"),
+            ("unlink", "unlink"),
+            ("rmdir", "rmdir"),
+            ("symlink", "symlink"),
+            ("rename", "rename"),
+            ("link", "link"),
+            ("open", "open"),
+            ("read", "read"),
+            ("write", "write"),
+            ("flush", "flush"),
+            ("release", "release"),
+            ("fsync", "fsync"),
+            ("opendir", "opendir"),
+            ("readdir", "readdir"),
+            ("releasedir", "releasedir"),
+            ("fsyncdir", "fsyncdir"),
+            ("statfs", "statfs"),
+            ("setxattr", "setxattr"),
+            ("getxattr", "getxattr"),
+            ("listxattr", "listxattr"),
+            ("removexattr", "removexattr"),
+            ("access", "access"),
+            ("create", "create"),
+            ("getlk", "getlk"),
+            ("setlk", "setlk"),
+            ("bmap", "bmap"),
+            ("ioctl", "ioctl"),
+            ("poll", "poll"),
+            ("write_buf", "write_buf"),
+            ("retrieve_reply", "retrieve_reply"),
+            ("forget_multi", "forget_multi"),
+            ("flock", "flock"),
+            ("fallocate", "fallocate"),
+            ("readdirplus", "readdirplus"),
+            ("copy_file_range", "copy_file_range"),
+            ("lseek", "lseek"),
+        ],
+    ),
+];
+
+/// Bits of `struct fuse_file_info` after `flags`, `(C field, Rust mask, minimum minor version)`.
+const FILE_INFO_BITS: &[(&str, &str, u32)] = &[
+    ("writepage", "FI_WRITEPAGE", 0),
+    ("direct_io", "FI_DIRECT_IO", 0),
+    ("keep_cache", "FI_KEEP_CACHE", 0),
+    ("flush", "FI_FLUSH", 0),
+    ("nonseekable", "FI_NONSEEKABLE", 0),
+    ("flock_release", "FI_FLOCK_RELEASE", 0),
+    ("cache_readdir", "FI_CACHE_READDIR", 5),
+];
+
+/// `(C macro or enum value, Rust value)`, macros missing from older headers are skipped.
+const VALUES: &[(&str, &str)] = &[
+    ("FUSE_BUF_IS_FD", "FuseBufFlags::FuseBufIsFd"),
+    ("FUSE_BUF_FD_SEEK", "FuseBufFlags::FuseBufFdSeek"),
+    ("FUSE_BUF_FD_RETRY", "FuseBufFlags::FuseBufFdRetry"),
+    ("FUSE_CAP_ASYNC_READ", "FuseCapFlag::AsyncRead"),
+    ("FUSE_CAP_POSIX_LOCKS", "FuseCapFlag::PosixLocks"),
+    ("FUSE_CAP_ATOMIC_O_TRUNC", "FuseCapFlag::AtomicOTrunc"),
+    ("FUSE_CAP_EXPORT_SUPPORT", "FuseCapFlag::ExportSupport"),
+    ("FUSE_CAP_DONT_MASK", "FuseCapFlag::DontMask"),
+    ("FUSE_CAP_SPLICE_WRITE", "FuseCapFlag::SpliceWrite"),
+    ("FUSE_CAP_SPLICE_MOVE", "FuseCapFlag::SpliceMove"),
+    ("FUSE_CAP_SPLICE_READ", "FuseCapFlag::SpliceRead"),
+    ("FUSE_CAP_FLOCK_LOCKS", "FuseCapFlag::FlockLocks"),
+    ("FUSE_CAP_IOCTL_DIR", "FuseCapFlag::IoctlDir"),
+    ("FUSE_CAP_AUTO_INVAL_DATA", "FuseCapFlag::AutoInvalData"),
+    ("FUSE_CAP_READDIRPLUS", "FuseCapFlag::Readdirplus"),
+    ("FUSE_CAP_READDIRPLUS_AUTO", "FuseCapFlag::ReaddirplusAuto"),
+    ("FUSE_CAP_ASYNC_DIO", "FuseCapFlag::AsyncDio"),
+    ("FUSE_CAP_WRITEBACK_CACHE", "FuseCapFlag::WritebackCache"),
+    ("FUSE_CAP_NO_OPEN_SUPPORT", "FuseCapFlag::NoOpenSupport"),
+    ("FUSE_CAP_PARALLEL_DIROPS", "FuseCapFlag::ParallelDirops"),
+    ("FUSE_CAP_POSIX_ACL", "FuseCapFlag::PosixAcl"),
+    ("FUSE_CAP_HANDLE_KILLPRIV", "FuseCapFlag::HandleKillpriv"),
+    ("FUSE_CAP_CACHE_SYMLINKS", "FuseCapFlag::CacheSymlinks"),
+    (
+        "FUSE_CAP_NO_OPENDIR_SUPPORT",
+        "FuseCapFlag::NoOpendirSupport",
+    ),
+    (
+        "FUSE_CAP_EXPLICIT_INVAL_DATA",
+        "FuseCapFlag::ExplicitInvalData",
+    ),
+    ("FUSE_IOCTL_COMPAT", "FuseIoctlFlag::Compat"),
+    ("FUSE_IOCTL_UNRESTRICTED", "FuseIoctlFlag::Unrestricted"),
+    ("FUSE_IOCTL_RETRY", "FuseIoctlFlag::Retry"),
+    ("FUSE_IOCTL_32BIT", "FuseIoctlFlag::Bit32"),
+    ("FUSE_IOCTL_DIR", "FuseIoctlFlag::Dir"),
+    ("FUSE_IOCTL_COMPAT_X32", "FuseIoctlFlag::CompatX32"),
+];
+
+// The enum values of `enum fuse_buf_flags` can't be tested with `#ifdef`.
+const ENUM_VALUES: &[&str] = &["FUSE_BUF_IS_FD", "FUSE_BUF_FD_SEEK", "FUSE_BUF_FD_RETRY"];
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=CC");
+    println!("cargo:rerun-if-env-changed=PKG_CONFIG_PATH");
+    println!("cargo:rustc-check-cfg=cfg(fuse_abi)");
+
+    // The program is run on the build machine.
+    if env::var("HOST").ok() != env::var("TARGET").ok() {
+        return;
+    }
+    let cflags = match include_flags() {
+        Some(cflags) => cflags,
+        None => return,
+    };
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    match generate(&out_dir, &cflags) {
+        Ok(layout) => {
+            fs::write(out_dir.join("fuse_abi.rs"), layout).unwrap();
+            println!("cargo:rustc-cfg=fuse_abi");
+        }
+        Err(err) => println!(
+            "cargo:warning=the layout was not checked against the libfuse headers: {}",
+            err
+        ),
+    }
+}
+
+fn include_flags() -> Option<Vec<String>> {
+    if let Ok(output) = Command::new("pkg-config")
+        .args(["--cflags", "fuse3"])
+        .output()
+    {
+        if output.status.success() {
+            let cflags = String::from_utf8_lossy(&output.stdout);
+            return Some(cflags.split_whitespace().map(String::from).collect());
+        }
+    }
+    if Path::new("/usr/include/fuse3/fuse_lowlevel.h").exists() {
+        return Some(vec!["-I/usr/include/fuse3".to_string()]);
+    }
+    None
+}
+
+fn generate(out_dir: &Path, cflags: &[String]) -> Result<String, String> {
+    let source = out_dir.join("fuse_abi.c");
+    let program = out_dir.join("fuse_abi");
+    fs::write(&source, c_source()).map_err(|err| err.to_string())?;
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let output = Command::new(&cc)
+        .args(cflags)
+        .arg(&source)
+        .arg("-o")
+        .arg(&program)
+        .output()
+        .map_err(|err| format!("{}: {}", cc, err))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).into_owned());
+    }
+    let output = Command::new(&program)
+        .output()
+        .map_err(|err| err.to_string())?;
+    if !output.status.success() {
+        return Err(format!("{} failed", program.display()));
+    }
+    String::from_utf8(output.stdout).map_err(|err| err.to_string())
+}
+
+fn c_source() -> String {
+    let mut c = String::from(
+        "#define FUSE_USE_VERSION 31\n\
+         #include <stddef.h>\n\
+         #include <stdint.h>\n\
+         #include <stdio.h>\n\
+         #include <string.h>\n\
+         #include <fuse_lowlevel.h>\n\
+         \n\
+         #define ROW(name, rust, c) printf(\"    (\\\"%s\\\", %s, %lu),\\n\", name, rust, (unsigned long)(c))\n\
+         \n\
+         int main(void) {\n\
+         \x20   struct fuse_file_info fi;\n\
+         \x20   uint32_t bits;\n\
+         \x20   printf(\"pub(crate) const LAYOUT: &[(&str, usize, usize)] = &[\\n\");\n",
+    );
+    for (c_name, rust_name, fields) in STRUCTS {
+        c.push_str(&format!(
+            "    ROW(\"{c}\", \"size_of::<{r}>()\", sizeof(struct {c}));\n",
+            c = c_name,
+            r = rust_name
+        ));
+        for (c_field, rust_field) in fields.iter() {
+            c.push_str(&format!(
+                "    ROW(\"{c}.{cf}\", \"offset_of!({r}, {rf})\", offsetof(struct {c}, {cf}));\n",
+                c = c_name,
+                cf = c_field,
+                r = rust_name,
+                rf = rust_field
+            ));
+        }
+    }
+    for (c_field, rust_mask, minor) in FILE_INFO_BITS {
+        c.push_str(&format!(
+            "#if FUSE_MINOR_VERSION >= {m}\n\
+             \x20   memset(&fi, 0, sizeof(fi));\n\
+             \x20   fi.{f} = 1;\n\
+             \x20   memcpy(&bits, (char *)&fi + offsetof(struct fuse_file_info, flags) + sizeof(fi.flags), sizeof(bits));\n\
+             \x20   ROW(\"fuse_file_info.{f}\", \"{r} as usize\", bits);\n\
+             #endif\n",
+            m = minor,
+            f = c_field,
+            r = rust_mask
+        ));
+    }
+    for (c_name, rust_value) in VALUES {
+        let row = format!(
+            "    ROW(\"{c}\", \"{r} as usize\", {c});\n",
+            c = c_name,
+            r = rust_value
+        );
+        if ENUM_VALUES.contains(c_name) {
+            c.push_str(&row);
+        } else {
+            c.push_str(&format!("#ifdef {}\n{}#endif\n", c_name, row));
+        }
+    }
+    c.push_str("    printf(\"];\\n\");\n    return 0;\n}\n");
+    c
+}
@@ -5,9 +5,6 @@ use rusfuse::*;
 #[derive(Debug)]
 struct HelloFs;
 
-const ENOENT: i32 = 2;
-const ENOSYS: i32 = 38;
-
 const FILE_NAME: &[u8] = b"hello";
 const TEXT: &[u8] = b"Hello World!\n";
 
@@ -49,77 +46,75 @@ const TEST_FILE_ATTR: FuseAttr = FuseAttr {
     blksize: 4032,
 };
 
-impl rusfuse::FileSystem for HelloFs {
-    fn lookup(&mut self, _ctx: &FuseCtx, parent: u64, name: &[u8]) -> Result<FuseEntryParam, i32> {
-        println!("call lookup parent: {:?} name: {:?}", parent, name);
-        if parent == 1 && name == FILE_NAME {
-            Ok(rusfuse::FuseEntryParam::new(TEST_FILE_ATTR, 0, 10.0, 10.0))
-        } else {
-            Err(ENOENT)
+register_ops! {
+    impl rusfuse::FileSystem for HelloFs {
+        fn lookup(
+            &mut self,
+            _ctx: &Request,
+            parent: u64,
+            name: &[u8],
+        ) -> Result<FuseEntryParam, Errno> {
+            println!("call lookup parent: {:?} name: {:?}", parent, name);
+            if parent == 1 && name == FILE_NAME {
+                Ok(rusfuse::FuseEntryParam::new(TEST_FILE_ATTR, 0, 10.0, 10.0))
+            } else {
+                Err(Errno::ENOENT)
+            }
         }
-    }
-    fn getattr(
-        &mut self,
-        _ctx: &FuseCtx,
-        ino: u64,
-        fi: Option<&mut FuseFileInfo>,
-    ) -> Result<(FuseAttr, f64), i32> {
-        println!("call getattr ino: {:?} fi: {:?}", ino, fi);
-        if ino == 1 {
-            Ok((TEST_DIR_ATTR, 1.0))
-        } else if ino == 2 {
-            Ok((TEST_FILE_ATTR, 1.0))
-        } else {
-            Err(ENOENT)
+        fn getattr(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            fi: Option<&mut FuseFileInfo>,
+        ) -> Result<(FuseAttr, f64), Errno> {
+            println!("call getattr ino: {:?} fi: {:?}", ino, fi);
+            if ino == 1 {
+                Ok((TEST_DIR_ATTR, 1.0))
+            } else if ino == 2 {
+                Ok((TEST_FILE_ATTR, 1.0))
+            } else {
+                Err(Errno::ENOENT)
+            }
         }
-    }
-    fn read(
-        &mut self,
-        _ctx: &FuseCtx,
-        ino: u64,
-        _size: usize,
-        _off: i64,
-        _fi: &mut FuseFileInfo,
-    ) -> Result<Vec<u8>, i32> {
-        println!("call read");
-        if ino == 2 {
-            Ok(TEXT.to_vec())
-        } else {
-            Err(ENOSYS)
+        fn read(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            _size: usize,
+            _off: i64,
+            _fi: &mut FuseFileInfo,
+        ) -> Result<ReadReply<'_>, Errno> {
+            println!("call read");
+            if ino == 2 {
+                Ok(ReadReply::Slice(TEXT))
+            } else {
+                Err(Errno::ENOSYS)
+            }
         }
-    }
-    fn readdir(
-        &mut self,
-        _ctx: &FuseCtx,
-        ino: u64,
-        _size: usize,
-        _off: i64,
-        _fi: &mut FuseFileInfo,
-    ) -> Result<Vec<FuseDirectory>, i32> {
-        println!(
-            "call readdir ino: {:?}, size: {:?}, off: {:?}",
-            ino, _size, _off
-        );
-        if ino != 1 {
-            Err(ENOSYS)
-        } else {
-            Ok(vec![
-                FuseDirectory {
-                    name: b".".to_vec(),
-                    file_type: FileType::Directory,
-                    ino: 1,
-                },
-                FuseDirectory {
-                    name: b"..".to_vec(),
-                    file_type: FileType::Directory,
-                    ino: 1,
-                },
-                FuseDirectory {
-                    name: FILE_NAME.to_vec(),
-                    file_type: FileType::RegularFile,
-                    ino: 2,
-                },
-            ])
+        fn readdir(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            off: i64,
+            _fi: &mut FuseFileInfo,
+            reply: &mut DirectoryReply,
+        ) -> Result<(), Errno> {
+            println!("call readdir ino: {:?}, off: {:?}", ino, off);
+            if ino != 1 {
+                return Err(Errno::ENOSYS);
+            }
+            let entries = [
+                (&b"."[..], TEST_DIR_ATTR),
+                (b"..", TEST_DIR_ATTR),
+                (FILE_NAME, TEST_FILE_ATTR),
+            ];
+            // The offset of an entry is where the next listing starts.
+            for (offset, (name, attr)) in (1..).zip(&entries).skip(off as usize) {
+                if reply.add(name, offset, attr) {
+                    break;
+                }
+            }
+            Ok(())
         }
     }
 }
@@ -127,6 +122,5 @@ impl rusfuse::FileSystem for HelloFs {
 fn main() {
     let mountpoint: String = env::args().nth(1).unwrap();
     let mut file_system = HelloFs {};
-    let ops = FuseOpFlag::Lookup | FuseOpFlag::Readdir | FuseOpFlag::Read | FuseOpFlag::Getattr;
-    Fuse::new(&mountpoint, &mut file_system, ops).run();
+    Fuse::new(&mountpoint, &mut file_system).unwrap().run();
 }
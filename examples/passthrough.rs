@@ -0,0 +1,15 @@
+use std::env;
+
+use rusfuse::*;
+
+fn main() {
+    // `passthrough /srv/data /mnt -o allow_other`, in the form of a mount helper.
+    let cmd = MountCommand::parse(env::args().skip(1)).unwrap();
+    let source = cmd.source.expect("no source directory given");
+    let mut file_system = PassthroughFs::new(&source).unwrap();
+    Fuse::builder(&cmd.mountpoint)
+        .options(cmd.options.subtype("passthrough").default_permissions())
+        .mount(&mut file_system)
+        .unwrap()
+        .run();
+}
@@ -0,0 +1,112 @@
+use std::cell::RefCell;
+use std::sync::mpsc::{Receiver, Sender};
+
+use libc::{c_void, gid_t};
+
+use crate::errno::Errno;
+use crate::fuse::{
+    fuse_req_userdata, FuseAttr, FuseCtx, FuseEntryParam, FuseFileInfo, FuseLock, FuseReq,
+    FuseStatvfs,
+};
+use crate::ioctl::IoctlIovec;
+use crate::request::CancellationToken;
+
+/// The answer captured by a [`MockSession`](crate::testing::MockSession), one variant
+/// per `fuse_reply_*` function.
+#[derive(Debug)]
+pub enum Reply {
+    /// The operation has no reply, e.g. `forget`.
+    None,
+    Ok,
+    Error(Errno),
+    Entry(FuseEntryParam),
+    Attr(FuseAttr, f64),
+    Readlink(Vec<u8>),
+    Open(FuseFileInfo),
+    Create(FuseEntryParam, FuseFileInfo),
+    /// The data of `read` and `getxattr`, or the entries packed by `readdir`.
+    Data(Vec<u8>),
+    Written(usize),
+    Statfs(FuseStatvfs),
+    XattrSize(usize),
+    Lock(FuseLock),
+    Bmap(u64),
+    Poll(u32),
+    Ioctl(i32, Vec<u8>),
+    IoctlRetry(Vec<IoctlIovec>, Vec<IoctlIovec>),
+    Lseek(i64),
+    /// A [`DeferredFileSystem`](crate::DeferredFileSystem) kept the reply object to answer later.
+    Pending(PendingReply),
+}
+
+/// A reply that the filesystem has not sent yet.
+#[derive(Debug)]
+pub struct PendingReply {
+    receiver: Receiver<Reply>,
+    cancellation: CancellationToken,
+}
+
+impl PendingReply {
+    pub(crate) fn new(receiver: Receiver<Reply>, cancellation: CancellationToken) -> Self {
+        Self {
+            receiver,
+            cancellation,
+        }
+    }
+    /// Blocks until the filesystem answers, a dropped reply object answers `EIO`.
+    pub fn wait(self) -> Reply {
+        self.receiver.recv().unwrap_or(Reply::Error(Errno::EIO))
+    }
+    /// Interrupts the request, like the kernel does when the caller gets a signal.
+    pub fn interrupt(&self) {
+        self.cancellation.cancel();
+    }
+}
+
+/// A request of a [`MockSession`](crate::testing::MockSession), which the trampolines
+/// receive as a fake `fuse_req_t` in place of one of libfuse.
+pub(crate) struct Captured {
+    /// The session data, what `fuse_req_userdata` returns.
+    pub(crate) userdata: *mut c_void,
+    pub(crate) ctx: FuseCtx,
+    pub(crate) groups: Vec<gid_t>,
+    pub(crate) sender: Sender<Reply>,
+    pub(crate) cancellation: CancellationToken,
+}
+
+thread_local! {
+    // The request behind the fake `fuse_req_t` while a trampoline runs on this thread.
+    static CAPTURED: RefCell<Option<Captured>> = const { RefCell::new(None) };
+}
+
+// Only its address is used, libfuse never hands it out.
+static FAKE_REQ: u8 = 0;
+
+fn fake_req() -> *mut FuseReq {
+    &FAKE_REQ as *const u8 as *mut FuseReq
+}
+
+/// Calls `f` with a fake request that stands for `captured`.
+pub(crate) fn with_fake_req<F: FnOnce(*mut FuseReq)>(captured: Captured, f: F) {
+    CAPTURED.with(|current| *current.borrow_mut() = Some(captured));
+    f(fake_req());
+    CAPTURED.with(|current| current.borrow_mut().take());
+}
+
+/// The captured request behind `req`, `None` for a request of libfuse.
+pub(crate) fn captured<R, F: FnOnce(&Captured) -> R>(req: *mut FuseReq, f: F) -> Option<R> {
+    if req != fake_req() {
+        return None;
+    }
+    CAPTURED.with(|current| current.borrow().as_ref().map(f))
+}
+
+/// Where the replies of the captured request on this thread go.
+pub(crate) fn sender() -> Option<Sender<Reply>> {
+    captured(fake_req(), |captured| captured.sender.clone())
+}
+
+/// `fuse_req_userdata`, which also knows the fake requests.
+pub(crate) unsafe fn req_userdata(req: *mut FuseReq) -> *mut c_void {
+    captured(req, |captured| captured.userdata).unwrap_or_else(|| fuse_req_userdata(req))
+}
@@ -0,0 +1,250 @@
+use std::ops::{BitAnd, BitOr};
+
+use crate::fuse::FuseConnInfo;
+
+/// Capabilities of a FUSE connection, the `FUSE_CAP_*` flags of libfuse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FuseCapFlag {
+    AsyncRead = 1 << 0,
+    PosixLocks = 1 << 1,
+    AtomicOTrunc = 1 << 3,
+    ExportSupport = 1 << 4,
+    DontMask = 1 << 6,
+    SpliceWrite = 1 << 7,
+    SpliceMove = 1 << 8,
+    SpliceRead = 1 << 9,
+    FlockLocks = 1 << 10,
+    IoctlDir = 1 << 11,
+    AutoInvalData = 1 << 12,
+    Readdirplus = 1 << 13,
+    ReaddirplusAuto = 1 << 14,
+    AsyncDio = 1 << 15,
+    WritebackCache = 1 << 16,
+    NoOpenSupport = 1 << 17,
+    ParallelDirops = 1 << 18,
+    PosixAcl = 1 << 19,
+    HandleKillpriv = 1 << 20,
+    CacheSymlinks = 1 << 23,
+    NoOpendirSupport = 1 << 24,
+    ExplicitInvalData = 1 << 25,
+}
+
+impl BitOr<FuseCapFlag> for FuseCapFlag {
+    type Output = u32;
+    fn bitor(self, rhs: Self) -> Self::Output {
+        self as u32 | rhs as u32
+    }
+}
+impl BitOr<FuseCapFlag> for u32 {
+    type Output = u32;
+    fn bitor(self, rhs: FuseCapFlag) -> Self::Output {
+        self | rhs as u32
+    }
+}
+impl BitAnd<FuseCapFlag> for u32 {
+    type Output = u32;
+    fn bitand(self, rhs: FuseCapFlag) -> Self::Output {
+        self & rhs as u32
+    }
+}
+
+/// Which data moves between `/dev/fuse` and the filesystem with `splice(2)`, see
+/// [`FuseBuilder::splice`](crate::FuseBuilder::splice).
+///
+/// Splicing avoids copying the data of large `read` and `write` requests through
+/// user space, at the cost of a pipe per thread and a few more system calls for
+/// small requests. Capabilities the kernel lacks stay off.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpliceConfig {
+    /// Receive requests through a pipe, [`FuseCapFlag::SpliceRead`], so that
+    /// `write_buf` gets the data of a `write` as a file descriptor.
+    pub read: bool,
+    /// Send replies made of file descriptors, like [`ReadReply::Fd`](crate::ReadReply::Fd),
+    /// through a pipe, [`FuseCapFlag::SpliceWrite`].
+    pub write: bool,
+    /// Move pages into the page cache of the kernel instead of copying them,
+    /// [`FuseCapFlag::SpliceMove`].
+    pub move_pages: bool,
+}
+
+impl SpliceConfig {
+    /// Everything spliced.
+    pub fn all() -> Self {
+        Self {
+            read: true,
+            write: true,
+            move_pages: true,
+        }
+    }
+    pub(crate) fn apply(&self, conn: &mut ConnectionInfo) {
+        let caps = [
+            (self.read, FuseCapFlag::SpliceRead),
+            (self.write, FuseCapFlag::SpliceWrite),
+            (self.move_pages, FuseCapFlag::SpliceMove),
+        ];
+        for &(on, cap) in caps.iter() {
+            if on {
+                conn.enable(cap);
+            } else {
+                conn.disable(cap);
+            }
+        }
+    }
+}
+
+/// Parameters of the connection negotiated with the kernel, handed to `init`.
+///
+/// `capable` is what the kernel and libfuse support; `want` starts with the
+/// defaults chosen by libfuse and may be changed within `capable` before
+/// `init` returns.
+#[repr(transparent)]
+pub struct ConnectionInfo(FuseConnInfo);
+
+impl ConnectionInfo {
+    pub(crate) unsafe fn from_raw<'a>(conn: *mut FuseConnInfo) -> &'a mut Self {
+        (conn as *mut Self).as_mut().unwrap()
+    }
+
+    pub fn proto_major(&self) -> u32 {
+        self.0.proto_major
+    }
+    pub fn proto_minor(&self) -> u32 {
+        self.0.proto_minor
+    }
+
+    /// Mask of [`FuseCapFlag`] values supported by the kernel and libfuse.
+    pub fn capable(&self) -> u32 {
+        self.0.capable
+    }
+    pub fn is_capable(&self, cap: FuseCapFlag) -> bool {
+        self.0.capable & cap != 0
+    }
+    /// Mask of [`FuseCapFlag`] values that will be enabled.
+    pub fn want(&self) -> u32 {
+        self.0.want
+    }
+    pub fn wants(&self, cap: FuseCapFlag) -> bool {
+        self.0.want & cap != 0
+    }
+    /// Enables a capability and returns `false`, leaving `want` untouched, if it is
+    /// not supported.
+    pub fn enable(&mut self, cap: FuseCapFlag) -> bool {
+        if !self.is_capable(cap) {
+            return false;
+        }
+        self.0.want |= cap as u32;
+        true
+    }
+    pub fn disable(&mut self, cap: FuseCapFlag) {
+        self.0.want &= !(cap as u32);
+    }
+
+    pub fn max_write(&self) -> u32 {
+        self.0.max_write
+    }
+    pub fn set_max_write(&mut self, max_write: u32) {
+        self.0.max_write = max_write;
+    }
+    /// Fixed by the `max_read` mount option, it can't be changed in `init`.
+    pub fn max_read(&self) -> u32 {
+        self.0.max_read
+    }
+    pub fn max_readahead(&self) -> u32 {
+        self.0.max_readahead
+    }
+    pub fn set_max_readahead(&mut self, max_readahead: u32) {
+        self.0.max_readahead = max_readahead;
+    }
+    pub fn max_background(&self) -> u32 {
+        self.0.max_background
+    }
+    pub fn set_max_background(&mut self, max_background: u32) {
+        self.0.max_background = max_background;
+    }
+    pub fn congestion_threshold(&self) -> u32 {
+        self.0.congestion_threshold
+    }
+    pub fn set_congestion_threshold(&mut self, congestion_threshold: u32) {
+        self.0.congestion_threshold = congestion_threshold;
+    }
+    /// Granularity of timestamps in nanoseconds, a power of 10 up to `1_000_000_000`.
+    pub fn time_gran(&self) -> u32 {
+        self.0.time_gran
+    }
+    pub fn set_time_gran(&mut self, time_gran: u32) {
+        self.0.time_gran = time_gran;
+    }
+}
+
+impl std::fmt::Debug for ConnectionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectionInfo")
+            .field("proto_major", &self.proto_major())
+            .field("proto_minor", &self.proto_minor())
+            .field("max_write", &self.max_write())
+            .field("max_read", &self.max_read())
+            .field("max_readahead", &self.max_readahead())
+            .field("capable", &format_args!("{:#x}", self.capable()))
+            .field("want", &format_args!("{:#x}", self.want()))
+            .field("max_background", &self.max_background())
+            .field("congestion_threshold", &self.congestion_threshold())
+            .field("time_gran", &self.time_gran())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connection::{ConnectionInfo, FuseCapFlag, SpliceConfig};
+    use crate::fuse::FuseConnInfo;
+
+    fn conn_info() -> FuseConnInfo {
+        let mut raw: FuseConnInfo = unsafe { std::mem::zeroed() };
+        raw.capable = FuseCapFlag::AsyncRead | FuseCapFlag::Readdirplus | FuseCapFlag::PosixAcl;
+        raw.want = FuseCapFlag::AsyncRead as u32;
+        raw
+    }
+
+    #[test]
+    fn enable_is_limited_to_capable() {
+        let mut raw = conn_info();
+        let conn = unsafe { ConnectionInfo::from_raw(&mut raw) };
+        assert!(conn.enable(FuseCapFlag::PosixAcl));
+        assert!(!conn.enable(FuseCapFlag::WritebackCache));
+        assert!(conn.wants(FuseCapFlag::PosixAcl));
+        assert!(!conn.wants(FuseCapFlag::WritebackCache));
+        conn.disable(FuseCapFlag::AsyncRead);
+        assert_eq!(raw.want, FuseCapFlag::PosixAcl as u32);
+    }
+
+    #[test]
+    fn setters_write_through() {
+        let mut raw = conn_info();
+        let conn = unsafe { ConnectionInfo::from_raw(&mut raw) };
+        conn.set_max_write(128 * 1024);
+        conn.set_max_background(64);
+        conn.set_time_gran(1000);
+        assert_eq!(raw.max_write, 128 * 1024);
+        assert_eq!(raw.max_background, 64);
+        assert_eq!(raw.time_gran, 1000);
+    }
+
+    #[test]
+    fn splice_config_applies_capable_flags() {
+        let mut raw = conn_info();
+        raw.capable |= FuseCapFlag::SpliceRead | FuseCapFlag::SpliceWrite;
+        raw.want |= FuseCapFlag::SpliceRead as u32;
+        let conn = unsafe { ConnectionInfo::from_raw(&mut raw) };
+        SpliceConfig {
+            read: false,
+            write: true,
+            move_pages: false,
+        }
+        .apply(conn);
+        assert!(!conn.wants(FuseCapFlag::SpliceRead));
+        assert!(conn.wants(FuseCapFlag::SpliceWrite));
+        SpliceConfig::all().apply(conn);
+        assert!(conn.wants(FuseCapFlag::SpliceRead));
+        assert!(!conn.wants(FuseCapFlag::SpliceMove));
+    }
+}
@@ -0,0 +1,538 @@
+use std::ops::{Deref, DerefMut};
+
+use crate::connection::ConnectionInfo;
+use crate::errno::Errno;
+use crate::fuse::{FuseBufvec, FuseFileInfo, FuseForgetData, FuseLock};
+use crate::ioctl::{IoctlOutput, IoctlRequest};
+use crate::notify::PollHandle;
+use crate::reply::{
+    ReplyAttr, ReplyBmap, ReplyCreate, ReplyData, ReplyDirectory, ReplyDirectoryPlus, ReplyEmpty,
+    ReplyEntry, ReplyIoctl, ReplyLock, ReplyLseek, ReplyOpen, ReplyPoll, ReplyReadlink,
+    ReplyStatfs, ReplyWrite, ReplyXattr,
+};
+use crate::request::Request;
+use crate::setattr::SetAttrRequest;
+use crate::FileSystem;
+
+// The operations of `operations!` with the reply object last, failed with `ENOSYS`
+// until implemented.
+macro_rules! deferred_methods {
+    (
+        answered {
+            $(
+                $(#[$meta:meta])*
+                fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty, $reply:ty;
+            )*
+        }
+        unanswered {
+            $(
+                $(#[$unanswered_meta:meta])*
+                fn $unanswered:ident($($unanswered_arg:ident: $unanswered_ty:ty),* $(,)?);
+            )*
+        }
+    ) => {
+        $(
+            $(#[$meta])*
+            #[allow(unused_variables)]
+            fn $name(&mut self, ctx: &Request, $($arg: $ty,)* reply: $reply) {
+                reply.error(Errno::ENOSYS);
+            }
+        )*
+        $(
+            $(#[$unanswered_meta])*
+            #[allow(unused_variables)]
+            fn $unanswered(&mut self, ctx: &Request, $($unanswered_arg: $unanswered_ty),*) {}
+        )*
+    };
+}
+
+/// A variant of [`FileSystem`] where each method answers through an owned reply object.
+///
+/// The reply objects are `Send`, so a request can be parked and answered later,
+/// possibly from another thread, for example a `setlk` that has to wait for a lock
+/// or a `read` waiting on a slow backend. A reply that is dropped without being
+/// used fails the request with `EIO`.
+pub trait DeferredFileSystem {
+    /// Operations registered with libfuse, a mask of [`FuseOpFlag`](crate::FuseOpFlag) values.
+    ///
+    /// Generated by [`register_ops!`](crate::register_ops), or written by hand where the
+    /// macro doesn't apply; operations left out of the mask are answered by libfuse
+    /// itself, usually with `ENOSYS`.
+    fn ops() -> u64
+    where
+        Self: Sized;
+    /// Called once before any other operation to inspect and adjust the connection.
+    fn init(&mut self, _conn: &mut ConnectionInfo) -> Result<(), Errno> {
+        Err(Errno::ENOSYS)
+    }
+    fn destroy(&mut self) -> Result<(), Errno> {
+        Err(Errno::ENOSYS)
+    }
+    fn readdir(
+        &mut self,
+        _ctx: &Request,
+        _ino: u64,
+        _size: usize,
+        _off: i64,
+        _fi: &mut FuseFileInfo,
+        reply: ReplyDirectory,
+    ) {
+        reply.error(Errno::ENOSYS);
+    }
+    fn readdirplus(
+        &mut self,
+        _ctx: &Request,
+        _ino: u64,
+        _size: usize,
+        _off: i64,
+        _fi: &mut FuseFileInfo,
+        reply: ReplyDirectoryPlus,
+    ) {
+        reply.error(Errno::ENOSYS);
+    }
+    operations!(deferred_methods);
+}
+
+/// Exposes a [`FileSystem`] as a [`DeferredFileSystem`] that answers before returning.
+pub(crate) struct Immediate<G>(pub(crate) G);
+
+impl<'a, G: DerefMut<Target = dyn FileSystem + 'a> + 'a> Deref for Immediate<G> {
+    type Target = dyn DeferredFileSystem + 'a;
+    fn deref(&self) -> &Self::Target {
+        self
+    }
+}
+
+impl<'a, G: DerefMut<Target = dyn FileSystem + 'a> + 'a> DerefMut for Immediate<G> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self
+    }
+}
+
+impl<'a, G: DerefMut<Target = dyn FileSystem + 'a>> DeferredFileSystem for Immediate<G> {
+    // Never asked, sessions take the mask of the wrapped filesystem.
+    fn ops() -> u64 {
+        0
+    }
+    fn init(&mut self, conn: &mut ConnectionInfo) -> Result<(), Errno> {
+        self.0.init(conn)
+    }
+    fn destroy(&mut self) -> Result<(), Errno> {
+        self.0.destroy()
+    }
+    fn lookup(&mut self, ctx: &Request, parent: u64, name: &[u8], reply: ReplyEntry) {
+        match self.0.lookup(ctx, parent, name) {
+            Ok(entry) => reply.entry(&entry),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn forget(&mut self, ctx: &Request, forget: FuseForgetData) {
+        self.0.forget(ctx, forget)
+    }
+    fn getattr(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        fi: Option<&mut FuseFileInfo>,
+        reply: ReplyAttr,
+    ) {
+        match self.0.getattr(ctx, ino, fi) {
+            Ok((attr, timeout)) => reply.attr(&attr, timeout),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn setattr(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        attr: &SetAttrRequest,
+        fi: Option<&mut FuseFileInfo>,
+        reply: ReplyAttr,
+    ) {
+        match self.0.setattr(ctx, ino, attr, fi) {
+            Ok((attr, timeout)) => reply.attr(&attr, timeout),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn readlink(&mut self, ctx: &Request, ino: u64, reply: ReplyReadlink) {
+        match self.0.readlink(ctx, ino) {
+            Ok(link) => reply.readlink(&link),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn mknod(
+        &mut self,
+        ctx: &Request,
+        parent: u64,
+        name: &[u8],
+        mode: u32,
+        rdev: u64,
+        reply: ReplyEntry,
+    ) {
+        match self.0.mknod(ctx, parent, name, mode, rdev) {
+            Ok(entry) => reply.entry(&entry),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn mkdir(&mut self, ctx: &Request, parent: u64, name: &[u8], mode: u32, reply: ReplyEntry) {
+        match self.0.mkdir(ctx, parent, name, mode) {
+            Ok(entry) => reply.entry(&entry),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn unlink(&mut self, ctx: &Request, parent: u64, name: &[u8], reply: ReplyEmpty) {
+        match self.0.unlink(ctx, parent, name) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn rmdir(&mut self, ctx: &Request, parent: u64, name: &[u8], reply: ReplyEmpty) {
+        match self.0.rmdir(ctx, parent, name) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn symlink(&mut self, ctx: &Request, link: &[u8], parent: u64, name: &[u8], reply: ReplyEntry) {
+        match self.0.symlink(ctx, link, parent, name) {
+            Ok(entry) => reply.entry(&entry),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn rename(
+        &mut self,
+        ctx: &Request,
+        parent: u64,
+        name: &[u8],
+        newparent: u64,
+        newname: &[u8],
+        flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        match self.0.rename(ctx, parent, name, newparent, newname, flags) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn link(&mut self, ctx: &Request, ino: u64, newparent: u64, newname: &[u8], reply: ReplyEntry) {
+        match self.0.link(ctx, ino, newparent, newname) {
+            Ok(entry) => reply.entry(&entry),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn open(&mut self, ctx: &Request, ino: u64, fi: FuseFileInfo, reply: ReplyOpen) {
+        match self.0.open(ctx, ino, fi) {
+            Ok(fi) => reply.opened(&fi),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn read(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        size: usize,
+        off: i64,
+        fi: &mut FuseFileInfo,
+        reply: ReplyData,
+    ) {
+        match self.0.read(ctx, ino, size, off, fi) {
+            Ok(data) => reply.read(&data),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn write(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        buf: &[u8],
+        size: usize,
+        off: i64,
+        fi: &mut FuseFileInfo,
+        reply: ReplyWrite,
+    ) {
+        match self.0.write(ctx, ino, buf, size, off, fi) {
+            Ok(count) => reply.written(count),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn flush(&mut self, ctx: &Request, ino: u64, fi: &mut FuseFileInfo, reply: ReplyEmpty) {
+        match self.0.flush(ctx, ino, fi) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn release(&mut self, ctx: &Request, ino: u64, fi: &mut FuseFileInfo, reply: ReplyEmpty) {
+        match self.0.release(ctx, ino, fi) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn fsync(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        datasync: i32,
+        fi: &mut FuseFileInfo,
+        reply: ReplyEmpty,
+    ) {
+        match self.0.fsync(ctx, ino, datasync, fi) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn opendir(&mut self, ctx: &Request, ino: u64, fi: &mut FuseFileInfo, reply: ReplyOpen) {
+        match self.0.opendir(ctx, ino, fi) {
+            Ok(fi) => reply.opened(&fi),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn readdir(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        _size: usize,
+        off: i64,
+        fi: &mut FuseFileInfo,
+        reply: ReplyDirectory,
+    ) {
+        let mut reply = reply;
+        match self.0.readdir(ctx, ino, off, fi, reply.entries()) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn releasedir(&mut self, ctx: &Request, ino: u64, fi: &mut FuseFileInfo, reply: ReplyEmpty) {
+        match self.0.releasedir(ctx, ino, fi) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn fsyncdir(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        datasync: i32,
+        fi: &mut FuseFileInfo,
+        reply: ReplyEmpty,
+    ) {
+        match self.0.fsyncdir(ctx, ino, datasync, fi) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn statfs(&mut self, ctx: &Request, ino: u64, reply: ReplyStatfs) {
+        match self.0.statfs(ctx, ino) {
+            Ok(stbuf) => reply.statfs(&stbuf),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn setxattr(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        name: &[u8],
+        value: &[u8],
+        size: usize,
+        flags: i32,
+        reply: ReplyEmpty,
+    ) {
+        match self.0.setxattr(ctx, ino, name, value, size, flags) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn getxattr(&mut self, ctx: &Request, ino: u64, name: &[u8], size: usize, reply: ReplyXattr) {
+        match self.0.getxattr(ctx, ino, name, size) {
+            Ok(value) => reply.value(size, &value),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn listxattr(&mut self, ctx: &Request, ino: u64, size: usize, reply: ReplyXattr) {
+        match self.0.listxattr(ctx, ino, size) {
+            Ok(value) => reply.value(size, &value),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn removexattr(&mut self, ctx: &Request, ino: u64, name: &[u8], reply: ReplyEmpty) {
+        match self.0.removexattr(ctx, ino, name) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn access(&mut self, ctx: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
+        match self.0.access(ctx, ino, mask) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn create(
+        &mut self,
+        ctx: &Request,
+        parent: u64,
+        name: &[u8],
+        mode: u32,
+        fi: &mut FuseFileInfo,
+        reply: ReplyCreate,
+    ) {
+        match self.0.create(ctx, parent, name, mode, fi) {
+            Ok(entry) => reply.created(&entry, fi),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn getlk(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        fi: &mut FuseFileInfo,
+        lock: &mut FuseLock,
+        reply: ReplyLock,
+    ) {
+        match self.0.getlk(ctx, ino, fi, lock) {
+            Ok(lock) => reply.locked(&lock),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn setlk(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        fi: &mut FuseFileInfo,
+        lock: &mut FuseLock,
+        sleep: i32,
+        reply: ReplyEmpty,
+    ) {
+        match self.0.setlk(ctx, ino, fi, lock, sleep) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn bmap(&mut self, ctx: &Request, ino: u64, blocksize: usize, idx: u64, reply: ReplyBmap) {
+        match self.0.bmap(ctx, ino, blocksize, idx) {
+            Ok(idx) => reply.bmap(idx),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn ioctl(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        fi: &mut FuseFileInfo,
+        ioctl: &IoctlRequest,
+        reply: ReplyIoctl,
+    ) {
+        match self.0.ioctl(ctx, ino, fi, ioctl) {
+            Ok(IoctlOutput::Done { result, data }) => reply.ioctl(result, &data),
+            Ok(IoctlOutput::Retry { input, output }) => reply.retry(&input, &output),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn poll(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        fi: &mut FuseFileInfo,
+        ph: Option<PollHandle>,
+        reply: ReplyPoll,
+    ) {
+        match self.0.poll(ctx, ino, fi, ph) {
+            Ok(revents) => reply.poll(revents),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn write_buf(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        bufv: &mut FuseBufvec,
+        off: i64,
+        fi: &FuseFileInfo,
+        reply: ReplyWrite,
+    ) {
+        match self.0.write_buf(ctx, ino, bufv, off, fi) {
+            Ok(count) => reply.written(count),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn retrieve_reply(&mut self, ctx: &Request, cookie: u64, ino: u64, offset: i64, data: &[u8]) {
+        self.0.retrieve_reply(ctx, cookie, ino, offset, data)
+    }
+    fn forget_multi(&mut self, ctx: &Request, forgets: Vec<FuseForgetData>) {
+        self.0.forget_multi(ctx, forgets)
+    }
+    fn flock(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        fi: &mut FuseFileInfo,
+        op: i32,
+        reply: ReplyEmpty,
+    ) {
+        match self.0.flock(ctx, ino, fi, op) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn fallocate(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        mode: i32,
+        offset: i64,
+        length: i64,
+        fi: &mut FuseFileInfo,
+        reply: ReplyEmpty,
+    ) {
+        match self.0.fallocate(ctx, ino, mode, offset, length, fi) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn readdirplus(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        _size: usize,
+        off: i64,
+        fi: &mut FuseFileInfo,
+        reply: ReplyDirectoryPlus,
+    ) {
+        let mut reply = reply;
+        match self.0.readdirplus(ctx, ino, off, fi, reply.entries()) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn copy_file_range(
+        &mut self,
+        ctx: &Request,
+        ino_in: u64,
+        off_in: i64,
+        fi_in: &mut FuseFileInfo,
+        ino_out: u64,
+        off_out: i64,
+        fi_out: &mut FuseFileInfo,
+        len: usize,
+        flags: i32,
+        reply: ReplyWrite,
+    ) {
+        match self.0.copy_file_range(
+            ctx, ino_in, off_in, fi_in, ino_out, off_out, fi_out, len, flags,
+        ) {
+            Ok(count) => reply.written(count),
+            Err(e) => reply.error(e),
+        }
+    }
+    fn lseek(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        off: i64,
+        whence: i32,
+        fi: &mut FuseFileInfo,
+        reply: ReplyLseek,
+    ) {
+        match self.0.lseek(ctx, ino, off, whence, fi) {
+            Ok(off) => reply.offset(off),
+            Err(e) => reply.error(e),
+        }
+    }
+}
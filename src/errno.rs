@@ -0,0 +1,266 @@
+use std::fmt;
+use std::io;
+
+macro_rules! errno {
+    ($($name:ident,)*) => {
+        impl Errno {
+            $(pub const $name: Errno = Errno(libc::$name);)*
+
+            fn name(self) -> Option<&'static str> {
+                match self.0 {
+                    $(libc::$name => Some(stringify!($name)),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+/// An error of a filesystem operation, answered to the kernel with `fuse_reply_err`.
+///
+/// Always a valid errno: [`Errno::new`] turns anything else into `EIO`. Errors of
+/// `std::io` convert with `?`, keeping the errno of the system call when there is one.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Errno(i32);
+
+errno! {
+    EPERM,
+    ENOENT,
+    ESRCH,
+    EINTR,
+    EIO,
+    ENXIO,
+    E2BIG,
+    ENOEXEC,
+    EBADF,
+    ECHILD,
+    EAGAIN,
+    ENOMEM,
+    EACCES,
+    EFAULT,
+    ENOTBLK,
+    EBUSY,
+    EEXIST,
+    EXDEV,
+    ENODEV,
+    ENOTDIR,
+    EISDIR,
+    EINVAL,
+    ENFILE,
+    EMFILE,
+    ENOTTY,
+    ETXTBSY,
+    EFBIG,
+    ENOSPC,
+    ESPIPE,
+    EROFS,
+    EMLINK,
+    EPIPE,
+    EDOM,
+    ERANGE,
+    EDEADLK,
+    ENAMETOOLONG,
+    ENOLCK,
+    ENOSYS,
+    ENOTEMPTY,
+    ELOOP,
+    ENOMSG,
+    EIDRM,
+    ECHRNG,
+    EL2NSYNC,
+    EL3HLT,
+    EL3RST,
+    ELNRNG,
+    EUNATCH,
+    ENOCSI,
+    EL2HLT,
+    EBADE,
+    EBADR,
+    EXFULL,
+    ENOANO,
+    EBADRQC,
+    EBADSLT,
+    EBFONT,
+    ENOSTR,
+    ENODATA,
+    ETIME,
+    ENOSR,
+    ENONET,
+    ENOPKG,
+    EREMOTE,
+    ENOLINK,
+    EADV,
+    ESRMNT,
+    ECOMM,
+    EPROTO,
+    EMULTIHOP,
+    EDOTDOT,
+    EBADMSG,
+    EOVERFLOW,
+    ENOTUNIQ,
+    EBADFD,
+    EREMCHG,
+    ELIBACC,
+    ELIBBAD,
+    ELIBSCN,
+    ELIBMAX,
+    ELIBEXEC,
+    EILSEQ,
+    ERESTART,
+    ESTRPIPE,
+    EUSERS,
+    ENOTSOCK,
+    EDESTADDRREQ,
+    EMSGSIZE,
+    EPROTOTYPE,
+    ENOPROTOOPT,
+    EPROTONOSUPPORT,
+    ESOCKTNOSUPPORT,
+    EOPNOTSUPP,
+    EPFNOSUPPORT,
+    EAFNOSUPPORT,
+    EADDRINUSE,
+    EADDRNOTAVAIL,
+    ENETDOWN,
+    ENETUNREACH,
+    ENETRESET,
+    ECONNABORTED,
+    ECONNRESET,
+    ENOBUFS,
+    EISCONN,
+    ENOTCONN,
+    ESHUTDOWN,
+    ETOOMANYREFS,
+    ETIMEDOUT,
+    ECONNREFUSED,
+    EHOSTDOWN,
+    EHOSTUNREACH,
+    EALREADY,
+    EINPROGRESS,
+    ESTALE,
+    EUCLEAN,
+    ENOTNAM,
+    ENAVAIL,
+    EISNAM,
+    EREMOTEIO,
+    EDQUOT,
+    ENOMEDIUM,
+    EMEDIUMTYPE,
+    ECANCELED,
+    ENOKEY,
+    EKEYEXPIRED,
+    EKEYREVOKED,
+    EKEYREJECTED,
+    EOWNERDEAD,
+    ENOTRECOVERABLE,
+    ERFKILL,
+    EHWPOISON,
+}
+
+impl Errno {
+    pub const EWOULDBLOCK: Errno = Errno::EAGAIN;
+    pub const EDEADLOCK: Errno = Errno::EDEADLK;
+    pub const ENOTSUP: Errno = Errno::EOPNOTSUPP;
+
+    /// Takes a positive errno as well as the negated one of libfuse and the kernel.
+    /// Zero and values out of range become `EIO`.
+    pub fn new(code: i32) -> Self {
+        match code.checked_abs() {
+            // libfuse rejects replies with an errno of 1000 or more.
+            Some(code) if code > 0 && code < 1000 => Errno(code),
+            _ => Errno::EIO,
+        }
+    }
+
+    /// The positive errno.
+    pub fn code(self) -> i32 {
+        self.0
+    }
+}
+
+impl fmt::Debug for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "Errno({})", self.0),
+        }
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        io::Error::from_raw_os_error(self.0).fmt(f)
+    }
+}
+
+impl std::error::Error for Errno {}
+
+impl From<i32> for Errno {
+    fn from(code: i32) -> Self {
+        Errno::new(code)
+    }
+}
+
+impl From<Errno> for i32 {
+    fn from(errno: Errno) -> Self {
+        errno.0
+    }
+}
+
+impl From<io::Error> for Errno {
+    fn from(err: io::Error) -> Self {
+        if let Some(code) = err.raw_os_error() {
+            return Errno::new(code);
+        }
+        match err.kind() {
+            io::ErrorKind::NotFound => Errno::ENOENT,
+            io::ErrorKind::PermissionDenied => Errno::EACCES,
+            io::ErrorKind::AlreadyExists => Errno::EEXIST,
+            io::ErrorKind::WouldBlock => Errno::EAGAIN,
+            io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => Errno::EINVAL,
+            io::ErrorKind::TimedOut => Errno::ETIMEDOUT,
+            io::ErrorKind::Interrupted => Errno::EINTR,
+            io::ErrorKind::Unsupported => Errno::ENOTSUP,
+            io::ErrorKind::OutOfMemory => Errno::ENOMEM,
+            _ => Errno::EIO,
+        }
+    }
+}
+
+impl From<Errno> for io::Error {
+    fn from(errno: Errno) -> Self {
+        io::Error::from_raw_os_error(errno.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errno::Errno;
+    use std::io;
+
+    #[test]
+    fn new_keeps_valid_codes() {
+        assert_eq!(Errno::new(libc::ENOENT), Errno::ENOENT);
+        assert_eq!(Errno::new(-libc::ENOENT), Errno::ENOENT);
+        assert_eq!(Errno::new(0), Errno::EIO);
+        assert_eq!(Errno::new(i32::MIN), Errno::EIO);
+        assert_eq!(Errno::new(4095), Errno::EIO);
+        assert_eq!(Errno::ENOTSUP.code(), libc::EOPNOTSUPP);
+    }
+
+    #[test]
+    fn from_io_error() {
+        let err = io::Error::from_raw_os_error(libc::ENOTEMPTY);
+        assert_eq!(Errno::from(err), Errno::ENOTEMPTY);
+        let err = io::Error::new(io::ErrorKind::NotFound, "gone");
+        assert_eq!(Errno::from(err), Errno::ENOENT);
+        let err = io::Error::new(io::ErrorKind::Other, "?");
+        assert_eq!(Errno::from(err), Errno::EIO);
+    }
+
+    #[test]
+    fn debug_shows_name() {
+        assert_eq!(format!("{:?}", Errno::EACCES), "EACCES");
+        assert_eq!(format!("{:?}", Errno::new(900)), "Errno(900)");
+    }
+}
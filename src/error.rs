@@ -0,0 +1,69 @@
+use std::ffi::NulError;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Why a filesystem could not be mounted.
+#[derive(Debug)]
+pub enum MountError {
+    /// The mountpoint or a mount option contains a NUL byte.
+    InvalidString(NulError),
+    /// The mountpoint does not exist.
+    MissingMountpoint(PathBuf),
+    /// A directory on the way to the mountpoint can't be searched.
+    PermissionDenied(PathBuf),
+    /// The mountpoint can't be looked up for another reason, e.g. a symlink loop or
+    /// a file in the middle of the path.
+    Io(PathBuf, io::Error),
+    /// The mountpoint exists but is not a directory.
+    NotADirectory(PathBuf),
+    /// `fuse_session_new` failed, usually because of an unknown mount option.
+    Session,
+    /// The handlers that unmount on `SIGINT`, `SIGTERM` and `SIGHUP` could not be installed.
+    SignalHandlers,
+    /// `fuse_session_mount` failed. libfuse logs the reason to stderr, e.g. a missing
+    /// `/dev/fuse` or `fusermount3`, a busy mountpoint, or no permission to mount
+    /// there, like `allow_other` without `user_allow_other` in `/etc/fuse.conf`.
+    Mount(PathBuf),
+}
+
+impl fmt::Display for MountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MountError::InvalidString(err) => write!(f, "invalid mount argument: {}", err),
+            MountError::MissingMountpoint(path) => {
+                write!(f, "mountpoint {} does not exist", path.display())
+            }
+            MountError::PermissionDenied(path) => {
+                write!(f, "no permission to reach mountpoint {}", path.display())
+            }
+            MountError::Io(path, err) => {
+                write!(f, "cannot access mountpoint {}: {}", path.display(), err)
+            }
+            MountError::NotADirectory(path) => {
+                write!(f, "mountpoint {} is not a directory", path.display())
+            }
+            MountError::Session => write!(f, "failed to create a fuse session"),
+            MountError::SignalHandlers => write!(f, "failed to install signal handlers"),
+            MountError::Mount(path) => {
+                write!(f, "failed to mount at {}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for MountError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MountError::InvalidString(err) => Some(err),
+            MountError::Io(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<NulError> for MountError {
+    fn from(err: NulError) -> Self {
+        MountError::InvalidString(err)
+    }
+}
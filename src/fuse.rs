@@ -1,13 +1,20 @@
 use std::mem;
+use std::ops::{BitAnd, BitOr, Deref, DerefMut};
 use std::os::raw::{c_short, c_ulong};
+use std::os::unix::io::RawFd;
 use std::ptr::null_mut;
+use std::slice;
+
+mod abi;
 
 use libc::{
     blkcnt_t, blksize_t, c_char, c_int, c_uint, c_void, dev_t, flock, fsblkcnt_t, fsfilcnt_t,
-    gid_t, ino_t, mode_t, nlink_t, off_t, pid_t, size_t, stat, statvfs, time_t, uid_t, S_IFBLK,
-    S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFREG, S_IFSOCK,
+    gid_t, ino_t, iovec, mode_t, nlink_t, off_t, pid_t, size_t, ssize_t, stat, statvfs, time_t,
+    uid_t, S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFREG, S_IFSOCK,
 };
 
+use crate::errno::Errno;
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct FuseArgs {
@@ -19,73 +26,379 @@ pub struct FuseArgs {
 #[repr(C)]
 #[derive(Debug)]
 pub struct FuseConnInfo {
-    proto_major: c_uint,
-    proto_minor: c_uint,
-    max_write: c_uint,
-    max_read: c_uint,
-    max_readahead: c_uint,
-    capable: c_uint,
-    want: c_uint,
-    max_background: c_uint,
-    congestion_threshold: c_uint,
-    time_gran: c_uint,
-    reserved: [c_uint; 22],
+    pub(crate) proto_major: c_uint,
+    pub(crate) proto_minor: c_uint,
+    pub(crate) max_write: c_uint,
+    pub(crate) max_read: c_uint,
+    pub(crate) max_readahead: c_uint,
+    pub(crate) capable: c_uint,
+    pub(crate) want: c_uint,
+    pub(crate) max_background: c_uint,
+    pub(crate) congestion_threshold: c_uint,
+    pub(crate) time_gran: c_uint,
+    pub(crate) reserved: [c_uint; 22],
 }
 
 #[repr(C)]
 pub struct FuseReq;
 
+/// `fuse_interrupt_func_t`, called by libfuse when a request is interrupted.
+pub(crate) type FuseInterruptFunc = extern "C" fn(req: *mut FuseReq, data: *mut c_void);
+
+// struct fuse_loop_config as of libfuse 3.2, see fuse_session_loop_mt_32.
+#[repr(C)]
+#[derive(Debug)]
+pub(crate) struct FuseLoopConfig {
+    pub(crate) clone_fd: c_int,
+    pub(crate) max_idle_threads: c_uint,
+}
+
 #[repr(C)]
 pub struct FuseSession;
 
-#[derive(Debug)]
+/// `enum fuse_buf_flags`, the kind of a [`FuseBuf`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FuseBufFlags {
+    /// A memory buffer.
     Default = 0,
-    #[allow(dead_code)]
+    /// The buffer is a file descriptor.
     FuseBufIsFd = 1 << 1,
-    #[allow(dead_code)]
+    /// The file descriptor is read or written at the position of the buffer, otherwise
+    /// at its current offset.
     FuseBufFdSeek = 1 << 2,
-    #[allow(dead_code)]
+    /// Short reads and writes of the file descriptor are retried until the end of the
+    /// file or an error.
     FuseBufFdRetry = 1 << 3,
 }
 
+impl BitOr for FuseBufFlags {
+    type Output = i32;
+    fn bitor(self, rhs: Self) -> Self::Output {
+        self as i32 | rhs as i32
+    }
+}
+
+impl BitAnd<FuseBufFlags> for i32 {
+    type Output = i32;
+    fn bitand(self, rhs: FuseBufFlags) -> Self::Output {
+        self & rhs as i32
+    }
+}
+
+/// `enum fuse_buf_copy_flags`, how [`FuseBufvec::copy_to`] moves the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FuseBufCopyFlags {
+    /// Never splice, always copy through memory.
+    NoSplice = 1 << 1,
+    /// Fail with `EINVAL` where splicing is not possible.
+    ForceSplice = 1 << 2,
+    /// Move the pages instead of copying them when splicing, with `SPLICE_F_MOVE`.
+    SpliceMove = 1 << 3,
+    /// Don't block on the pipe when splicing, with `SPLICE_F_NONBLOCK`.
+    SpliceNonblock = 1 << 4,
+}
+
+impl BitOr for FuseBufCopyFlags {
+    type Output = i32;
+    fn bitor(self, rhs: Self) -> Self::Output {
+        self as i32 | rhs as i32
+    }
+}
+
+impl BitOr<FuseBufCopyFlags> for i32 {
+    type Output = i32;
+    fn bitor(self, rhs: FuseBufCopyFlags) -> Self::Output {
+        self | rhs as i32
+    }
+}
+
+/// A buffer of a [`FuseBufvec`], in memory or a file descriptor.
 #[repr(C)]
 pub struct FuseBuf {
     size: size_t,
-    flags: FuseBufFlags,
+    // `enum fuse_buf_flags`, a mask of `FuseBufFlags`
+    flags: c_int,
     mem: *mut c_void,
     fd: c_int,
-    pop: c_uint,
+    pos: off_t,
 }
 
 impl FuseBuf {
-    pub(crate) fn new() -> Self {
+    fn new() -> Self {
         Self {
             size: 0,
-            flags: FuseBufFlags::Default,
+            flags: FuseBufFlags::Default as c_int,
             mem: null_mut(),
             fd: 0,
-            pop: 0,
+            pos: 0,
+        }
+    }
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    /// A mask of [`FuseBufFlags`].
+    pub fn flags(&self) -> i32 {
+        self.flags
+    }
+    /// The file descriptor of a [`FuseBufFlags::FuseBufIsFd`] buffer.
+    pub fn fd(&self) -> Option<RawFd> {
+        if self.flags & FuseBufFlags::FuseBufIsFd != 0 {
+            Some(self.fd)
+        } else {
+            None
         }
     }
+    /// The position of a [`FuseBufFlags::FuseBufFdSeek`] buffer.
+    pub fn pos(&self) -> Option<i64> {
+        if self.flags & FuseBufFlags::FuseBufFdSeek != 0 {
+            Some(self.pos)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Debug for FuseBuf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FuseBuf")
+            .field("size", &self.size)
+            .field("flags", &self.flags)
+            .field("fd", &self.fd())
+            .field("pos", &self.pos())
+            .finish()
+    }
+}
+
+/// The buffer the session loop receives requests into, reused for every request.
+///
+/// libfuse allocates its memory on the first request and keeps it while the buffer
+/// comes back, also when a request arrives in a pipe as a file descriptor.
+pub(crate) struct ReceiveBuf(FuseBuf);
+
+impl ReceiveBuf {
+    pub(crate) fn new() -> Self {
+        Self(FuseBuf::new())
+    }
+}
+
+impl Deref for ReceiveBuf {
+    type Target = FuseBuf;
+    fn deref(&self) -> &FuseBuf {
+        &self.0
+    }
+}
+
+impl DerefMut for ReceiveBuf {
+    fn deref_mut(&mut self) -> &mut FuseBuf {
+        &mut self.0
+    }
+}
+
+impl Drop for ReceiveBuf {
+    fn drop(&mut self) {
+        unsafe { libc::free(self.0.mem) };
+    }
+}
+
+/// The part of a buffer that a [`FuseBufvec`] has not consumed yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FuseBufChunk<'a> {
+    Memory(&'a [u8]),
+    /// `size` bytes of `fd`, from `pos` or from its current offset.
+    Fd {
+        fd: RawFd,
+        pos: Option<i64>,
+        size: usize,
+    },
+}
+
+/// Flags passed to `open(2)`, the `O_*` constants of libc.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct OFlag(c_int);
+
+impl OFlag {
+    pub const RDONLY: OFlag = OFlag(libc::O_RDONLY);
+    pub const WRONLY: OFlag = OFlag(libc::O_WRONLY);
+    pub const RDWR: OFlag = OFlag(libc::O_RDWR);
+    /// Mask of the access mode, one of `RDONLY`, `WRONLY` and `RDWR`.
+    pub const ACCMODE: OFlag = OFlag(libc::O_ACCMODE);
+    pub const APPEND: OFlag = OFlag(libc::O_APPEND);
+    pub const CREAT: OFlag = OFlag(libc::O_CREAT);
+    pub const EXCL: OFlag = OFlag(libc::O_EXCL);
+    pub const TRUNC: OFlag = OFlag(libc::O_TRUNC);
+    pub const NONBLOCK: OFlag = OFlag(libc::O_NONBLOCK);
+    pub const SYNC: OFlag = OFlag(libc::O_SYNC);
+    pub const DSYNC: OFlag = OFlag(libc::O_DSYNC);
+    pub const DIRECT: OFlag = OFlag(libc::O_DIRECT);
+    pub const DIRECTORY: OFlag = OFlag(libc::O_DIRECTORY);
+    pub const NOFOLLOW: OFlag = OFlag(libc::O_NOFOLLOW);
+    pub const NOATIME: OFlag = OFlag(libc::O_NOATIME);
+    pub const CLOEXEC: OFlag = OFlag(libc::O_CLOEXEC);
+    pub const LARGEFILE: OFlag = OFlag(libc::O_LARGEFILE);
+
+    pub fn from_bits(bits: i32) -> Self {
+        OFlag(bits)
+    }
+    pub fn bits(self) -> i32 {
+        self.0
+    }
+    /// `RDONLY`, `WRONLY` or `RDWR`.
+    pub fn access_mode(self) -> OFlag {
+        OFlag(self.0 & libc::O_ACCMODE)
+    }
+    /// Whether all bits of `other` are set; use [`OFlag::access_mode`] for the access mode.
+    pub fn contains(self, other: OFlag) -> bool {
+        self.0 & other.0 == other.0
+    }
+    pub fn insert(&mut self, other: OFlag) {
+        self.0 |= other.0;
+    }
+    pub fn remove(&mut self, other: OFlag) {
+        self.0 &= !other.0;
+    }
 }
 
+impl BitOr for OFlag {
+    type Output = OFlag;
+    fn bitor(self, rhs: Self) -> Self::Output {
+        OFlag(self.0 | rhs.0)
+    }
+}
+
+impl std::fmt::Debug for OFlag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "OFlag({:#o})", self.0)
+    }
+}
+
+/// Information about an open file, `struct fuse_file_info` of libfuse.
+///
+/// The flags that libfuse keeps in a bitfield are read and written with the
+/// accessors below.
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Clone, Default)]
 pub struct FuseFileInfo {
-    pub flags: i16,
-    pub writepage: u16,
-    pub direct_io: u16,
-    pub keep_cache: u16,
-    pub flush: u16,
-    pub nonseekable: u16,
-    pub flock_release: u16,
-    pub cache_readdir: u16,
-    pub padding: u16,
-    pub padding2: u16,
-    pub fh: u64,
-    pub lock_owner: u64,
-    pub poll_events: u32,
+    flags: c_int,
+    // The bitfields `writepage` to `padding`, see the `FI_*` masks.
+    bits: c_uint,
+    padding2: c_uint,
+    fh: u64,
+    lock_owner: u64,
+    poll_events: u32,
+}
+
+// Masks of the bitfields in `struct fuse_file_info` as laid out by GCC and Clang on
+// little-endian targets, checked against the headers by `abi`.
+pub(crate) const FI_WRITEPAGE: c_uint = 1 << 0;
+pub(crate) const FI_DIRECT_IO: c_uint = 1 << 1;
+pub(crate) const FI_KEEP_CACHE: c_uint = 1 << 2;
+pub(crate) const FI_FLUSH: c_uint = 1 << 3;
+pub(crate) const FI_NONSEEKABLE: c_uint = 1 << 4;
+pub(crate) const FI_FLOCK_RELEASE: c_uint = 1 << 5;
+pub(crate) const FI_CACHE_READDIR: c_uint = 1 << 6;
+
+macro_rules! file_info_bit {
+    ($(#[$meta:meta])* $get:ident, $set:ident, $mask:ident) => {
+        $(#[$meta])*
+        pub fn $get(&self) -> bool {
+            self.bits & $mask != 0
+        }
+        pub fn $set(&mut self, value: bool) {
+            if value {
+                self.bits |= $mask;
+            } else {
+                self.bits &= !$mask;
+            }
+        }
+    };
+}
+
+impl FuseFileInfo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Flags passed to `open(2)` or `creat(2)`.
+    pub fn flags(&self) -> OFlag {
+        OFlag(self.flags)
+    }
+    pub fn set_flags(&mut self, flags: OFlag) {
+        self.flags = flags.0;
+    }
+    file_info_bit!(
+        /// The write comes from the page cache, not from a process.
+        writepage,
+        set_writepage,
+        FI_WRITEPAGE
+    );
+    file_info_bit!(
+        /// Set in `open` to bypass the page cache for this file.
+        direct_io,
+        set_direct_io,
+        FI_DIRECT_IO
+    );
+    file_info_bit!(
+        /// Set in `open` to keep the data already cached for this file.
+        keep_cache,
+        set_keep_cache,
+        FI_KEEP_CACHE
+    );
+    file_info_bit!(
+        /// The `release` is also a flush, or `flush` is called because of a `close`.
+        flush,
+        set_flush,
+        FI_FLUSH
+    );
+    file_info_bit!(
+        /// Set in `open` when the file does not support seeking.
+        nonseekable,
+        set_nonseekable,
+        FI_NONSEEKABLE
+    );
+    file_info_bit!(
+        /// The `release` must also remove the `flock` locks of `lock_owner`.
+        flock_release,
+        set_flock_release,
+        FI_FLOCK_RELEASE
+    );
+    file_info_bit!(
+        /// Set in `opendir` to let the kernel cache the entries of the directory.
+        cache_readdir,
+        set_cache_readdir,
+        FI_CACHE_READDIR
+    );
+    /// File handle chosen by the filesystem in `open`, `opendir` or `create`.
+    pub fn fh(&self) -> u64 {
+        self.fh
+    }
+    pub fn set_fh(&mut self, fh: u64) {
+        self.fh = fh;
+    }
+    /// Owner of the locks, given for `flush`, `release`, `getlk`, `setlk` and `flock`.
+    pub fn lock_owner(&self) -> u64 {
+        self.lock_owner
+    }
+    /// Events the caller of `poll` is waiting for.
+    pub fn poll_events(&self) -> u32 {
+        self.poll_events
+    }
+}
+
+impl std::fmt::Debug for FuseFileInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FuseFileInfo")
+            .field("flags", &self.flags())
+            .field("writepage", &self.writepage())
+            .field("direct_io", &self.direct_io())
+            .field("keep_cache", &self.keep_cache())
+            .field("flush", &self.flush())
+            .field("nonseekable", &self.nonseekable())
+            .field("flock_release", &self.flock_release())
+            .field("cache_readdir", &self.cache_readdir())
+            .field("fh", &self.fh)
+            .field("lock_owner", &self.lock_owner)
+            .field("poll_events", &self.poll_events)
+            .finish()
+    }
 }
 
 #[repr(C)]
@@ -99,7 +412,7 @@ pub struct FuseForgetData {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct FuseCtx {
     pub uid: uid_t,
     pub gid: gid_t,
@@ -115,7 +428,101 @@ pub struct FuseBufvec {
     buf: [FuseBuf; 1],
 }
 
+impl FuseBufvec {
+    /// A vector of a single memory buffer, `FUSE_BUFVEC_INIT` of libfuse.
+    pub(crate) fn new(mem: *mut c_void, size: usize) -> Self {
+        let mut buf = FuseBuf::new();
+        buf.mem = mem;
+        buf.size = size;
+        Self::with_buf(buf)
+    }
+    /// A vector of `size` bytes of `fd`, read or written at `pos` or, without one, at
+    /// the current offset of `fd`.
+    pub fn from_fd(fd: RawFd, pos: Option<i64>, size: usize) -> Self {
+        let mut buf = FuseBuf::new();
+        buf.flags = FuseBufFlags::FuseBufIsFd | FuseBufFlags::FuseBufFdRetry;
+        if let Some(pos) = pos {
+            buf.flags |= FuseBufFlags::FuseBufFdSeek as c_int;
+            buf.pos = pos;
+        }
+        buf.fd = fd;
+        buf.size = size;
+        Self::with_buf(buf)
+    }
+    fn with_buf(buf: FuseBuf) -> Self {
+        Self {
+            count: 1,
+            idx: 0,
+            off: 0,
+            buf: [buf],
+        }
+    }
+    /// All the buffers, including those already consumed.
+    pub fn bufs(&self) -> &[FuseBuf] {
+        // `buf` is a flexible array member of `count` buffers.
+        unsafe { slice::from_raw_parts(self.buf.as_ptr(), self.count) }
+    }
+    /// The data left in the buffers, in order.
+    pub fn chunks(&self) -> impl Iterator<Item = FuseBufChunk<'_>> {
+        let off = self.off;
+        let idx = self.idx;
+        self.bufs()[idx.min(self.count)..]
+            .iter()
+            .enumerate()
+            .map(move |(i, buf)| {
+                let skip = if i == 0 { off.min(buf.size) } else { 0 };
+                match buf.fd() {
+                    Some(fd) => FuseBufChunk::Fd {
+                        fd,
+                        pos: buf.pos().map(|pos| pos + skip as i64),
+                        size: buf.size - skip,
+                    },
+                    None if buf.mem.is_null() => FuseBufChunk::Memory(&[]),
+                    None => {
+                        let mem = unsafe { slice::from_raw_parts(buf.mem as *const u8, buf.size) };
+                        FuseBufChunk::Memory(&mem[skip..])
+                    }
+                }
+            })
+    }
+    /// The total size of the buffers, `fuse_buf_size` of libfuse.
+    pub fn size(&self) -> usize {
+        unsafe { fuse_buf_size(self) }
+    }
+    /// Copies the data left in the buffers into `dst` with `fuse_buf_copy`, which
+    /// splices between pipes and file descriptors unless `flags`, a mask of
+    /// [`FuseBufCopyFlags`], tells otherwise.
+    ///
+    /// Both vectors are advanced past the data copied, and the count is short at the
+    /// end of a file descriptor or of `dst`.
+    pub fn copy_to(&mut self, dst: &mut FuseBufvec, flags: i32) -> Result<usize, Errno> {
+        let res = unsafe { fuse_buf_copy(dst, self, flags) };
+        if res < 0 {
+            return Err(Errno::new(res as i32));
+        }
+        Ok(res as usize)
+    }
+    /// Writes the data left to `fd` at `pos`, or at its current offset without one.
+    pub fn copy_to_fd(&mut self, fd: RawFd, pos: Option<i64>, flags: i32) -> Result<usize, Errno> {
+        let size = self.size();
+        self.copy_to(&mut FuseBufvec::from_fd(fd, pos, size), flags)
+    }
+    /// Copies the data left into `dst`, as much as fits.
+    pub fn copy_to_slice(&mut self, dst: &mut [u8]) -> Result<usize, Errno> {
+        let mut dst = FuseBufvec::new(dst.as_mut_ptr() as *mut c_void, dst.len());
+        self.copy_to(&mut dst, 0)
+    }
+    /// Copies the data left, which may come from file descriptors, into memory.
+    pub fn copy_to_vec(&mut self) -> Result<Vec<u8>, Errno> {
+        let mut data = vec![0u8; self.size()];
+        let len = self.copy_to_slice(&mut data)?;
+        data.truncate(len);
+        Ok(data)
+    }
+}
+
 #[repr(C)]
+#[derive(Clone)]
 pub struct FuseEntryParam {
     pub ino: u64,
     pub generation: u64,
@@ -136,157 +543,195 @@ impl FuseEntryParam {
     }
 }
 
+// `stat` of libc has no `Debug` impl without its `extra_traits` feature.
+impl std::fmt::Debug for FuseEntryParam {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FuseEntryParam")
+            .field("ino", &self.ino)
+            .field("generation", &self.generation)
+            .field("attr", &FuseAttr::new(&self.attr))
+            .field("attr_timeout", &self.attr_timeout)
+            .field("entry_timeout", &self.entry_timeout)
+            .finish()
+    }
+}
+
 #[repr(C)]
 pub(crate) struct FuseLowLevelOps {
     // void (*init) (void *userdata, struct fuse_conn_info *conn);
-    pub(crate) init: *const fn(*mut c_void, *mut FuseConnInfo),
+    pub(crate) init: Option<extern "C" fn(*mut c_void, *mut FuseConnInfo)>,
 
     // void (*destroy) (void *userdata);
-    pub(crate) destroy: *const fn(*mut c_void),
+    pub(crate) destroy: Option<extern "C" fn(*mut c_void)>,
 
     // void (*lookup) (fuse_req_t req, fuse_ino_t parent, const char *name);
-    pub(crate) lookup: *const fn(*mut FuseReq, u64, *const c_char),
+    pub(crate) lookup: Option<extern "C" fn(*mut FuseReq, u64, *const c_char)>,
 
     // void (*forget) (fuse_req_t req, fuse_ino_t ino, uint64_t nlookup);
-    pub(crate) forget: *const fn(*mut FuseReq, u64, u64),
+    pub(crate) forget: Option<extern "C" fn(*mut FuseReq, u64, u64)>,
 
     // void (*getattr) (fuse_req_t req, fuse_ino_t ino, struct fuse_file_info *fi);
-    pub(crate) getattr: *const fn(*mut FuseReq, u64, *mut FuseFileInfo),
+    pub(crate) getattr: Option<extern "C" fn(*mut FuseReq, u64, *mut FuseFileInfo)>,
 
     // void (*setattr) (fuse_req_t req, fuse_ino_t ino, struct stat *attr, int to_set, struct fuse_file_info *fi);
-    pub(crate) setattr: *const fn(*mut FuseReq, u64, *mut stat, i16, *mut FuseFileInfo),
+    pub(crate) setattr:
+        Option<extern "C" fn(*mut FuseReq, u64, *mut stat, c_int, *mut FuseFileInfo)>,
 
     // void (*readlink) (fuse_req_t req, fuse_ino_t ino);
-    pub(crate) readlink: *const fn(*mut FuseReq, u64),
+    pub(crate) readlink: Option<extern "C" fn(*mut FuseReq, u64)>,
 
     // void (*mknod) (fuse_req_t req, fuse_ino_t parent, const char *name, mode_t mode, dev_t rdev);
-    pub(crate) mknod: *const fn(*mut FuseReq, u64, *const c_char, mode_t, dev_t),
+    pub(crate) mknod: Option<extern "C" fn(*mut FuseReq, u64, *const c_char, mode_t, dev_t)>,
 
     // void (*mkdir) (fuse_req_t req, fuse_ino_t parent, const char *name, mode_t mode);
-    pub(crate) mkdir: *const fn(*mut FuseReq, u64, *const c_char, mode_t),
+    pub(crate) mkdir: Option<extern "C" fn(*mut FuseReq, u64, *const c_char, mode_t)>,
 
     // void (*unlink) (fuse_req_t req, fuse_ino_t parent, const char *name);
-    pub(crate) unlink: *const fn(*mut FuseReq, u64, *const c_char),
+    pub(crate) unlink: Option<extern "C" fn(*mut FuseReq, u64, *const c_char)>,
 
     // void (*rmdir) (fuse_req_t req, fuse_ino_t parent, const char *name);
-    pub(crate) rmdir: *const fn(*mut FuseReq, u64, *const c_char),
+    pub(crate) rmdir: Option<extern "C" fn(*mut FuseReq, u64, *const c_char)>,
 
     // void (*symlink) (fuse_req_t req, const char *link, fuse_ino_t parent, const char *name);
-    pub(crate) symlink: *const fn(*mut FuseReq, *const c_char, u64, *const c_char),
+    pub(crate) symlink: Option<extern "C" fn(*mut FuseReq, *const c_char, u64, *const c_char)>,
 
     // void (*rename) (fuse_req_t req, fuse_ino_t parent, const char *name, fuse_ino_t newparent, const char *newname, unsigned int flags);
-    pub(crate) rename: *const fn(*mut FuseReq, u64, *const c_char, u64, *const c_char, u16),
+    pub(crate) rename:
+        Option<extern "C" fn(*mut FuseReq, u64, *const c_char, u64, *const c_char, c_uint)>,
 
     // void (*link) (fuse_req_t req, fuse_ino_t ino, fuse_ino_t newparent, const char *newname);
-    pub(crate) link: *const fn(*mut FuseReq, u64, u64, *const c_char),
+    pub(crate) link: Option<extern "C" fn(*mut FuseReq, u64, u64, *const c_char)>,
 
     // void (*open) (fuse_req_t req, fuse_ino_t ino, struct fuse_file_info *fi);
-    pub(crate) open: *const fn(*mut FuseReq, u64, *mut FuseFileInfo),
+    pub(crate) open: Option<extern "C" fn(*mut FuseReq, u64, *mut FuseFileInfo)>,
 
     // void (*read) (fuse_req_t req, fuse_ino_t ino, size_t size, off_t off, struct fuse_file_info *fi);
-    pub(crate) read: *const fn(*mut FuseReq, u64, size_t, off_t, *mut FuseFileInfo),
+    pub(crate) read: Option<extern "C" fn(*mut FuseReq, u64, size_t, off_t, *mut FuseFileInfo)>,
 
     // void (*write) (fuse_req_t req, fuse_ino_t ino, const char *buf, size_t size, off_t off, struct fuse_file_info *fi);
-    pub(crate) write: *const fn(*mut FuseReq, u64, *const c_char, size_t, off_t, *mut FuseFileInfo),
+    pub(crate) write:
+        Option<extern "C" fn(*mut FuseReq, u64, *const c_char, size_t, off_t, *mut FuseFileInfo)>,
 
     // void (*flush) (fuse_req_t req, fuse_ino_t ino, struct fuse_file_info *fi);
-    pub(crate) flush: *const fn(*mut FuseReq, u64, *mut FuseFileInfo),
+    pub(crate) flush: Option<extern "C" fn(*mut FuseReq, u64, *mut FuseFileInfo)>,
 
     // void (*release) (fuse_req_t req, fuse_ino_t ino, struct fuse_file_info *fi);
-    pub(crate) release: *const fn(*mut FuseReq, u64, *mut FuseFileInfo),
+    pub(crate) release: Option<extern "C" fn(*mut FuseReq, u64, *mut FuseFileInfo)>,
 
     // void (*fsync) (fuse_req_t req, fuse_ino_t ino, int datasync, struct fuse_file_info *fi);
-    pub(crate) fsync: *const fn(*mut FuseReq, u64, c_int, *mut FuseFileInfo),
+    pub(crate) fsync: Option<extern "C" fn(*mut FuseReq, u64, c_int, *mut FuseFileInfo)>,
 
     // void (*opendir) (fuse_req_t req, fuse_ino_t ino, struct fuse_file_info *fi);
-    pub(crate) opendir: *const fn(*mut FuseReq, u64, *mut FuseFileInfo),
+    pub(crate) opendir: Option<extern "C" fn(*mut FuseReq, u64, *mut FuseFileInfo)>,
 
     // void (*readdir) (fuse_req_t req, fuse_ino_t ino, size_t size, off_t off, struct fuse_file_info *fi);
-    pub(crate) readdir: *const fn(*mut FuseReq, u64, size_t, off_t, *mut FuseFileInfo),
+    pub(crate) readdir: Option<extern "C" fn(*mut FuseReq, u64, size_t, off_t, *mut FuseFileInfo)>,
 
     // void (*releasedir) (fuse_req_t req, fuse_ino_t ino, struct fuse_file_info *fi);
-    pub(crate) releasedir: *const fn(*mut FuseReq, u64, *mut FuseFileInfo),
+    pub(crate) releasedir: Option<extern "C" fn(*mut FuseReq, u64, *mut FuseFileInfo)>,
 
     // void (*fsyncdir) (fuse_req_t req, fuse_ino_t ino, int datasync, struct fuse_file_info *fi);
-    pub(crate) fsyncdir: *const fn(*mut FuseReq, u64, c_int, *mut FuseFileInfo),
+    pub(crate) fsyncdir: Option<extern "C" fn(*mut FuseReq, u64, c_int, *mut FuseFileInfo)>,
 
     // void (*statfs) (fuse_req_t req, fuse_ino_t ino);
-    pub(crate) statfs: *const fn(*mut FuseReq, u64),
+    pub(crate) statfs: Option<extern "C" fn(*mut FuseReq, u64)>,
 
     // void (*setxattr) (fuse_req_t req, fuse_ino_t ino, const char *name, const char *value, size_t size, int flags);
-    pub(crate) setxattr: *const fn(*mut FuseReq, u64, *const c_char, *const c_char, size_t, c_int),
+    pub(crate) setxattr:
+        Option<extern "C" fn(*mut FuseReq, u64, *const c_char, *const c_char, size_t, c_int)>,
 
     // void (*getxattr) (fuse_req_t req, fuse_ino_t ino, const char *name, size_t size);
-    pub(crate) getxattr: *const fn(*mut FuseReq, u64, *const c_char, size_t),
+    pub(crate) getxattr: Option<extern "C" fn(*mut FuseReq, u64, *const c_char, size_t)>,
 
     // void (*listxattr) (fuse_req_t req, fuse_ino_t ino, size_t size);
-    pub(crate) listxattr: *const fn(*mut FuseReq, u64, size_t),
+    pub(crate) listxattr: Option<extern "C" fn(*mut FuseReq, u64, size_t)>,
 
     // void (*removexattr) (fuse_req_t req, fuse_ino_t ino, const char *name);
-    pub(crate) removexattr: *const fn(*mut FuseReq, u64, *const c_char),
+    pub(crate) removexattr: Option<extern "C" fn(*mut FuseReq, u64, *const c_char)>,
 
     // void (*access) (fuse_req_t req, fuse_ino_t ino, int mask);
-    pub(crate) access: *const fn(*mut FuseReq, u64, c_int),
+    pub(crate) access: Option<extern "C" fn(*mut FuseReq, u64, c_int)>,
 
     // void (*create) (fuse_req_t req, fuse_ino_t parent, const char *name, mode_t mode, struct fuse_file_info *fi);
-    pub(crate) create: *const fn(*mut FuseReq, u64, *const c_char, mode_t, *mut FuseFileInfo),
+    pub(crate) create:
+        Option<extern "C" fn(*mut FuseReq, u64, *const c_char, mode_t, *mut FuseFileInfo)>,
 
     // void (*getlk) (fuse_req_t req, fuse_ino_t ino, struct fuse_file_info *fi, struct flock *lock);
-    pub(crate) getlk: *const fn(*mut FuseReq, u64, *mut FuseFileInfo, *mut flock),
+    pub(crate) getlk: Option<extern "C" fn(*mut FuseReq, u64, *mut FuseFileInfo, *mut flock)>,
 
     // void (*setlk) (fuse_req_t req, fuse_ino_t ino, struct fuse_file_info *fi, struct flock *lock, int sleep);
-    pub(crate) setlk: *const fn(*mut FuseReq, u64, *mut FuseFileInfo, *mut flock, c_int),
+    pub(crate) setlk:
+        Option<extern "C" fn(*mut FuseReq, u64, *mut FuseFileInfo, *mut flock, c_int)>,
 
     // void (*bmap) (fuse_req_t req, fuse_ino_t ino, size_t blocksize, uint64_t idx);
-    pub(crate) bmap: *const fn(*mut FuseReq, u64, size_t, u64),
-
-    // TODO: Ioctl
+    pub(crate) bmap: Option<extern "C" fn(*mut FuseReq, u64, size_t, u64)>,
+
+    // void (*ioctl) (fuse_req_t req, fuse_ino_t ino, unsigned int cmd, void *arg, struct fuse_file_info *fi, unsigned flags, const void *in_buf, size_t in_bufsz, size_t out_bufsz);
+    pub(crate) ioctl: Option<
+        extern "C" fn(
+            *mut FuseReq,
+            u64,
+            c_uint,
+            *mut c_void,
+            *mut FuseFileInfo,
+            c_uint,
+            *const c_void,
+            size_t,
+            size_t,
+        ),
+    >,
 
     // void (*poll) (fuse_req_t req, fuse_ino_t ino, struct fuse_file_info *fi, struct fuse_pollhandle *ph);
-    pub(crate) poll: *const fn(*mut FuseReq, u64, *mut FuseFileInfo, *mut FusePollhandle),
+    pub(crate) poll:
+        Option<extern "C" fn(*mut FuseReq, u64, *mut FuseFileInfo, *mut FusePollhandle)>,
 
     // void (*write_buf) (fuse_req_t req, fuse_ino_t ino, struct fuse_bufvec *bufv, off_t off, struct fuse_file_info *fi);
-    pub(crate) write_buf: *const fn(*mut FuseReq, u64, *mut FuseBufvec, off_t, *mut FuseFileInfo),
+    pub(crate) write_buf:
+        Option<extern "C" fn(*mut FuseReq, u64, *mut FuseBufvec, off_t, *mut FuseFileInfo)>,
 
-    // TODO
     // void (*retrieve_reply) (fuse_req_t req, void *cookie, fuse_ino_t ino, off_t offset, struct fuse_bufvec *bufv);
-    // pub(crate) retrieve_reply: *const fn(*mut FuseReq, *mut c_void, u64, off_t, *mut FuseBufvec),
+    pub(crate) retrieve_reply:
+        Option<extern "C" fn(*mut FuseReq, *mut c_void, u64, off_t, *mut FuseBufvec)>,
 
     // void (*forget_multi) (fuse_req_t req, size_t count, struct fuse_forget_data *forgets);
-    pub(crate) forget_multi: *const fn(*mut FuseReq, size_t, *mut FuseForgetData),
+    pub(crate) forget_multi: Option<extern "C" fn(*mut FuseReq, size_t, *mut FuseForgetData)>,
 
     // void (*flock) (fuse_req_t req, fuse_ino_t ino, struct fuse_file_info *fi, int op);
-    pub(crate) flock: *const fn(*mut FuseReq, u64, *mut FuseFileInfo, c_int),
+    pub(crate) flock: Option<extern "C" fn(*mut FuseReq, u64, *mut FuseFileInfo, c_int)>,
 
     // void (*fallocate) (fuse_req_t req, fuse_ino_t ino, int mode, off_t offset, off_t length, struct fuse_file_info *fi);
-    pub(crate) fallocate: *const fn(*mut FuseReq, u64, c_int, off_t, off_t, *mut FuseFileInfo),
+    pub(crate) fallocate:
+        Option<extern "C" fn(*mut FuseReq, u64, c_int, off_t, off_t, *mut FuseFileInfo)>,
 
     // void (*readdirplus) (fuse_req_t req, fuse_ino_t ino, size_t size, off_t off, struct fuse_file_info *fi);
-    pub(crate) readdirplus: *const fn(*mut FuseReq, u64, size_t, off_t, *mut FuseFileInfo),
+    pub(crate) readdirplus:
+        Option<extern "C" fn(*mut FuseReq, u64, size_t, off_t, *mut FuseFileInfo)>,
 
     // void (*copy_file_range) (fuse_req_t req, fuse_ino_t ino_in,
     //              off_t off_in, struct fuse_file_info *fi_in,
     //              fuse_ino_t ino_out, off_t off_out,
     //              struct fuse_file_info *fi_out, size_t len,
     //              int flags);
-    pub(crate) copy_file_range: *const fn(
-        *mut FuseReq,
-        u64,
-        off_t,
-        *mut FuseFileInfo,
-        u64,
-        off_t,
-        *mut FuseFileInfo,
-        size_t,
-        c_int,
-    ),
+    pub(crate) copy_file_range: Option<
+        extern "C" fn(
+            *mut FuseReq,
+            u64,
+            off_t,
+            *mut FuseFileInfo,
+            u64,
+            off_t,
+            *mut FuseFileInfo,
+            size_t,
+            c_int,
+        ),
+    >,
 
     // void (*lseek) (fuse_req_t req, fuse_ino_t ino, off_t off, int whence, struct fuse_file_info *fi);
-    pub(crate) lseek: *const fn(*mut FuseReq, u64, off_t, c_int, *mut FuseFileInfo),
+    pub(crate) lseek: Option<extern "C" fn(*mut FuseReq, u64, off_t, c_int, *mut FuseFileInfo)>,
 }
 
-#[repr(C)]
-#[derive(Debug)]
+// Converted to `struct stat` of libc with `convert`, never handed to libfuse as is.
+#[derive(Debug, Clone, Copy, Default)]
 pub struct FuseAttr {
     pub dev: u64,
     pub ino: u64,
@@ -349,7 +794,8 @@ impl FuseAttr {
     }
 }
 
-#[repr(C)]
+// Converted to `struct statvfs` of libc with `convert`, never handed to libfuse as is.
+#[derive(Debug, Clone)]
 pub struct FuseStatvfs {
     pub bsize: u64,
     pub frsize: u64,
@@ -397,7 +843,8 @@ impl FuseStatvfs {
     }
 }
 
-#[repr(C)]
+// Converted to `struct flock` of libc with `convert`, never handed to libfuse as is.
+#[derive(Debug, Clone)]
 pub struct FuseLock {
     type_: i16,
     whence: i16,
@@ -469,35 +916,6 @@ impl FileType {
     }
 }
 
-pub struct FuseDirectory {
-    pub name: Vec<u8>,
-    pub file_type: FileType,
-    pub ino: u64,
-}
-
-impl FuseDirectory {
-    pub fn attr(&self) -> FuseAttr {
-        FuseAttr {
-            dev: 0,
-            ino: self.ino,
-            size: 0,
-            blocks: 0,
-            atime: 0,
-            atimensec: 0,
-            mtime: 0,
-            mtimensec: 0,
-            ctime: 0,
-            ctimensec: 0,
-            mode: self.file_type.to_mode(),
-            nlink: 0,
-            uid: 0,
-            gid: 0,
-            rdev: 0,
-            blksize: 0,
-        }
-    }
-}
-
 #[link(name = "fuse3")]
 extern "C" {
     #[allow(improper_ctypes)]
@@ -510,7 +928,10 @@ extern "C" {
     #[allow(improper_ctypes)]
     pub(crate) fn fuse_session_destroy(fuse_session: *mut FuseSession);
     #[allow(improper_ctypes)]
-    pub(crate) fn fuse_session_mount(fuse_session: *mut FuseSession, mountpoint: *const c_char);
+    pub(crate) fn fuse_session_mount(
+        fuse_session: *mut FuseSession,
+        mountpoint: *const c_char,
+    ) -> c_int;
     #[allow(improper_ctypes)]
     pub(crate) fn fuse_session_unmount(fuse_session: *mut FuseSession);
     // #[allow(improper_ctypes)]
@@ -524,6 +945,14 @@ extern "C" {
     #[allow(improper_ctypes)]
     pub(crate) fn fuse_req_ctx(req: *mut FuseReq) -> *const FuseCtx;
     #[allow(improper_ctypes)]
+    pub(crate) fn fuse_req_getgroups(req: *mut FuseReq, size: c_int, list: *mut gid_t) -> c_int;
+    #[allow(improper_ctypes)]
+    pub(crate) fn fuse_req_interrupt_func(
+        req: *mut FuseReq,
+        func: Option<FuseInterruptFunc>,
+        data: *mut c_void,
+    );
+    #[allow(improper_ctypes)]
     pub(crate) fn fuse_reply_open(req: *mut FuseReq, fi: *const FuseFileInfo) -> c_int;
     #[allow(improper_ctypes)]
     pub(crate) fn fuse_reply_write(req: *mut FuseReq, count: size_t) -> c_int;
@@ -546,10 +975,10 @@ extern "C" {
     pub(crate) fn fuse_reply_readlink(req: *mut FuseReq, link: *const c_char) -> c_int;
     #[allow(improper_ctypes)]
     pub(crate) fn fuse_reply_buf(req: *mut FuseReq, buf: *const c_char, size: size_t) -> c_int;
-    // #[allow(improper_ctypes)]
-    // pub(crate) fn fuse_reply_data(req: *mut FuseReq, bufv: *mut FuseBufvec, flags: FuseBufCopyFlags) -> c_int;
-    // #[allow(improper_ctypes)]
-    // pub(crate) fn fuse_reply_iov(req: *mut FuseReq, iov: *const iovec, count: c_int) -> c_int;
+    #[allow(improper_ctypes)]
+    pub(crate) fn fuse_reply_data(req: *mut FuseReq, bufv: *mut FuseBufvec, flags: c_int) -> c_int;
+    #[allow(improper_ctypes)]
+    pub(crate) fn fuse_reply_iov(req: *mut FuseReq, iov: *const iovec, count: c_int) -> c_int;
     #[allow(improper_ctypes)]
     pub(crate) fn fuse_reply_statfs(req: *mut FuseReq, stbuf: *const statvfs) -> c_int;
     #[allow(improper_ctypes)]
@@ -561,6 +990,21 @@ extern "C" {
     #[allow(improper_ctypes)]
     pub(crate) fn fuse_reply_poll(req: *mut FuseReq, revents: u32) -> c_int;
     #[allow(improper_ctypes)]
+    pub(crate) fn fuse_reply_ioctl(
+        req: *mut FuseReq,
+        result: c_int,
+        buf: *const c_void,
+        size: size_t,
+    ) -> c_int;
+    #[allow(improper_ctypes)]
+    pub(crate) fn fuse_reply_ioctl_retry(
+        req: *mut FuseReq,
+        in_iov: *const iovec,
+        in_count: size_t,
+        out_iov: *const iovec,
+        out_count: size_t,
+    ) -> c_int;
+    #[allow(improper_ctypes)]
     pub(crate) fn fuse_reply_lseek(req: *mut FuseReq, off: off_t) -> c_int;
     #[allow(improper_ctypes)]
     pub(crate) fn fuse_add_direntry(
@@ -572,6 +1016,15 @@ extern "C" {
         off: off_t,
     ) -> size_t;
     #[allow(improper_ctypes)]
+    pub(crate) fn fuse_add_direntry_plus(
+        req: *mut FuseReq,
+        buf: *mut c_char,
+        bufsize: size_t,
+        name: *const c_char,
+        e: *const FuseEntryParam,
+        off: off_t,
+    ) -> size_t;
+    #[allow(improper_ctypes)]
     pub(crate) fn fuse_session_receive_buf(
         fuse_session: *mut FuseSession,
         buf: *mut FuseBuf,
@@ -582,19 +1035,106 @@ extern "C" {
         buf: *const FuseBuf,
     ) -> c_int;
     #[allow(improper_ctypes)]
+    pub(crate) fn fuse_session_loop_mt_32(
+        fuse_session: *mut FuseSession,
+        config: *mut FuseLoopConfig,
+    ) -> c_int;
+    #[allow(improper_ctypes)]
     pub(crate) fn fuse_set_signal_handlers(fuse_session: *mut FuseSession) -> c_int;
     #[allow(improper_ctypes)]
     pub(crate) fn fuse_remove_signal_handlers(fuse_session: *mut FuseSession);
+    #[allow(improper_ctypes)]
+    pub(crate) fn fuse_lowlevel_notify_inval_inode(
+        fuse_session: *mut FuseSession,
+        ino: u64,
+        off: off_t,
+        len: off_t,
+    ) -> c_int;
+    #[allow(improper_ctypes)]
+    pub(crate) fn fuse_lowlevel_notify_inval_entry(
+        fuse_session: *mut FuseSession,
+        parent: u64,
+        name: *const c_char,
+        namelen: size_t,
+    ) -> c_int;
+    #[allow(improper_ctypes)]
+    pub(crate) fn fuse_lowlevel_notify_delete(
+        fuse_session: *mut FuseSession,
+        parent: u64,
+        child: u64,
+        name: *const c_char,
+        namelen: size_t,
+    ) -> c_int;
+    #[allow(improper_ctypes)]
+    pub(crate) fn fuse_lowlevel_notify_store(
+        fuse_session: *mut FuseSession,
+        ino: u64,
+        offset: off_t,
+        bufv: *mut FuseBufvec,
+        flags: c_int,
+    ) -> c_int;
+    #[allow(improper_ctypes)]
+    pub(crate) fn fuse_lowlevel_notify_retrieve(
+        fuse_session: *mut FuseSession,
+        ino: u64,
+        size: size_t,
+        offset: off_t,
+        cookie: *mut c_void,
+    ) -> c_int;
+    #[allow(improper_ctypes)]
+    pub(crate) fn fuse_lowlevel_notify_poll(ph: *mut FusePollhandle) -> c_int;
+    #[allow(improper_ctypes)]
+    pub(crate) fn fuse_pollhandle_destroy(ph: *mut FusePollhandle);
+    #[allow(improper_ctypes)]
+    pub(crate) fn fuse_buf_size(bufv: *const FuseBufvec) -> size_t;
+    #[allow(improper_ctypes)]
+    pub(crate) fn fuse_buf_copy(
+        dst: *mut FuseBufvec,
+        src: *mut FuseBufvec,
+        flags: c_int,
+    ) -> ssize_t;
 
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::fuse::{FuseBufChunk, FuseBufvec, FuseFileInfo, OFlag, FI_DIRECT_IO, FI_KEEP_CACHE};
     use crate::FuseAttr;
-    use libc::{stat, S_IFREG, S_IRGRP, S_IROTH, S_IRUSR, S_IWGRP, S_IWOTH, S_IWUSR};
+    use libc::{c_void, stat, S_IFREG, S_IRGRP, S_IROTH, S_IRUSR, S_IWGRP, S_IWUSR};
     use std::borrow::BorrowMut;
     use std::ffi::CString;
     use std::mem;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn bufvec_chunks_and_copies() {
+        let data = b"hello world";
+        let mut bufv = FuseBufvec::new(data.as_ptr() as *mut c_void, data.len());
+        assert_eq!(bufv.size(), 11);
+        let mut head = [0u8; 6];
+        assert_eq!(bufv.copy_to_slice(&mut head), Ok(6));
+        assert_eq!(&head, b"hello ");
+        let chunks: Vec<_> = bufv.chunks().collect();
+        assert_eq!(chunks, vec![FuseBufChunk::Memory(b"world")]);
+
+        let path = std::env::temp_dir().join(format!("rusfuse-bufvec-{}", std::process::id()));
+        let file = std::fs::File::create(&path).unwrap();
+        assert_eq!(bufv.copy_to_fd(file.as_raw_fd(), Some(2), 0), Ok(5));
+        assert_eq!(std::fs::read(&path).unwrap(), b"\0\0world");
+
+        let file = std::fs::File::open(&path).unwrap();
+        let mut bufv = FuseBufvec::from_fd(file.as_raw_fd(), Some(2), 10);
+        let chunk = bufv.chunks().next().unwrap();
+        let fd = file.as_raw_fd();
+        let expected = FuseBufChunk::Fd {
+            fd,
+            pos: Some(2),
+            size: 10,
+        };
+        assert_eq!(chunk, expected);
+        assert_eq!(bufv.copy_to_vec().unwrap(), b"world");
+        std::fs::remove_file(path).unwrap();
+    }
     #[test]
     fn fuse_attr_new() {
         let sb = unsafe {
@@ -636,4 +1176,29 @@ mod tests {
         assert_eq!(sb.st_ctime, sc.st_ctime);
         assert_eq!(sb.st_ctime_nsec, sc.st_ctime_nsec);
     }
+
+    #[test]
+    fn file_info_bits_are_independent() {
+        let mut fi = FuseFileInfo::new();
+        fi.set_direct_io(true);
+        fi.set_keep_cache(true);
+        assert_eq!(fi.bits, FI_DIRECT_IO | FI_KEEP_CACHE);
+        assert!(fi.direct_io() && fi.keep_cache());
+        assert!(!fi.writepage() && !fi.flush() && !fi.nonseekable());
+        fi.set_direct_io(false);
+        assert_eq!(fi.bits, FI_KEEP_CACHE);
+        assert_eq!(fi.padding2, 0);
+    }
+
+    #[test]
+    fn file_info_flags() {
+        let mut fi = FuseFileInfo::new();
+        fi.set_flags(OFlag::RDWR | OFlag::APPEND | OFlag::NOFOLLOW);
+        assert_eq!(fi.flags().access_mode(), OFlag::RDWR);
+        assert!(fi.flags().contains(OFlag::APPEND));
+        assert!(!fi.flags().contains(OFlag::TRUNC));
+        let mut flags = fi.flags();
+        flags.remove(OFlag::APPEND);
+        assert_eq!(flags, OFlag::RDWR | OFlag::NOFOLLOW);
+    }
 }
@@ -0,0 +1,57 @@
+//! Layout checks of the structs shared with libfuse.
+//!
+//! The sizes below hold for every libfuse 3 release on 64-bit targets. When the
+//! headers are installed, `build.rs` also generates `LAYOUT`, comparing every field
+//! offset, bitfield and flag value with what the C compiler sees.
+
+#[allow(unused_imports)]
+use std::mem::{offset_of, size_of};
+
+#[allow(unused_imports)]
+use super::*;
+#[allow(unused_imports)]
+use crate::{FuseCapFlag, FuseIoctlFlag};
+
+#[cfg(target_pointer_width = "64")]
+const _: () = {
+    assert!(size_of::<FuseArgs>() == 24);
+    assert!(size_of::<FuseConnInfo>() == 128);
+    assert!(size_of::<FuseLoopConfig>() == 8);
+    assert!(size_of::<FuseBuf>() == 40);
+    assert!(size_of::<FuseBufvec>() == 64);
+    assert!(size_of::<FuseFileInfo>() == 40);
+    assert!(offset_of!(FuseFileInfo, fh) == 16);
+    assert!(size_of::<FuseForgetData>() == 16);
+    assert!(size_of::<FuseCtx>() == 16);
+    assert!(size_of::<FuseLowLevelOps>() == 44 * 8);
+};
+
+#[cfg(fuse_abi)]
+include!(concat!(env!("OUT_DIR"), "/fuse_abi.rs"));
+
+// Left to `layout_matches_headers` in tests, which lists every mismatch.
+#[cfg(all(fuse_abi, not(test)))]
+const _: () = {
+    let mut i = 0;
+    while i < LAYOUT.len() {
+        if LAYOUT[i].1 != LAYOUT[i].2 {
+            panic!("the layout differs from the libfuse headers, run `cargo test --lib abi` for details");
+        }
+        i += 1;
+    }
+};
+
+#[cfg(all(test, fuse_abi))]
+mod tests {
+    use super::LAYOUT;
+
+    #[test]
+    fn layout_matches_headers() {
+        let mismatches: Vec<String> = LAYOUT
+            .iter()
+            .filter(|(_, rust, c)| rust != c)
+            .map(|(name, rust, c)| format!("{}: {} in Rust, {} in C", name, rust, c))
+            .collect();
+        assert!(mismatches.is_empty(), "{:#?}", mismatches);
+    }
+}
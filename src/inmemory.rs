@@ -0,0 +1,1293 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use libc::{
+    FALLOC_FL_KEEP_SIZE, FALLOC_FL_PUNCH_HOLE, F_OK, RENAME_EXCHANGE, RENAME_NOREPLACE, R_OK,
+    SEEK_DATA, SEEK_HOLE, S_IFDIR, S_IFLNK, S_IFMT, S_IFREG, S_ISGID, W_OK, XATTR_CREATE,
+    XATTR_REPLACE, X_OK,
+};
+
+use crate::errno::Errno;
+use crate::fuse::{
+    FuseAttr, FuseBufvec, FuseEntryParam, FuseFileInfo, FuseForgetData, FuseStatvfs,
+};
+use crate::reply::{DirectoryPlusReply, DirectoryReply, ReadReply};
+use crate::request::Request;
+use crate::setattr::{SetAttrRequest, TimeOrNow};
+use crate::FileSystem;
+
+const ROOT_INO: u64 = 1;
+const BLOCK_SIZE: u64 = 4096;
+const NAME_MAX: usize = 255;
+const XATTR_NAME_MAX: usize = 255;
+const XATTR_SIZE_MAX: usize = 65536;
+// Files don't grow past this, so a write at a huge offset fails with `EFBIG`
+// instead of aborting the process when the data is allocated.
+const MAX_FILE_SIZE: u64 = 1 << 32;
+
+enum Content {
+    File(Vec<u8>),
+    Dir {
+        // The inode of `..`, the root is its own parent.
+        parent: u64,
+        entries: Entries,
+    },
+    Symlink(Vec<u8>),
+    // Devices, FIFOs and sockets only have attributes.
+    Special,
+}
+
+/// The entries of a directory with the offsets `readdir` hands out for them.
+///
+/// An entry keeps its offset until it is removed and new entries get higher ones,
+/// so a listing continued from an offset neither skips nor repeats the entries
+/// that were there all along.
+struct Entries {
+    // The inode and the offset of each name.
+    by_name: BTreeMap<Vec<u8>, (u64, i64)>,
+    by_offset: BTreeMap<i64, Vec<u8>>,
+    next_offset: i64,
+}
+
+impl Entries {
+    // `.` and `..` are listed first, at the offsets 1 and 2.
+    const FIRST_OFFSET: i64 = 3;
+
+    fn new() -> Self {
+        Self {
+            by_name: BTreeMap::new(),
+            by_offset: BTreeMap::new(),
+            next_offset: Self::FIRST_OFFSET,
+        }
+    }
+
+    fn get(&self, name: &[u8]) -> Option<&u64> {
+        self.by_name.get(name).map(|(ino, _)| ino)
+    }
+
+    fn contains_key(&self, name: &[u8]) -> bool {
+        self.by_name.contains_key(name)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    // Replacing the inode of a name keeps its offset.
+    fn insert(&mut self, name: Vec<u8>, ino: u64) {
+        if let Some(entry) = self.by_name.get_mut(&name) {
+            entry.0 = ino;
+            return;
+        }
+        let offset = self.next_offset;
+        self.next_offset += 1;
+        self.by_offset.insert(offset, name.clone());
+        self.by_name.insert(name, (ino, offset));
+    }
+
+    fn remove(&mut self, name: &[u8]) -> Option<u64> {
+        let (ino, offset) = self.by_name.remove(name)?;
+        self.by_offset.remove(&offset);
+        Some(ino)
+    }
+
+    /// The offset, name and inode of the entries after `off`.
+    fn after(&self, off: i64) -> impl Iterator<Item = (i64, &[u8], u64)> {
+        self.by_offset
+            .range(off.max(Self::FIRST_OFFSET - 1) + 1..)
+            .map(move |(&offset, name)| (offset, name.as_slice(), self.by_name[name].0))
+    }
+}
+
+struct Node {
+    content: Content,
+    // Including the file type bits.
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    rdev: u64,
+    nlink: u32,
+    atime: SystemTime,
+    mtime: SystemTime,
+    ctime: SystemTime,
+    xattrs: BTreeMap<Vec<u8>, Vec<u8>>,
+    // The node is freed once it has no links, the kernel forgot it and no file is open.
+    nlookup: u64,
+    open: u64,
+}
+
+impl Node {
+    fn new(content: Content, mode: u32, uid: u32, gid: u32) -> Self {
+        let now = SystemTime::now();
+        Self {
+            nlink: if let Content::Dir { .. } = content {
+                2
+            } else {
+                1
+            },
+            content,
+            mode,
+            uid,
+            gid,
+            rdev: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            xattrs: BTreeMap::new(),
+            nlookup: 0,
+            open: 0,
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        matches!(self.content, Content::Dir { .. })
+    }
+
+    fn size(&self) -> u64 {
+        match &self.content {
+            Content::File(data) => data.len() as u64,
+            Content::Symlink(target) => target.len() as u64,
+            Content::Dir { .. } => BLOCK_SIZE,
+            Content::Special => 0,
+        }
+    }
+
+    fn attr(&self, ino: u64) -> FuseAttr {
+        let (atime, atimensec) = timestamp(self.atime);
+        let (mtime, mtimensec) = timestamp(self.mtime);
+        let (ctime, ctimensec) = timestamp(self.ctime);
+        let size = self.size();
+        FuseAttr {
+            dev: 0,
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime,
+            atimensec,
+            mtime,
+            mtimensec,
+            ctime,
+            ctimensec,
+            mode: self.mode,
+            nlink: self.nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: self.rdev as u32,
+            blksize: BLOCK_SIZE as u32,
+        }
+    }
+
+    fn data_mut(&mut self) -> Result<&mut Vec<u8>, Errno> {
+        match &mut self.content {
+            Content::File(data) => Ok(data),
+            Content::Dir { .. } => Err(Errno::EISDIR),
+            _ => Err(Errno::EINVAL),
+        }
+    }
+
+    fn entries(&self) -> Result<&Entries, Errno> {
+        match &self.content {
+            Content::Dir { entries, .. } => Ok(entries),
+            _ => Err(Errno::ENOTDIR),
+        }
+    }
+
+    fn entries_mut(&mut self) -> Result<&mut Entries, Errno> {
+        match &mut self.content {
+            Content::Dir { entries, .. } => Ok(entries),
+            _ => Err(Errno::ENOTDIR),
+        }
+    }
+
+    fn touch(&mut self) {
+        let now = SystemTime::now();
+        self.mtime = now;
+        self.ctime = now;
+    }
+}
+
+// Times before the epoch can't be represented in `FuseAttr` and become the epoch.
+fn timestamp(time: SystemTime) -> (u64, u32) {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| (d.as_secs(), d.subsec_nanos()))
+        .unwrap_or((0, 0))
+}
+
+// Where `len` bytes at `off` end, `EFBIG` past the largest file.
+fn file_end(off: i64, len: u64) -> Result<usize, Errno> {
+    match (off as u64).checked_add(len) {
+        Some(end) if end <= MAX_FILE_SIZE => Ok(end as usize),
+        _ => Err(Errno::EFBIG),
+    }
+}
+
+fn resolve(time: TimeOrNow) -> SystemTime {
+    match time {
+        TimeOrNow::SpecificTime(time) => time,
+        TimeOrNow::Now => SystemTime::now(),
+    }
+}
+
+/// A filesystem that keeps everything in memory, with the semantics of a local
+/// POSIX filesystem: hard links, symlinks, devices, xattrs, `rename(2)` flags and
+/// files that stay readable after being unlinked while open.
+///
+/// Permissions are only checked in `access`, so mount with `default_permissions`
+/// to have the kernel enforce them. POSIX and `flock(2)` locks are left to the kernel.
+pub struct InMemoryFs {
+    nodes: HashMap<u64, Node>,
+    next_ino: u64,
+    timeout: f64,
+}
+
+impl Default for InMemoryFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryFs {
+    /// An empty filesystem whose root belongs to the user running the process.
+    pub fn new() -> Self {
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let root = Content::Dir {
+            parent: ROOT_INO,
+            entries: Entries::new(),
+        };
+        let mut root = Node::new(root, S_IFDIR | 0o755, uid, gid);
+        // Never forgotten.
+        root.nlookup = 1;
+        Self {
+            nodes: vec![(ROOT_INO, root)].into_iter().collect(),
+            next_ino: ROOT_INO + 1,
+            timeout: 1.0,
+        }
+    }
+
+    /// How long the kernel may cache attributes and lookups, one second by default.
+    pub fn timeout(mut self, timeout: f64) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn node(&self, ino: u64) -> Result<&Node, Errno> {
+        self.nodes.get(&ino).ok_or(Errno::ENOENT)
+    }
+
+    fn node_mut(&mut self, ino: u64) -> Result<&mut Node, Errno> {
+        self.nodes.get_mut(&ino).ok_or(Errno::ENOENT)
+    }
+
+    fn child(&self, parent: u64, name: &[u8]) -> Result<u64, Errno> {
+        self.node(parent)?
+            .entries()?
+            .get(name)
+            .copied()
+            .ok_or(Errno::ENOENT)
+    }
+
+    // Answers a request that creates an entry, which counts as a lookup.
+    fn entry(&mut self, ino: u64) -> Result<FuseEntryParam, Errno> {
+        let timeout = self.timeout;
+        let node = self.node_mut(ino)?;
+        node.nlookup += 1;
+        Ok(FuseEntryParam::new(node.attr(ino), 0, timeout, timeout))
+    }
+
+    fn insert(
+        &mut self,
+        ctx: &Request,
+        parent: u64,
+        name: &[u8],
+        mode: u32,
+        content: Content,
+    ) -> Result<u64, Errno> {
+        if name.len() > NAME_MAX {
+            return Err(Errno::ENAMETOOLONG);
+        }
+        let dir = self.node(parent)?;
+        if dir.entries()?.contains_key(name) {
+            return Err(Errno::EEXIST);
+        }
+        // A set-group-ID directory hands its group down, and its flag to subdirectories.
+        let (gid, mode) = if dir.mode & S_ISGID != 0 {
+            let inherit = if mode & S_IFMT == S_IFDIR { S_ISGID } else { 0 };
+            (dir.gid, mode | inherit)
+        } else {
+            (ctx.gid, mode)
+        };
+        let is_dir = matches!(content, Content::Dir { .. });
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        self.nodes
+            .insert(ino, Node::new(content, mode, ctx.uid, gid));
+        let dir = self.node_mut(parent)?;
+        dir.entries_mut()?.insert(name.to_vec(), ino);
+        if is_dir {
+            dir.nlink += 1;
+        }
+        dir.touch();
+        Ok(ino)
+    }
+
+    fn free_if_unused(&mut self, ino: u64) {
+        if let Some(node) = self.nodes.get(&ino) {
+            if ino != ROOT_INO && node.nlink == 0 && node.nlookup == 0 && node.open == 0 {
+                self.nodes.remove(&ino);
+            }
+        }
+    }
+
+    // Drops the link from an entry that was removed, a directory loses `.` as well.
+    fn unlinked(&mut self, ino: u64) {
+        if let Some(node) = self.nodes.get_mut(&ino) {
+            node.nlink = if node.is_dir() {
+                0
+            } else {
+                node.nlink.saturating_sub(1)
+            };
+            node.ctime = SystemTime::now();
+        }
+        self.free_if_unused(ino);
+    }
+
+    // Moves the `..` of a directory, with the link counts of both parents.
+    fn reparent(&mut self, ino: u64, from: u64, to: u64) -> Result<(), Errno> {
+        if from == to {
+            return Ok(());
+        }
+        if let Content::Dir { parent, .. } = &mut self.node_mut(ino)?.content {
+            *parent = to;
+            self.node_mut(from)?.nlink -= 1;
+            self.node_mut(to)?.nlink += 1;
+        }
+        Ok(())
+    }
+
+    // Whether `ino` is `dir` or one of its subdirectories.
+    fn is_within(&self, mut ino: u64, dir: u64) -> Result<bool, Errno> {
+        loop {
+            if ino == dir {
+                return Ok(true);
+            }
+            match self.node(ino)?.content {
+                Content::Dir { parent, .. } if ino != ROOT_INO => ino = parent,
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    fn remove_entry(&mut self, parent: u64, name: &[u8], dir: bool) -> Result<(), Errno> {
+        let ino = self.child(parent, name)?;
+        let node = self.node(ino)?;
+        match (dir, node.is_dir()) {
+            (false, true) => return Err(Errno::EISDIR),
+            (true, false) => return Err(Errno::ENOTDIR),
+            (true, true) if !node.entries()?.is_empty() => return Err(Errno::ENOTEMPTY),
+            _ => {}
+        }
+        let parent_node = self.node_mut(parent)?;
+        parent_node.entries_mut()?.remove(name);
+        if dir {
+            parent_node.nlink -= 1;
+        }
+        parent_node.touch();
+        self.unlinked(ino);
+        Ok(())
+    }
+
+    fn exchange(
+        &mut self,
+        parent: u64,
+        name: &[u8],
+        newparent: u64,
+        newname: &[u8],
+    ) -> Result<(), Errno> {
+        let (ino, other) = (self.child(parent, name)?, self.child(newparent, newname)?);
+        if (self.node(ino)?.is_dir() && self.is_within(newparent, ino)?)
+            || (self.node(other)?.is_dir() && self.is_within(parent, other)?)
+        {
+            return Err(Errno::EINVAL);
+        }
+        self.node_mut(parent)?
+            .entries_mut()?
+            .insert(name.to_vec(), other);
+        self.node_mut(newparent)?
+            .entries_mut()?
+            .insert(newname.to_vec(), ino);
+        self.reparent(ino, parent, newparent)?;
+        self.reparent(other, newparent, parent)?;
+        for ino in &[parent, newparent] {
+            self.node_mut(*ino)?.touch();
+        }
+        for ino in &[ino, other] {
+            self.node_mut(*ino)?.ctime = SystemTime::now();
+        }
+        Ok(())
+    }
+
+    fn file(&self, ino: u64) -> Result<&[u8], Errno> {
+        match &self.node(ino)?.content {
+            Content::File(data) => Ok(data),
+            Content::Dir { .. } => Err(Errno::EISDIR),
+            _ => Err(Errno::EINVAL),
+        }
+    }
+
+    fn write_at(&mut self, ino: u64, off: i64, buf: &[u8]) -> Result<usize, Errno> {
+        if off < 0 {
+            return Err(Errno::EINVAL);
+        }
+        let node = self.node_mut(ino)?;
+        let data = node.data_mut()?;
+        let (start, end) = (off as usize, file_end(off, buf.len() as u64)?);
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[start..end].copy_from_slice(buf);
+        node.touch();
+        Ok(buf.len())
+    }
+}
+
+register_ops! {
+    impl FileSystem for InMemoryFs {
+        fn lookup(
+            &mut self,
+            _ctx: &Request,
+            parent: u64,
+            name: &[u8],
+        ) -> Result<FuseEntryParam, Errno> {
+            let ino = self.child(parent, name)?;
+            self.entry(ino)
+        }
+        fn forget(&mut self, _ctx: &Request, forget: FuseForgetData) {
+            if let Some(node) = self.nodes.get_mut(&forget.ino) {
+                node.nlookup = node.nlookup.saturating_sub(forget.nlookup);
+            }
+            self.free_if_unused(forget.ino);
+        }
+        fn forget_multi(&mut self, ctx: &Request, forgets: Vec<FuseForgetData>) {
+            for forget in forgets {
+                self.forget(ctx, forget);
+            }
+        }
+        fn getattr(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            _fi: Option<&mut FuseFileInfo>,
+        ) -> Result<(FuseAttr, f64), Errno> {
+            Ok((self.node(ino)?.attr(ino), self.timeout))
+        }
+        fn setattr(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            attr: &SetAttrRequest,
+            _fi: Option<&mut FuseFileInfo>,
+        ) -> Result<(FuseAttr, f64), Errno> {
+            let node = self.node_mut(ino)?;
+            if let Some(size) = attr.size {
+                let size = file_end(0, size)?;
+                node.data_mut()?.resize(size, 0);
+                node.mtime = SystemTime::now();
+            }
+            if let Some(mode) = attr.mode {
+                node.mode = (node.mode & S_IFMT) | (mode & 0o7777);
+            }
+            if let Some(uid) = attr.uid {
+                node.uid = uid;
+            }
+            if let Some(gid) = attr.gid {
+                node.gid = gid;
+            }
+            if let Some(atime) = attr.atime {
+                node.atime = resolve(atime);
+            }
+            if let Some(mtime) = attr.mtime {
+                node.mtime = resolve(mtime);
+            }
+            node.ctime = attr.ctime.unwrap_or_else(SystemTime::now);
+            Ok((node.attr(ino), self.timeout))
+        }
+        fn readlink(&mut self, _ctx: &Request, ino: u64) -> Result<Vec<u8>, Errno> {
+            match &self.node(ino)?.content {
+                Content::Symlink(target) => Ok(target.clone()),
+                _ => Err(Errno::EINVAL),
+            }
+        }
+        fn mknod(
+            &mut self,
+            ctx: &Request,
+            parent: u64,
+            name: &[u8],
+            mode: u32,
+            rdev: u64,
+        ) -> Result<FuseEntryParam, Errno> {
+            let content = match mode & S_IFMT {
+                S_IFREG | 0 => Content::File(Vec::new()),
+                S_IFDIR | S_IFLNK => return Err(Errno::EINVAL),
+                _ => Content::Special,
+            };
+            let mode = if mode & S_IFMT == 0 {
+                mode | S_IFREG
+            } else {
+                mode
+            };
+            let ino = self.insert(ctx, parent, name, mode, content)?;
+            self.node_mut(ino)?.rdev = rdev;
+            self.entry(ino)
+        }
+        fn mkdir(
+            &mut self,
+            ctx: &Request,
+            parent: u64,
+            name: &[u8],
+            mode: u32,
+        ) -> Result<FuseEntryParam, Errno> {
+            let content = Content::Dir {
+                parent,
+                entries: Entries::new(),
+            };
+            let mode = S_IFDIR | (mode & 0o7777);
+            let ino = self.insert(ctx, parent, name, mode, content)?;
+            self.entry(ino)
+        }
+        fn unlink(&mut self, _ctx: &Request, parent: u64, name: &[u8]) -> Result<(), Errno> {
+            self.remove_entry(parent, name, false)
+        }
+        fn rmdir(&mut self, _ctx: &Request, parent: u64, name: &[u8]) -> Result<(), Errno> {
+            self.remove_entry(parent, name, true)
+        }
+        fn symlink(
+            &mut self,
+            ctx: &Request,
+            link: &[u8],
+            parent: u64,
+            name: &[u8],
+        ) -> Result<FuseEntryParam, Errno> {
+            let content = Content::Symlink(link.to_vec());
+            let ino = self.insert(ctx, parent, name, S_IFLNK | 0o777, content)?;
+            self.entry(ino)
+        }
+        fn rename(
+            &mut self,
+            _ctx: &Request,
+            parent: u64,
+            name: &[u8],
+            newparent: u64,
+            newname: &[u8],
+            flags: u32,
+        ) -> Result<(), Errno> {
+            let (noreplace, exchange) = (RENAME_NOREPLACE as u32, RENAME_EXCHANGE as u32);
+            // `RENAME_WHITEOUT` is only meaningful to overlay filesystems.
+            if flags & !(noreplace | exchange) != 0 || flags == noreplace | exchange {
+                return Err(Errno::EINVAL);
+            }
+            if newname.len() > NAME_MAX {
+                return Err(Errno::ENAMETOOLONG);
+            }
+            if flags & exchange != 0 {
+                return self.exchange(parent, name, newparent, newname);
+            }
+            let ino = self.child(parent, name)?;
+            let node = self.node(ino)?;
+            let is_dir = node.is_dir();
+            if is_dir && self.is_within(newparent, ino)? {
+                return Err(Errno::EINVAL);
+            }
+            if let Some(&target) = self.node(newparent)?.entries()?.get(newname) {
+                if target == ino {
+                    return Ok(());
+                }
+                if flags & noreplace != 0 {
+                    return Err(Errno::EEXIST);
+                }
+                let target_node = self.node(target)?;
+                match (is_dir, target_node.is_dir()) {
+                    (true, false) => return Err(Errno::ENOTDIR),
+                    (false, true) => return Err(Errno::EISDIR),
+                    (true, true) if !target_node.entries()?.is_empty() => {
+                        return Err(Errno::ENOTEMPTY)
+                    }
+                    _ => {}
+                }
+                if is_dir {
+                    self.node_mut(newparent)?.nlink -= 1;
+                }
+                self.node_mut(newparent)?.entries_mut()?.remove(newname);
+                self.unlinked(target);
+            }
+            self.node_mut(parent)?.entries_mut()?.remove(name);
+            self.node_mut(newparent)?
+                .entries_mut()?
+                .insert(newname.to_vec(), ino);
+            self.reparent(ino, parent, newparent)?;
+            self.node_mut(parent)?.touch();
+            self.node_mut(newparent)?.touch();
+            self.node_mut(ino)?.ctime = SystemTime::now();
+            Ok(())
+        }
+        fn link(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            newparent: u64,
+            newname: &[u8],
+        ) -> Result<FuseEntryParam, Errno> {
+            if self.node(ino)?.is_dir() {
+                return Err(Errno::EPERM);
+            }
+            if newname.len() > NAME_MAX {
+                return Err(Errno::ENAMETOOLONG);
+            }
+            let dir = self.node_mut(newparent)?;
+            if dir.entries()?.contains_key(newname) {
+                return Err(Errno::EEXIST);
+            }
+            dir.entries_mut()?.insert(newname.to_vec(), ino);
+            dir.touch();
+            let node = self.node_mut(ino)?;
+            node.nlink += 1;
+            node.ctime = SystemTime::now();
+            self.entry(ino)
+        }
+        fn open(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            fi: FuseFileInfo,
+        ) -> Result<FuseFileInfo, Errno> {
+            self.node_mut(ino)?.open += 1;
+            Ok(fi)
+        }
+        fn read(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            size: usize,
+            off: i64,
+            _fi: &mut FuseFileInfo,
+        ) -> Result<ReadReply<'_>, Errno> {
+            if off < 0 {
+                return Err(Errno::EINVAL);
+            }
+            self.file(ino)?;
+            self.node_mut(ino)?.atime = SystemTime::now();
+            let data = self.file(ino)?;
+            let start = (off as usize).min(data.len());
+            let end = start.saturating_add(size).min(data.len());
+            Ok(ReadReply::Slice(&data[start..end]))
+        }
+        fn write(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            buf: &[u8],
+            _size: usize,
+            off: i64,
+            _fi: &mut FuseFileInfo,
+        ) -> Result<usize, Errno> {
+            self.write_at(ino, off, buf)
+        }
+        fn write_buf(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            bufv: &mut FuseBufvec,
+            off: i64,
+            _fi: &FuseFileInfo,
+        ) -> Result<usize, Errno> {
+            if off < 0 {
+                return Err(Errno::EINVAL);
+            }
+            let node = self.node_mut(ino)?;
+            let data = node.data_mut()?;
+            let (start, end) = (off as usize, file_end(off, bufv.size() as u64)?);
+            let len = data.len();
+            if len < end {
+                data.resize(end, 0);
+            }
+            // Straight into the file, also from the pipe of a spliced request.
+            let res = bufv.copy_to_slice(&mut data[start..end]);
+            let written = *res.as_ref().unwrap_or(&0);
+            data.truncate(len.max(start + written));
+            if written > 0 {
+                node.touch();
+            }
+            res
+        }
+        fn flush(
+            &mut self,
+            _ctx: &Request,
+            _ino: u64,
+            _fi: &mut FuseFileInfo,
+        ) -> Result<(), Errno> {
+            Ok(())
+        }
+        fn release(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            _fi: &mut FuseFileInfo,
+        ) -> Result<(), Errno> {
+            let node = self.node_mut(ino)?;
+            node.open = node.open.saturating_sub(1);
+            self.free_if_unused(ino);
+            Ok(())
+        }
+        fn fsync(
+            &mut self,
+            _ctx: &Request,
+            _ino: u64,
+            _datasync: i32,
+            _fi: &mut FuseFileInfo,
+        ) -> Result<(), Errno> {
+            Ok(())
+        }
+        fn opendir(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            _fi: &mut FuseFileInfo,
+        ) -> Result<FuseFileInfo, Errno> {
+            self.node(ino)?.entries()?;
+            Ok(FuseFileInfo::new())
+        }
+        fn readdir(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            off: i64,
+            _fi: &mut FuseFileInfo,
+            reply: &mut DirectoryReply,
+        ) -> Result<(), Errno> {
+            let node = self.node(ino)?;
+            let parent = match node.content {
+                Content::Dir { parent, .. } => parent,
+                _ => return Err(Errno::ENOTDIR),
+            };
+            let dots: [(&[u8], u64); 2] = [(b".", ino), (b"..", parent)];
+            for (offset, &(name, ino)) in (1..).zip(&dots).skip(off.max(0) as usize) {
+                if reply.add(name, offset, &self.node(ino)?.attr(ino)) {
+                    return Ok(());
+                }
+            }
+            for (offset, name, child) in node.entries()?.after(off) {
+                if reply.add(name, offset, &self.node(child)?.attr(child)) {
+                    break;
+                }
+            }
+            Ok(())
+        }
+        fn readdirplus(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            off: i64,
+            _fi: &mut FuseFileInfo,
+            reply: &mut DirectoryPlusReply,
+        ) -> Result<(), Errno> {
+            let timeout = self.timeout;
+            let param = |node: &Node, ino| FuseEntryParam::new(node.attr(ino), 0, timeout, timeout);
+            let node = self.node(ino)?;
+            let parent = match node.content {
+                Content::Dir { parent, .. } => parent,
+                _ => return Err(Errno::ENOTDIR),
+            };
+            let dots: [(&[u8], u64); 2] = [(b".", ino), (b"..", parent)];
+            for (offset, &(name, ino)) in (1..).zip(&dots).skip(off.max(0) as usize) {
+                if reply.add(name, &param(self.node(ino)?, ino), offset) {
+                    return Ok(());
+                }
+            }
+            // The entries that made it into the reply count as lookups.
+            let mut added = Vec::new();
+            for (offset, name, child) in node.entries()?.after(off) {
+                if reply.add(name, &param(self.node(child)?, child), offset) {
+                    break;
+                }
+                added.push(child);
+            }
+            for child in added {
+                self.node_mut(child)?.nlookup += 1;
+            }
+            Ok(())
+        }
+        fn releasedir(
+            &mut self,
+            _ctx: &Request,
+            _ino: u64,
+            _fi: &mut FuseFileInfo,
+        ) -> Result<(), Errno> {
+            Ok(())
+        }
+        fn fsyncdir(
+            &mut self,
+            _ctx: &Request,
+            _ino: u64,
+            _datasync: i32,
+            _fi: &mut FuseFileInfo,
+        ) -> Result<(), Errno> {
+            Ok(())
+        }
+        fn statfs(&mut self, _ctx: &Request, _ino: u64) -> Result<FuseStatvfs, Errno> {
+            let used: u64 = self
+                .nodes
+                .values()
+                .map(|node| node.size().div_ceil(BLOCK_SIZE))
+                .sum();
+            // Bounded by the memory of the machine, like tmpfs.
+            let mut info = unsafe { std::mem::zeroed::<libc::sysinfo>() };
+            let free = if unsafe { libc::sysinfo(&mut info) } == 0 {
+                info.freeram as u64 * info.mem_unit as u64 / BLOCK_SIZE
+            } else {
+                0
+            };
+            let files = self.nodes.len() as u64;
+            Ok(FuseStatvfs {
+                bsize: BLOCK_SIZE,
+                frsize: BLOCK_SIZE,
+                blocks: used + free,
+                bfree: free,
+                bavail: free,
+                files: u32::MAX as u64,
+                ffree: u32::MAX as u64 - files,
+                favail: u32::MAX as u64 - files,
+                fsid: 0,
+                flag: 0,
+                namemax: NAME_MAX as u64,
+            })
+        }
+        fn setxattr(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            name: &[u8],
+            value: &[u8],
+            _size: usize,
+            flags: i32,
+        ) -> Result<(), Errno> {
+            if name.is_empty() || name.len() > XATTR_NAME_MAX {
+                return Err(Errno::ERANGE);
+            }
+            if value.len() > XATTR_SIZE_MAX {
+                return Err(Errno::E2BIG);
+            }
+            let node = self.node_mut(ino)?;
+            let exists = node.xattrs.contains_key(name);
+            if flags & XATTR_CREATE != 0 && exists {
+                return Err(Errno::EEXIST);
+            }
+            if flags & XATTR_REPLACE != 0 && !exists {
+                return Err(Errno::ENODATA);
+            }
+            node.xattrs.insert(name.to_vec(), value.to_vec());
+            node.ctime = SystemTime::now();
+            Ok(())
+        }
+        fn getxattr(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            name: &[u8],
+            _size: usize,
+        ) -> Result<Vec<u8>, Errno> {
+            self.node(ino)?
+                .xattrs
+                .get(name)
+                .cloned()
+                .ok_or(Errno::ENODATA)
+        }
+        fn listxattr(&mut self, _ctx: &Request, ino: u64, _size: usize) -> Result<Vec<u8>, Errno> {
+            let mut names = Vec::new();
+            for name in self.node(ino)?.xattrs.keys() {
+                names.extend_from_slice(name);
+                names.push(0);
+            }
+            Ok(names)
+        }
+        fn removexattr(&mut self, _ctx: &Request, ino: u64, name: &[u8]) -> Result<(), Errno> {
+            let node = self.node_mut(ino)?;
+            node.xattrs.remove(name).ok_or(Errno::ENODATA)?;
+            node.ctime = SystemTime::now();
+            Ok(())
+        }
+        fn access(&mut self, ctx: &Request, ino: u64, mask: i32) -> Result<(), Errno> {
+            let node = self.node(ino)?;
+            if mask == F_OK {
+                return Ok(());
+            }
+            let granted = if ctx.uid == 0 {
+                // Root may execute only what someone may execute.
+                let exec = node.is_dir() || node.mode & 0o111 != 0;
+                R_OK | W_OK | if exec { X_OK } else { 0 }
+            } else if ctx.uid == node.uid {
+                (node.mode >> 6) as i32 & 0o7
+            } else if ctx.gid == node.gid
+                || matches!(ctx.groups(), Ok(groups) if groups.contains(&node.gid))
+            {
+                (node.mode >> 3) as i32 & 0o7
+            } else {
+                node.mode as i32 & 0o7
+            };
+            if mask & !granted == 0 {
+                Ok(())
+            } else {
+                Err(Errno::EACCES)
+            }
+        }
+        fn create(
+            &mut self,
+            ctx: &Request,
+            parent: u64,
+            name: &[u8],
+            mode: u32,
+            _fi: &mut FuseFileInfo,
+        ) -> Result<FuseEntryParam, Errno> {
+            let mode = S_IFREG | (mode & 0o7777);
+            let ino = self.insert(ctx, parent, name, mode, Content::File(Vec::new()))?;
+            self.node_mut(ino)?.open += 1;
+            self.entry(ino)
+        }
+        fn fallocate(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            mode: i32,
+            offset: i64,
+            length: i64,
+            _fi: &mut FuseFileInfo,
+        ) -> Result<(), Errno> {
+            if offset < 0 || length <= 0 {
+                return Err(Errno::EINVAL);
+            }
+            let end = offset.checked_add(length).ok_or(Errno::EFBIG)? as usize;
+            let node = self.node_mut(ino)?;
+            let data = node.data_mut()?;
+            match mode {
+                0 if data.len() < end => data.resize(file_end(offset, length as u64)?, 0),
+                0 | FALLOC_FL_KEEP_SIZE => return Ok(()),
+                m if m == FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE => {
+                    let start = (offset as usize).min(data.len());
+                    let end = end.min(data.len());
+                    data[start..end].iter_mut().for_each(|b| *b = 0);
+                }
+                _ => return Err(Errno::EOPNOTSUPP),
+            }
+            node.touch();
+            Ok(())
+        }
+        fn copy_file_range(
+            &mut self,
+            _ctx: &Request,
+            ino_in: u64,
+            off_in: i64,
+            _fi_in: &mut FuseFileInfo,
+            ino_out: u64,
+            off_out: i64,
+            _fi_out: &mut FuseFileInfo,
+            len: usize,
+            flags: i32,
+        ) -> Result<usize, Errno> {
+            if flags != 0 || off_in < 0 {
+                return Err(Errno::EINVAL);
+            }
+            let data = self.file(ino_in)?;
+            let start = (off_in as usize).min(data.len());
+            let end = start.saturating_add(len).min(data.len());
+            let data = data[start..end].to_vec();
+            self.write_at(ino_out, off_out, &data)
+        }
+        fn lseek(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            off: i64,
+            whence: i32,
+            _fi: &mut FuseFileInfo,
+        ) -> Result<i64, Errno> {
+            // Files have no holes, everything up to the size is data.
+            let size = self.file(ino)?.len() as i64;
+            if off < 0 || off >= size {
+                return Err(Errno::ENXIO);
+            }
+            match whence {
+                SEEK_DATA => Ok(off),
+                SEEK_HOLE => Ok(size),
+                _ => Err(Errno::EINVAL),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::inmemory::InMemoryFs;
+    use crate::testing::MockSession;
+    use crate::{Errno, FileSystem, FuseCtx, FuseFileInfo, Request, SetAttrRequest};
+
+    const CTX: FuseCtx = FuseCtx {
+        uid: 1000,
+        gid: 1000,
+        pid: 0,
+        umask: 0o022,
+    };
+
+    fn ctx() -> Request {
+        Request::from(CTX)
+    }
+
+    fn create(fs: &mut InMemoryFs, parent: u64, name: &[u8], data: &[u8]) -> u64 {
+        let mut fi = FuseFileInfo::new();
+        let ino = fs.create(&ctx(), parent, name, 0o644, &mut fi).unwrap().ino;
+        fs.write(&ctx(), ino, data, data.len(), 0, &mut fi).unwrap();
+        fs.release(&ctx(), ino, &mut fi).unwrap();
+        ino
+    }
+
+    fn nlink(fs: &mut InMemoryFs, ino: u64) -> u32 {
+        fs.getattr(&ctx(), ino, None).unwrap().0.nlink
+    }
+
+    #[test]
+    fn read_honours_offset_and_size() {
+        let mut fs = InMemoryFs::new();
+        let ino = create(&mut fs, 1, b"f", b"ab\0cdef");
+        let mut fi = FuseFileInfo::new();
+        assert_eq!(
+            fs.read(&ctx(), ino, 3, 1, &mut fi)
+                .unwrap()
+                .to_vec()
+                .unwrap(),
+            b"b\0c"
+        );
+        assert_eq!(
+            fs.read(&ctx(), ino, 100, 5, &mut fi)
+                .unwrap()
+                .to_vec()
+                .unwrap(),
+            b"ef"
+        );
+        assert!(fs
+            .read(&ctx(), ino, 10, 100, &mut fi)
+            .unwrap()
+            .to_vec()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn hard_links_keep_data() {
+        let mut fs = InMemoryFs::new();
+        let ino = create(&mut fs, 1, b"a", b"data");
+        assert_eq!(fs.link(&ctx(), ino, 1, b"b").unwrap().ino, ino);
+        assert_eq!(nlink(&mut fs, ino), 2);
+        fs.unlink(&ctx(), 1, b"a").unwrap();
+        assert_eq!(nlink(&mut fs, ino), 1);
+        assert_eq!(fs.lookup(&ctx(), 1, b"b").unwrap().ino, ino);
+
+        // Unlinked while still known to the kernel: kept until forgotten.
+        fs.unlink(&ctx(), 1, b"b").unwrap();
+        assert_eq!(nlink(&mut fs, ino), 0);
+        // Looked up by create, link and lookup.
+        let nlookup = 3;
+        fs.forget(&ctx(), crate::FuseForgetData { ino, nlookup });
+        assert_eq!(fs.getattr(&ctx(), ino, None).unwrap_err(), Errno::ENOENT);
+    }
+
+    #[test]
+    fn directories_count_links() {
+        let mut fs = InMemoryFs::new();
+        let dir = fs.mkdir(&ctx(), 1, b"d", 0o755).unwrap().ino;
+        assert_eq!((nlink(&mut fs, 1), nlink(&mut fs, dir)), (3, 2));
+        create(&mut fs, dir, b"f", b"");
+        assert_eq!(fs.rmdir(&ctx(), 1, b"d"), Err(Errno::ENOTEMPTY));
+        assert_eq!(fs.unlink(&ctx(), 1, b"d"), Err(Errno::EISDIR));
+        fs.unlink(&ctx(), dir, b"f").unwrap();
+        fs.rmdir(&ctx(), 1, b"d").unwrap();
+        assert_eq!(nlink(&mut fs, 1), 2);
+    }
+
+    #[test]
+    fn rename_flags() {
+        let mut fs = InMemoryFs::new();
+        let a = create(&mut fs, 1, b"a", b"1");
+        let b = create(&mut fs, 1, b"b", b"2");
+        assert_eq!(fs.rename(&ctx(), 1, b"a", 1, b"b", 1), Err(Errno::EEXIST));
+        fs.rename(&ctx(), 1, b"a", 1, b"b", 2).unwrap();
+        assert_eq!(fs.lookup(&ctx(), 1, b"a").unwrap().ino, b);
+        assert_eq!(fs.lookup(&ctx(), 1, b"b").unwrap().ino, a);
+
+        fs.rename(&ctx(), 1, b"a", 1, b"b", 0).unwrap();
+        assert_eq!(fs.lookup(&ctx(), 1, b"b").unwrap().ino, b);
+        assert_eq!(fs.lookup(&ctx(), 1, b"a").err(), Some(Errno::ENOENT));
+
+        let dir = fs.mkdir(&ctx(), 1, b"d", 0o755).unwrap().ino;
+        let sub = fs.mkdir(&ctx(), dir, b"s", 0o755).unwrap().ino;
+        assert_eq!(fs.rename(&ctx(), 1, b"d", sub, b"x", 0), Err(Errno::EINVAL));
+        assert_eq!(fs.rename(&ctx(), 1, b"b", 1, b"d", 0), Err(Errno::EISDIR));
+        fs.rename(&ctx(), dir, b"s", 1, b"s", 0).unwrap();
+        assert_eq!((nlink(&mut fs, 1), nlink(&mut fs, dir)), (4, 2));
+
+        // Flags above the low 16 bits reach the filesystem through the trampoline.
+        let reply = MockSession::new(&mut fs).rename(1, b"b", 1, b"c", 1 << 16);
+        assert_eq!(reply.err(), Some(Errno::EINVAL));
+    }
+
+    #[test]
+    fn truncate_and_mode() {
+        let mut fs = InMemoryFs::new();
+        let ino = create(&mut fs, 1, b"f", b"0123456789");
+        let attr = SetAttrRequest {
+            mode: Some(0o600),
+            size: Some(4),
+            ..SetAttrRequest::default()
+        };
+        let (attr, _) = fs.setattr(&ctx(), ino, &attr, None).unwrap();
+        assert_eq!((attr.size, attr.mode), (4, libc::S_IFREG | 0o600));
+        let mut fi = FuseFileInfo::new();
+        assert_eq!(
+            fs.read(&ctx(), ino, 10, 0, &mut fi)
+                .unwrap()
+                .to_vec()
+                .unwrap(),
+            b"0123"
+        );
+    }
+
+    #[test]
+    fn symlinks_and_xattrs() {
+        let mut fs = InMemoryFs::new();
+        let link = fs.symlink(&ctx(), b"target", 1, b"l").unwrap();
+        assert_eq!(link.attr.st_size, 6);
+        assert_eq!(fs.readlink(&ctx(), link.ino).unwrap(), b"target");
+
+        let ino = create(&mut fs, 1, b"f", b"");
+        fs.setxattr(&ctx(), ino, b"user.a", b"1", 1, 0).unwrap();
+        fs.setxattr(&ctx(), ino, b"user.b", b"2", 1, 0).unwrap();
+        let create = libc::XATTR_CREATE;
+        assert_eq!(
+            fs.setxattr(&ctx(), ino, b"user.a", b"3", 1, create),
+            Err(Errno::EEXIST)
+        );
+        assert_eq!(fs.getxattr(&ctx(), ino, b"user.a", 0).unwrap(), b"1");
+        assert_eq!(fs.listxattr(&ctx(), ino, 0).unwrap(), b"user.a\0user.b\0");
+        fs.removexattr(&ctx(), ino, b"user.a").unwrap();
+        assert_eq!(fs.getxattr(&ctx(), ino, b"user.a", 0), Err(Errno::ENODATA));
+    }
+
+    #[test]
+    fn readdir_continues_after_changes() {
+        let mut fs = InMemoryFs::new();
+        for name in &[b"a", b"b", b"c", b"d"] {
+            create(&mut fs, 1, *name, b"");
+        }
+        let mut fi = FuseFileInfo::new();
+        let mut page = |fs: &mut InMemoryFs, off| {
+            // Room for three entries with one-letter names.
+            let reply = MockSession::new(fs).readdir(1, 96, off, &mut fi);
+            reply.unwrap_dirents()
+        };
+        let first = page(&mut fs, 0);
+        let names: Vec<_> = first.iter().map(|entry| &entry.name[..]).collect();
+        assert_eq!(names, vec![&b"."[..], b"..", b"a"]);
+
+        // Entries removed or added meanwhile don't shift the rest of the listing.
+        fs.unlink(&ctx(), 1, b"a").unwrap();
+        fs.unlink(&ctx(), 1, b"b").unwrap();
+        create(&mut fs, 1, b"e", b"");
+        let rest = page(&mut fs, first[2].off);
+        let names: Vec<_> = rest.iter().map(|entry| &entry.name[..]).collect();
+        assert_eq!(names, vec![&b"c"[..], b"d", b"e"]);
+        assert!(page(&mut fs, rest[2].off).is_empty());
+    }
+
+    #[test]
+    fn readdirplus_counts_lookups() {
+        let mut fs = InMemoryFs::new();
+        let a = create(&mut fs, 1, b"a", b"data");
+        let b = create(&mut fs, 1, b"b", b"");
+        let mut fi = FuseFileInfo::new();
+        // Room for three entries with one-letter names.
+        let reply = MockSession::new(&mut fs).readdirplus(1, 480, 0, &mut fi);
+        let entries = reply.unwrap_direntplus();
+        let names: Vec<_> = entries.iter().map(|e| &e.entry.name[..]).collect();
+        assert_eq!(names, vec![&b"."[..], b"..", b"a"]);
+        assert_eq!((entries[2].nodeid, entries[2].size), (a, 4));
+        // `b` didn't fit, so only `a` was looked up.
+        assert_eq!(fs.nodes[&a].nlookup, 2);
+        assert_eq!(fs.nodes[&b].nlookup, 1);
+    }
+
+    #[test]
+    fn write_buf_extends_the_file() {
+        let mut fs = InMemoryFs::new();
+        let ino = create(&mut fs, 1, b"f", b"abc");
+        let mut session = MockSession::new(&mut fs);
+        let fi = FuseFileInfo::new();
+        assert_eq!(session.write_buf(ino, b"XY", 1, &fi).unwrap_written(), 2);
+        assert_eq!(session.write_buf(ino, b"!", 5, &fi).unwrap_written(), 1);
+        let mut fi = FuseFileInfo::new();
+        assert_eq!(session.read(ino, 10, 0, &mut fi).unwrap_data(), b"aXY\0\0!");
+    }
+
+    #[test]
+    fn huge_sizes_fail_with_efbig() {
+        let mut fs = InMemoryFs::new();
+        let ino = create(&mut fs, 1, b"f", b"abc");
+        let mut fi = FuseFileInfo::new();
+        for off in &[1 << 60, i64::MAX] {
+            assert_eq!(
+                fs.write(&ctx(), ino, b"x", 1, *off, &mut fi),
+                Err(Errno::EFBIG)
+            );
+            assert_eq!(
+                fs.fallocate(&ctx(), ino, 0, *off, 1, &mut fi),
+                Err(Errno::EFBIG)
+            );
+        }
+        let attr = SetAttrRequest {
+            size: Some(8 << 60),
+            ..SetAttrRequest::default()
+        };
+        let res = fs.setattr(&ctx(), ino, &attr, None);
+        assert_eq!(res.err(), Some(Errno::EFBIG));
+
+        // Through the trampolines, which must not panic either.
+        let mut session = MockSession::new(&mut fs);
+        let reply = session.write(ino, b"x", 1 << 60, &mut fi);
+        assert_eq!(reply.err(), Some(Errno::EFBIG));
+        let reply = session.write_buf(ino, b"x", i64::MAX, &fi);
+        assert_eq!(reply.err(), Some(Errno::EFBIG));
+        let reply = session.setattr(ino, &attr, None);
+        assert_eq!(reply.err(), Some(Errno::EFBIG));
+        let reply = session.fallocate(ino, 0, 1 << 60, 1 << 60, &mut fi);
+        assert_eq!(reply.err(), Some(Errno::EFBIG));
+        assert_eq!(session.read(ino, 10, 0, &mut fi).unwrap_data(), b"abc");
+    }
+
+    #[test]
+    fn access_checks_supplementary_groups() {
+        let mut fs = InMemoryFs::new();
+        let ino = create(&mut fs, 1, b"f", b"");
+        let attr = SetAttrRequest {
+            mode: Some(0o640),
+            ..SetAttrRequest::default()
+        };
+        fs.setattr(&ctx(), ino, &attr, None).unwrap();
+        let other = FuseCtx {
+            uid: 2000,
+            gid: 2000,
+            ..CTX
+        };
+        let reply = MockSession::new(&mut fs).ctx(other).access(ino, libc::R_OK);
+        assert_eq!(reply.err(), Some(Errno::EACCES));
+        let reply = MockSession::new(&mut fs)
+            .ctx(other)
+            .groups(vec![2001, CTX.gid])
+            .access(ino, libc::R_OK);
+        assert_eq!(reply.err(), None);
+    }
+}
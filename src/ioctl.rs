@@ -0,0 +1,66 @@
+use std::ops::BitAnd;
+
+/// Flags of an `ioctl` request, the `FUSE_IOCTL_*` values of libfuse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FuseIoctlFlag {
+    /// A 32-bit compat ioctl on a 64-bit machine.
+    Compat = 1 << 0,
+    /// Not restricted to well-formed ioctls, the areas to copy may be asked with a retry.
+    Unrestricted = 1 << 1,
+    /// Set by the kernel when the request is the retry of an earlier one.
+    Retry = 1 << 2,
+    /// The caller is a 32-bit process.
+    Bit32 = 1 << 3,
+    /// Issued on a directory.
+    Dir = 1 << 4,
+    /// An x32 compat ioctl on a 64-bit machine.
+    CompatX32 = 1 << 5,
+}
+
+impl BitAnd<FuseIoctlFlag> for u32 {
+    type Output = u32;
+    fn bitand(self, rhs: FuseIoctlFlag) -> Self::Output {
+        self & rhs as u32
+    }
+}
+
+/// An area in the memory of the calling process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoctlIovec {
+    pub base: u64,
+    pub len: usize,
+}
+
+impl IoctlIovec {
+    pub fn new(base: u64, len: usize) -> Self {
+        Self { base, len }
+    }
+}
+
+/// An `ioctl(2)` of the calling process, handed to [`FileSystem::ioctl`](crate::FileSystem::ioctl).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoctlRequest<'a> {
+    pub cmd: u32,
+    /// The address passed to `ioctl(2)` in the calling process.
+    pub arg: u64,
+    /// [`FuseIoctlFlag`] bits.
+    pub flags: u32,
+    /// The data copied from the caller.
+    pub in_buf: &'a [u8],
+    /// How much may be copied back to the caller.
+    pub out_size: usize,
+}
+
+/// The answer of [`FileSystem::ioctl`](crate::FileSystem::ioctl).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IoctlOutput {
+    /// The value returned by `ioctl(2)` and the data copied back to the caller,
+    /// at most `out_size` bytes.
+    Done { result: i32, data: Vec<u8> },
+    /// Only for [`FuseIoctlFlag::Unrestricted`]: asks the kernel to send the request
+    /// again with `input` copied in and room for `output` to be copied out.
+    Retry {
+        input: Vec<IoctlIovec>,
+        output: Vec<IoctlIovec>,
+    },
+}
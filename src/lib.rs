@@ -1,36 +1,77 @@
 #[macro_use]
 extern crate log;
 
+#[macro_use]
+mod macros;
+
 use std::borrow::{Borrow, BorrowMut};
-use std::cmp::min;
 use std::env;
 use std::ffi::{CStr, CString};
+use std::io;
+use std::marker::PhantomData;
 use std::mem::size_of;
-use std::ops::{BitAnd, BitOr};
-use std::ptr::{null, null_mut};
+use std::ops::{BitAnd, BitOr, DerefMut};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use libc::{c_char, c_int, c_void, dev_t, flock, mode_t, off_t, size_t, stat, EINTR};
+use libc::{c_char, c_int, c_uint, c_void, dev_t, flock, mode_t, off_t, size_t, stat, EINTR};
 
+mod capture;
+mod connection;
+mod deferred_filesystem;
+mod errno;
+mod error;
 mod filesystem;
 mod fuse;
+mod inmemory;
+mod ioctl;
+mod mount_options;
+mod notify;
+mod passthrough;
+mod reply;
+mod request;
+mod setattr;
+mod sync_filesystem;
+pub mod testing;
 mod utils;
 
+use crate::capture::req_userdata;
+pub use crate::connection::{ConnectionInfo, FuseCapFlag, SpliceConfig};
+pub use crate::deferred_filesystem::DeferredFileSystem;
+use crate::deferred_filesystem::Immediate;
+pub use crate::errno::Errno;
+pub use crate::error::MountError;
 pub use crate::filesystem::FileSystem;
 use crate::fuse::{
-    fuse_add_direntry, fuse_remove_signal_handlers, fuse_reply_attr, fuse_reply_bmap,
-    fuse_reply_buf, fuse_reply_create, fuse_reply_entry, fuse_reply_err, fuse_reply_lock,
-    fuse_reply_lseek, fuse_reply_none, fuse_reply_open, fuse_reply_poll, fuse_reply_readlink,
-    fuse_reply_statfs, fuse_reply_write, fuse_reply_xattr, fuse_req_ctx, fuse_req_userdata,
-    fuse_session_destroy, fuse_session_exited, fuse_session_mount, fuse_session_new,
-    fuse_session_process_buf, fuse_session_receive_buf, fuse_session_reset, fuse_session_unmount,
-    fuse_set_signal_handlers, FuseArgs, FuseBuf, FuseConnInfo, FuseLowLevelOps, FuseReq,
-    FuseSession,
+    fuse_remove_signal_handlers, fuse_session_destroy, fuse_session_exited,
+    fuse_session_loop_mt_32, fuse_session_mount, fuse_session_new, fuse_session_process_buf,
+    fuse_session_receive_buf, fuse_session_reset, fuse_session_unmount, fuse_set_signal_handlers,
+    FuseArgs, FuseConnInfo, FuseLoopConfig, FuseLowLevelOps, FuseSession, ReceiveBuf,
 };
 pub use crate::fuse::{
-    FileType, FuseAttr, FuseBufvec, FuseCtx, FuseDirectory, FuseEntryParam, FuseFileInfo,
-    FuseForgetData, FuseLock, FusePollhandle, FuseStatvfs,
+    FileType, FuseAttr, FuseBuf, FuseBufChunk, FuseBufCopyFlags, FuseBufFlags, FuseBufvec, FuseCtx,
+    FuseEntryParam, FuseFileInfo, FuseForgetData, FuseLock, FusePollhandle, FuseReq, FuseStatvfs,
+    OFlag,
 };
+pub use crate::inmemory::InMemoryFs;
+pub use crate::ioctl::{FuseIoctlFlag, IoctlIovec, IoctlOutput, IoctlRequest};
+pub use crate::mount_options::{MountCommand, MountCommandError, MountOptions};
+use crate::notify::{close_session, open_session, shared_session, SharedSession};
+pub use crate::notify::{Notifier, PollHandle};
+pub use crate::passthrough::PassthroughFs;
+use crate::reply::reply_none;
+pub use crate::reply::{
+    DirectoryPlusReply, DirectoryReply, ReadReply, ReplyAttr, ReplyBmap, ReplyCreate, ReplyData,
+    ReplyDirectory, ReplyDirectoryPlus, ReplyEmpty, ReplyEntry, ReplyIoctl, ReplyLock, ReplyLseek,
+    ReplyOpen, ReplyPoll, ReplyReadlink, ReplyStatfs, ReplyWrite, ReplyXattr,
+};
+pub use crate::request::{CancellationToken, Request};
+pub use crate::setattr::{SetAttrRequest, TimeOrNow};
+use crate::sync_filesystem::SharedRef;
+pub use crate::sync_filesystem::SyncFileSystem;
+use crate::utils::bytes_from_raw;
 
+#[repr(u64)]
 pub enum FuseOpFlag {
     Init = 1 << 0,
     Destroy = 1 << 1,
@@ -74,6 +115,8 @@ pub enum FuseOpFlag {
     Readdirplus = 1 << 39,
     CopyFileRange = 1 << 40,
     Lseek = 1 << 41,
+    RetrieveReply = 1 << 42,
+    Ioctl = 1 << 43,
 }
 
 impl BitOr<FuseOpFlag> for FuseOpFlag {
@@ -95,35 +138,118 @@ impl BitAnd<FuseOpFlag> for u64 {
     }
 }
 
+/// The session userdata, owned by [`Fuse`].
+struct SessionData {
+    file_system: *mut c_void,
+    splice: Option<SpliceConfig>,
+    // For the poll handles, which must not outlive the session.
+    session: SharedSession,
+    // The id of the next request, see `Request::unique`.
+    next_unique: AtomicU64,
+}
+
+// The filesystem is only reached through the session, like with a bare pointer.
+unsafe impl Send for SessionData {}
+
+impl SessionData {
+    unsafe fn from_userdata<'a>(userdata: *mut c_void) -> &'a SessionData {
+        (userdata as *const SessionData).as_ref().unwrap()
+    }
+    fn next_unique(&self) -> u64 {
+        self.next_unique.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// How the trampolines in `FuseOps` reach the filesystem stored in the session data.
+trait Dispatch {
+    type Guard<'a>: DerefMut<Target = dyn DeferredFileSystem + 'a>
+    where
+        Self: 'a;
+    unsafe fn file_system<'a>(userdata: *mut c_void) -> Self::Guard<'a>
+    where
+        Self: 'a;
+}
+
+/// The userdata is a `FileSystem` that is only touched by the single-threaded loop.
+struct Exclusive<T>(PhantomData<T>);
+
+impl<T: FileSystem> Dispatch for Exclusive<T> {
+    type Guard<'a>
+        = Immediate<&'a mut (dyn FileSystem + 'a)>
+    where
+        Self: 'a;
+    unsafe fn file_system<'a>(userdata: *mut c_void) -> Self::Guard<'a>
+    where
+        Self: 'a,
+    {
+        Immediate((userdata as *mut T).as_mut().unwrap())
+    }
+}
+
+/// The userdata is a `SyncFileSystem` that may be used by several worker threads at once.
+struct Shared<T>(PhantomData<T>);
+
+impl<T: SyncFileSystem> Dispatch for Shared<T> {
+    type Guard<'a>
+        = Immediate<SharedRef<'a, T>>
+    where
+        Self: 'a;
+    unsafe fn file_system<'a>(userdata: *mut c_void) -> Self::Guard<'a>
+    where
+        Self: 'a,
+    {
+        Immediate(SharedRef((userdata as *const T).as_ref().unwrap()))
+    }
+}
+
+/// The userdata is a `DeferredFileSystem` that answers through reply objects.
+struct Deferred<T>(PhantomData<T>);
+
+impl<T: DeferredFileSystem> Dispatch for Deferred<T> {
+    type Guard<'a>
+        = &'a mut (dyn DeferredFileSystem + 'a)
+    where
+        Self: 'a;
+    unsafe fn file_system<'a>(userdata: *mut c_void) -> Self::Guard<'a>
+    where
+        Self: 'a,
+    {
+        (userdata as *mut T).as_mut().unwrap()
+    }
+}
+
 struct FuseOps;
 
 macro_rules! filesystem {
     ($req:expr) => {
         unsafe {
-            let userdata = fuse_req_userdata($req);
-            (userdata as *mut T).as_mut().unwrap()
+            let data = SessionData::from_userdata(req_userdata($req));
+            D::file_system(data.file_system)
         }
     };
 }
 
 macro_rules! ctx {
     ($req:expr) => {
-        unsafe { fuse_req_ctx($req).as_ref().unwrap() }
+        &unsafe {
+            let data = SessionData::from_userdata(req_userdata($req));
+            Request::new($req, data.next_unique())
+        }
     };
 }
 
 macro_rules! op {
     ($ops:expr, $name:ident, $flag:ident) => {
         if $ops & FuseOpFlag::$flag == 0 {
-            null()
+            None
         } else {
-            (FuseOps::$name::<T>) as *const _
+            Some(FuseOps::$name::<D>)
         }
     };
 }
 
 impl FuseOps {
-    fn fuse_low_level_ops<T: FileSystem>(ops: u64) -> FuseLowLevelOps {
+    fn fuse_low_level_ops<D: Dispatch>(ops: u64) -> FuseLowLevelOps {
         FuseLowLevelOps {
             init: op!(ops, init, Init),
             destroy: op!(ops, destroy, Destroy),
@@ -159,8 +285,10 @@ impl FuseOps {
             getlk: op!(ops, getlk, Getlk),
             setlk: op!(ops, setlk, Setlk),
             bmap: op!(ops, bmap, Bmap),
+            ioctl: op!(ops, ioctl, Ioctl),
             poll: op!(ops, poll, Poll),
             write_buf: op!(ops, write_buf, WriteBuf),
+            retrieve_reply: op!(ops, retrieve_reply, RetrieveReply),
             forget_multi: op!(ops, forget_multi, ForgetMulti),
             flock: op!(ops, flock, Flock),
             fallocate: op!(ops, fallocate, Fallocate),
@@ -169,243 +297,195 @@ impl FuseOps {
             lseek: op!(ops, lseek, Lseek),
         }
     }
-    fn init<T: FileSystem>(userdata: *mut c_void, _conn: *mut FuseConnInfo) {
-        let file_system = unsafe { (userdata as *mut T).as_mut().unwrap() };
-        let _ = file_system.init();
+    extern "C" fn init<D: Dispatch>(userdata: *mut c_void, conn: *mut FuseConnInfo) {
+        let data = unsafe { SessionData::from_userdata(userdata) };
+        let conn = unsafe { ConnectionInfo::from_raw(conn) };
+        // Before the filesystem, which has the last word.
+        if let Some(splice) = data.splice {
+            splice.apply(conn);
+        }
+        let mut file_system = unsafe { D::file_system(data.file_system) };
+        let _ = file_system.init(conn);
     }
-    fn destroy<T: FileSystem>(userdata: *mut c_void) {
-        let file_system = unsafe { (userdata as *mut T).as_mut().unwrap() };
+    extern "C" fn destroy<D: Dispatch>(userdata: *mut c_void) {
+        let data = unsafe { SessionData::from_userdata(userdata) };
+        let mut file_system = unsafe { D::file_system(data.file_system) };
         let _ = file_system.destroy();
     }
-    fn lookup<T: FileSystem>(req: *mut FuseReq, parent: u64, name: *const c_char) {
-        let file_system = filesystem!(req);
+    extern "C" fn lookup<D: Dispatch>(req: *mut FuseReq, parent: u64, name: *const c_char) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
 
-        match file_system.lookup(ctx, parent, unsafe { CStr::from_ptr(name).to_bytes() }) {
-            Ok(entry_param) => unsafe {
-                let _ret = fuse_reply_entry(req, entry_param.borrow());
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        file_system.lookup(
+            ctx,
+            parent,
+            unsafe { CStr::from_ptr(name).to_bytes() },
+            ReplyEntry::new(ctx),
+        );
     }
-    fn forget<T: FileSystem>(req: *mut FuseReq, ino: u64, nlookup: u64) {
-        let file_system = filesystem!(req);
+    extern "C" fn forget<D: Dispatch>(req: *mut FuseReq, ino: u64, nlookup: u64) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
         file_system.forget(ctx, FuseForgetData { ino, nlookup });
-        unsafe {
-            fuse_reply_none(req);
-        }
+        reply_none(ctx);
     }
-    fn getattr<T: FileSystem>(req: *mut FuseReq, ino: u64, fi: *mut FuseFileInfo) {
-        let file_system = filesystem!(req);
+    extern "C" fn getattr<D: Dispatch>(req: *mut FuseReq, ino: u64, fi: *mut FuseFileInfo) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.getattr(ctx, ino, unsafe { fi.as_mut() }) {
-            Ok((attr, timeout)) => unsafe {
-                let _ret = fuse_reply_attr(req, attr.convert().borrow(), timeout);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        file_system.getattr(ctx, ino, unsafe { fi.as_mut() }, ReplyAttr::new(ctx));
     }
-    fn setattr<T: FileSystem>(
+    extern "C" fn setattr<D: Dispatch>(
         req: *mut FuseReq,
         ino: u64,
         attr: *mut stat,
-        to_set: i16,
+        to_set: c_int,
         fi: *mut FuseFileInfo,
     ) {
-        let file_system = filesystem!(req);
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.setattr(
+        file_system.setattr(
             ctx,
             ino,
-            &FuseAttr::new(unsafe { attr.as_ref().unwrap() }),
-            to_set,
+            &SetAttrRequest::new(unsafe { attr.as_ref().unwrap() }, to_set),
             unsafe { fi.as_mut() },
-        ) {
-            Ok((attr, timeout)) => unsafe {
-                let _ret = fuse_reply_attr(req, attr.convert().borrow(), timeout);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+            ReplyAttr::new(ctx),
+        );
     }
-    fn readlink<T: FileSystem>(req: *mut FuseReq, ino: u64) {
-        let file_system = filesystem!(req);
-        let ctx = ctx!(req);
-        match file_system.readlink(ctx, ino) {
-            Ok(link) => unsafe {
-                let link = CString::new(&link[..]).unwrap();
-                let _ret = fuse_reply_readlink(req, link.as_ptr());
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+    extern "C" fn readlink<D: Dispatch>(req: *mut FuseReq, ino: u64) {
+        let mut file_system = filesystem!(req);
+        let ctx = ctx!(req);
+        file_system.readlink(ctx, ino, ReplyReadlink::new(ctx));
     }
-    fn mknod<T: FileSystem>(
+    extern "C" fn mknod<D: Dispatch>(
         req: *mut FuseReq,
         parent: u64,
         name: *const c_char,
         mode: mode_t,
         rdev: dev_t,
     ) {
-        let file_system = filesystem!(req);
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.mknod(
+        file_system.mknod(
             ctx,
             parent,
             unsafe { CStr::from_ptr(name).to_bytes() },
             mode,
             rdev,
-        ) {
-            Ok(entry_param) => unsafe {
-                let _ret = fuse_reply_entry(req, entry_param.borrow());
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+            ReplyEntry::new(ctx),
+        );
     }
-    fn mkdir<T: FileSystem>(req: *mut FuseReq, parent: u64, name: *const c_char, mode: mode_t) {
-        let file_system = filesystem!(req);
+    extern "C" fn mkdir<D: Dispatch>(
+        req: *mut FuseReq,
+        parent: u64,
+        name: *const c_char,
+        mode: mode_t,
+    ) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.mkdir(
+        file_system.mkdir(
             ctx,
             parent,
             unsafe { CStr::from_ptr(name).to_bytes() },
             mode,
-        ) {
-            Ok(entry_param) => unsafe {
-                let _ret = fuse_reply_entry(req, entry_param.borrow());
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+            ReplyEntry::new(ctx),
+        );
     }
-    fn unlink<T: FileSystem>(req: *mut FuseReq, parent: u64, name: *const c_char) {
-        let file_system = filesystem!(req);
+    extern "C" fn unlink<D: Dispatch>(req: *mut FuseReq, parent: u64, name: *const c_char) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.unlink(ctx, parent, unsafe { CStr::from_ptr(name).to_bytes() }) {
-            Ok(..) => unsafe {
-                fuse_reply_err(req, 0);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        file_system.unlink(
+            ctx,
+            parent,
+            unsafe { CStr::from_ptr(name).to_bytes() },
+            ReplyEmpty::new(ctx),
+        );
     }
-    fn rmdir<T: FileSystem>(req: *mut FuseReq, parent: u64, name: *const c_char) {
-        let file_system = filesystem!(req);
+    extern "C" fn rmdir<D: Dispatch>(req: *mut FuseReq, parent: u64, name: *const c_char) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.rmdir(ctx, parent, unsafe { CStr::from_ptr(name).to_bytes() }) {
-            Ok(..) => unsafe {
-                fuse_reply_err(req, 0);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        file_system.rmdir(
+            ctx,
+            parent,
+            unsafe { CStr::from_ptr(name).to_bytes() },
+            ReplyEmpty::new(ctx),
+        );
     }
-    fn symlink<T: FileSystem>(
+    extern "C" fn symlink<D: Dispatch>(
         req: *mut FuseReq,
         link: *const c_char,
         parent: u64,
         name: *const c_char,
     ) {
-        let file_system = filesystem!(req);
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.symlink(
+        file_system.symlink(
             ctx,
             unsafe { CStr::from_ptr(link).to_bytes() },
             parent,
             unsafe { CStr::from_ptr(name).to_bytes() },
-        ) {
-            Ok(entry_param) => unsafe {
-                let _ret = fuse_reply_entry(req, entry_param.borrow());
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+            ReplyEntry::new(ctx),
+        );
     }
-    fn rename<T: FileSystem>(
+    extern "C" fn rename<D: Dispatch>(
         req: *mut FuseReq,
         parent: u64,
         name: *const c_char,
         newparent: u64,
         newname: *const c_char,
-        flags: u16,
+        flags: c_uint,
     ) {
-        let file_system = filesystem!(req);
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.rename(
+        file_system.rename(
             ctx,
             parent,
             unsafe { CStr::from_ptr(name).to_bytes() },
             newparent,
             unsafe { CStr::from_ptr(newname).to_bytes() },
             flags,
-        ) {
-            Ok(..) => unsafe {
-                fuse_reply_err(req, 0);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+            ReplyEmpty::new(ctx),
+        );
     }
-    fn link<T: FileSystem>(req: *mut FuseReq, ino: u64, newparent: u64, newname: *const c_char) {
-        let file_system = filesystem!(req);
-        let ctx = ctx!(req);
-        match file_system.link(ctx, ino, newparent, unsafe {
-            CStr::from_ptr(newname).to_bytes()
-        }) {
-            Ok(entry_param) => unsafe {
-                let _ret = fuse_reply_entry(req, entry_param.borrow());
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+    extern "C" fn link<D: Dispatch>(
+        req: *mut FuseReq,
+        ino: u64,
+        newparent: u64,
+        newname: *const c_char,
+    ) {
+        let mut file_system = filesystem!(req);
+        let ctx = ctx!(req);
+        file_system.link(
+            ctx,
+            ino,
+            newparent,
+            unsafe { CStr::from_ptr(newname).to_bytes() },
+            ReplyEntry::new(ctx),
+        );
     }
-    fn open<T: FileSystem>(req: *mut FuseReq, ino: u64, fi: *mut FuseFileInfo) {
-        let file_system = filesystem!(req);
+    extern "C" fn open<D: Dispatch>(req: *mut FuseReq, ino: u64, fi: *mut FuseFileInfo) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.open(ctx, ino, unsafe { fi.read() }) {
-            Ok(fi) => unsafe {
-                let _ret = fuse_reply_open(req, fi.borrow());
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        file_system.open(ctx, ino, unsafe { fi.read() }, ReplyOpen::new(ctx));
     }
-    fn read<T: FileSystem>(
+    extern "C" fn read<D: Dispatch>(
         req: *mut FuseReq,
         ino: u64,
         size: size_t,
         off: off_t,
         fi: *mut FuseFileInfo,
     ) {
-        let file_system = filesystem!(req);
-        let ctx = ctx!(req);
-        match file_system.read(ctx, ino, size, off, unsafe { fi.as_mut().unwrap() }) {
-            Ok(message) => unsafe {
-                let buf = CString::new(&message[..]).unwrap();
-                let _ret = fuse_reply_buf(req, buf.as_ptr(), message.len());
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        let mut file_system = filesystem!(req);
+        let ctx = ctx!(req);
+        file_system.read(
+            ctx,
+            ino,
+            size,
+            off,
+            unsafe { fi.as_mut().unwrap() },
+            ReplyData::new(ctx),
+        );
     }
-    fn write<T: FileSystem>(
+    extern "C" fn write<D: Dispatch>(
         req: *mut FuseReq,
         ino: u64,
         buf: *const c_char,
@@ -413,159 +493,114 @@ impl FuseOps {
         off: off_t,
         fi: *mut FuseFileInfo,
     ) {
-        let file_system = filesystem!(req);
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.write(
+        file_system.write(
             ctx,
             ino,
-            unsafe { CStr::from_ptr(buf).to_bytes() },
+            unsafe { bytes_from_raw(buf, size) },
             size,
             off,
             unsafe { fi.as_mut().unwrap() },
-        ) {
-            Ok(count) => unsafe {
-                let _ret = fuse_reply_write(req, count);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+            ReplyWrite::new(ctx),
+        );
     }
-    fn flush<T: FileSystem>(req: *mut FuseReq, ino: u64, fi: *mut FuseFileInfo) {
-        let file_system = filesystem!(req);
+    extern "C" fn flush<D: Dispatch>(req: *mut FuseReq, ino: u64, fi: *mut FuseFileInfo) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.flush(ctx, ino, unsafe { fi.as_mut().unwrap() }) {
-            Ok(..) => unsafe {
-                fuse_reply_err(req, 0);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        file_system.flush(
+            ctx,
+            ino,
+            unsafe { fi.as_mut().unwrap() },
+            ReplyEmpty::new(ctx),
+        );
     }
-    fn release<T: FileSystem>(req: *mut FuseReq, ino: u64, fi: *mut FuseFileInfo) {
-        let file_system = filesystem!(req);
+    extern "C" fn release<D: Dispatch>(req: *mut FuseReq, ino: u64, fi: *mut FuseFileInfo) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.release(ctx, ino, unsafe { fi.as_mut().unwrap() }) {
-            Ok(..) => unsafe {
-                fuse_reply_err(req, 0);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        file_system.release(
+            ctx,
+            ino,
+            unsafe { fi.as_mut().unwrap() },
+            ReplyEmpty::new(ctx),
+        );
     }
-    fn fsync<T: FileSystem>(req: *mut FuseReq, ino: u64, datasync: c_int, fi: *mut FuseFileInfo) {
-        let file_system = filesystem!(req);
+    extern "C" fn fsync<D: Dispatch>(
+        req: *mut FuseReq,
+        ino: u64,
+        datasync: c_int,
+        fi: *mut FuseFileInfo,
+    ) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.fsync(ctx, ino, datasync, unsafe { fi.as_mut().unwrap() }) {
-            Ok(..) => {}
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        file_system.fsync(
+            ctx,
+            ino,
+            datasync,
+            unsafe { fi.as_mut().unwrap() },
+            ReplyEmpty::new(ctx),
+        );
     }
-    fn opendir<T: FileSystem>(req: *mut FuseReq, ino: u64, fi: *mut FuseFileInfo) {
-        let file_system = filesystem!(req);
+    extern "C" fn opendir<D: Dispatch>(req: *mut FuseReq, ino: u64, fi: *mut FuseFileInfo) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.opendir(ctx, ino, unsafe { fi.as_mut().unwrap() }) {
-            Ok(fi) => unsafe {
-                let _ret = fuse_reply_open(req, fi.borrow());
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        file_system.opendir(
+            ctx,
+            ino,
+            unsafe { fi.as_mut().unwrap() },
+            ReplyOpen::new(ctx),
+        );
     }
-    fn readdir<T: FileSystem>(
+    extern "C" fn readdir<D: Dispatch>(
         req: *mut FuseReq,
         ino: u64,
         size: size_t,
         off: off_t,
         fi: *mut FuseFileInfo,
     ) {
-        let file_system = filesystem!(req);
-        let ctx = ctx!(req);
-        match file_system.readdir(ctx, ino, size, off, unsafe { fi.as_mut().unwrap() }) {
-            Ok(dirs) => unsafe {
-                let mut buf = Vec::<u8>::new();
-                let mut buf_size: usize = 0;
-                for dir in dirs.iter() {
-                    let name_buf = CString::new(&dir.name[..]).unwrap();
-                    let tmp_buf_size =
-                        fuse_add_direntry(req, null_mut(), 0, name_buf.as_ptr(), null(), 0);
-                    buf_size += tmp_buf_size;
-                    let mut tmp_buf = Vec::<u8>::with_capacity(tmp_buf_size);
-                    let ret = fuse_add_direntry(
-                        req,
-                        tmp_buf.as_mut_ptr() as *mut c_char,
-                        tmp_buf_size,
-                        name_buf.as_ptr(),
-                        dir.attr().convert().borrow(),
-                        buf_size as i64,
-                    );
-                    assert_eq!(tmp_buf_size, ret);
-                    tmp_buf.set_len(ret);
-                    buf.extend(tmp_buf);
-                }
-                if off == 0 {
-                    let _ret =
-                        fuse_reply_buf(req, buf.as_ptr() as *const c_char, min(buf.len(), size));
-                } else if (off as usize) < buf_size {
-                    let out: Vec<_> = buf.drain((off as usize)..).collect();
-                    let _ret =
-                        fuse_reply_buf(req, out.as_ptr() as *const c_char, min(out.len(), size));
-                } else {
-                    let _ret = fuse_reply_buf(req, null(), 0);
-                }
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        let mut file_system = filesystem!(req);
+        let ctx = ctx!(req);
+        file_system.readdir(
+            ctx,
+            ino,
+            size,
+            off,
+            unsafe { fi.as_mut().unwrap() },
+            ReplyDirectory::new(ctx, size),
+        );
     }
-    fn releasedir<T: FileSystem>(req: *mut FuseReq, ino: u64, fi: *mut FuseFileInfo) {
-        let file_system = filesystem!(req);
+    extern "C" fn releasedir<D: Dispatch>(req: *mut FuseReq, ino: u64, fi: *mut FuseFileInfo) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.releasedir(ctx, ino, unsafe { fi.as_mut().unwrap() }) {
-            Ok(..) => unsafe {
-                fuse_reply_err(req, 0);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        file_system.releasedir(
+            ctx,
+            ino,
+            unsafe { fi.as_mut().unwrap() },
+            ReplyEmpty::new(ctx),
+        );
     }
-    fn fsyncdir<T: FileSystem>(
+    extern "C" fn fsyncdir<D: Dispatch>(
         req: *mut FuseReq,
         ino: u64,
         datasync: c_int,
         fi: *mut FuseFileInfo,
     ) {
-        let file_system = filesystem!(req);
-        let ctx = ctx!(req);
-        match file_system.fsyncdir(ctx, ino, datasync, unsafe { fi.as_mut().unwrap() }) {
-            Ok(..) => unsafe {
-                fuse_reply_err(req, 0);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        let mut file_system = filesystem!(req);
+        let ctx = ctx!(req);
+        file_system.fsyncdir(
+            ctx,
+            ino,
+            datasync,
+            unsafe { fi.as_mut().unwrap() },
+            ReplyEmpty::new(ctx),
+        );
     }
-    fn statfs<T: FileSystem>(req: *mut FuseReq, ino: u64) {
-        let file_system = filesystem!(req);
+    extern "C" fn statfs<D: Dispatch>(req: *mut FuseReq, ino: u64) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.statfs(ctx, ino) {
-            Ok(stbuf) => unsafe {
-                let _ret = fuse_reply_statfs(req, stbuf.convert().borrow());
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        file_system.statfs(ctx, ino, ReplyStatfs::new(ctx));
     }
-    fn setxattr<T: FileSystem>(
+    extern "C" fn setxattr<D: Dispatch>(
         req: *mut FuseReq,
         ino: u64,
         name: *const c_char,
@@ -573,223 +608,223 @@ impl FuseOps {
         size: size_t,
         flags: c_int,
     ) {
-        let file_system = filesystem!(req);
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.setxattr(
+        file_system.setxattr(
             ctx,
             ino,
             unsafe { CStr::from_ptr(name).to_bytes() },
-            unsafe { CStr::from_ptr(value).to_bytes() },
+            unsafe { bytes_from_raw(value, size) },
             size,
             flags,
-        ) {
-            Ok(..) => unsafe {
-                fuse_reply_err(req, 0);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+            ReplyEmpty::new(ctx),
+        );
     }
-    fn getxattr<T: FileSystem>(req: *mut FuseReq, ino: u64, name: *const c_char, size: size_t) {
-        let file_system = filesystem!(req);
-        let ctx = ctx!(req);
-        match file_system.getxattr(ctx, ino, unsafe { CStr::from_ptr(name).to_bytes() }, size) {
-            Ok(message) => unsafe {
-                if size == 0 {
-                    let _ret = fuse_reply_xattr(req, message.len());
-                    debug!("get_xattr: ret={}", _ret);
-                } else {
-                    let buf = CString::new(&message[..]).unwrap();
-                    let _ret = fuse_reply_buf(req, buf.as_ptr(), message.len());
-                }
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+    extern "C" fn getxattr<D: Dispatch>(
+        req: *mut FuseReq,
+        ino: u64,
+        name: *const c_char,
+        size: size_t,
+    ) {
+        let mut file_system = filesystem!(req);
+        let ctx = ctx!(req);
+        file_system.getxattr(
+            ctx,
+            ino,
+            unsafe { CStr::from_ptr(name).to_bytes() },
+            size,
+            ReplyXattr::new(ctx),
+        );
     }
-    fn listxattr<T: FileSystem>(req: *mut FuseReq, ino: u64, size: size_t) {
-        let file_system = filesystem!(req);
+    extern "C" fn listxattr<D: Dispatch>(req: *mut FuseReq, ino: u64, size: size_t) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.listxattr(ctx, ino, size) {
-            Ok(message) => unsafe {
-                if size == 0 {
-                    let _ret = fuse_reply_xattr(req, message.len());
-                } else {
-                    let buf = CString::new(&message[..]).unwrap();
-                    let _ret = fuse_reply_buf(req, buf.as_ptr(), message.len());
-                }
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        file_system.listxattr(ctx, ino, size, ReplyXattr::new(ctx));
     }
-    fn removexattr<T: FileSystem>(req: *mut FuseReq, ino: u64, name: *const c_char) {
-        let file_system = filesystem!(req);
+    extern "C" fn removexattr<D: Dispatch>(req: *mut FuseReq, ino: u64, name: *const c_char) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.removexattr(ctx, ino, unsafe { CStr::from_ptr(name).to_bytes() }) {
-            Ok(..) => unsafe {
-                fuse_reply_err(req, 0);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        file_system.removexattr(
+            ctx,
+            ino,
+            unsafe { CStr::from_ptr(name).to_bytes() },
+            ReplyEmpty::new(ctx),
+        );
     }
-    fn access<T: FileSystem>(req: *mut FuseReq, ino: u64, mask: c_int) {
-        let file_system = filesystem!(req);
+    extern "C" fn access<D: Dispatch>(req: *mut FuseReq, ino: u64, mask: c_int) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.access(ctx, ino, mask) {
-            Ok(..) => unsafe {
-                fuse_reply_err(req, 0);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        file_system.access(ctx, ino, mask, ReplyEmpty::new(ctx));
     }
-    fn create<T: FileSystem>(
+    extern "C" fn create<D: Dispatch>(
         req: *mut FuseReq,
         parent: u64,
         name: *const c_char,
         mode: mode_t,
         fi: *mut FuseFileInfo,
     ) {
-        let file_system = filesystem!(req);
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.create(
+        file_system.create(
             ctx,
             parent,
             unsafe { CStr::from_ptr(name).to_bytes() },
             mode,
             unsafe { fi.as_mut().unwrap() },
-        ) {
-            Ok(entry_param) => unsafe {
-                let _ret = fuse_reply_create(req, entry_param.borrow(), fi.as_ref().unwrap());
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+            ReplyCreate::new(ctx),
+        );
     }
-    fn getlk<T: FileSystem>(req: *mut FuseReq, ino: u64, fi: *mut FuseFileInfo, lock: *mut flock) {
-        let file_system = filesystem!(req);
+    extern "C" fn getlk<D: Dispatch>(
+        req: *mut FuseReq,
+        ino: u64,
+        fi: *mut FuseFileInfo,
+        lock: *mut flock,
+    ) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.getlk(
+        file_system.getlk(
             ctx,
             ino,
             unsafe { fi.as_mut().unwrap() },
             &mut FuseLock::new(unsafe { lock.as_ref().unwrap() }),
-        ) {
-            Ok(lock) => unsafe {
-                let _ret = fuse_reply_lock(req, lock.convert().borrow());
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+            ReplyLock::new(ctx),
+        );
     }
-    fn setlk<T: FileSystem>(
+    extern "C" fn setlk<D: Dispatch>(
         req: *mut FuseReq,
         ino: u64,
         fi: *mut FuseFileInfo,
         lock: *mut flock,
         sleep: c_int,
     ) {
-        let file_system = filesystem!(req);
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.setlk(
+        file_system.setlk(
             ctx,
             ino,
             unsafe { fi.as_mut().unwrap() },
             &mut FuseLock::new(unsafe { lock.as_ref().unwrap() }),
             sleep,
-        ) {
-            Ok(..) => unsafe {
-                fuse_reply_err(req, 0);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+            ReplyEmpty::new(ctx),
+        );
     }
-    fn bmap<T: FileSystem>(req: *mut FuseReq, ino: u64, blocksize: size_t, idx: u64) {
-        let file_system = filesystem!(req);
+    extern "C" fn bmap<D: Dispatch>(req: *mut FuseReq, ino: u64, blocksize: size_t, idx: u64) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.bmap(ctx, ino, blocksize, idx) {
-            Ok(idx) => unsafe {
-                let _ret = fuse_reply_bmap(req, idx);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        file_system.bmap(ctx, ino, blocksize, idx, ReplyBmap::new(ctx));
+    }
+    // The signature of libfuse.
+    #[allow(clippy::too_many_arguments)]
+    extern "C" fn ioctl<D: Dispatch>(
+        req: *mut FuseReq,
+        ino: u64,
+        cmd: c_uint,
+        arg: *mut c_void,
+        fi: *mut FuseFileInfo,
+        flags: c_uint,
+        in_buf: *const c_void,
+        in_bufsz: size_t,
+        out_bufsz: size_t,
+    ) {
+        let mut file_system = filesystem!(req);
+        let ctx = ctx!(req);
+        let ioctl = IoctlRequest {
+            cmd,
+            arg: arg as u64,
+            flags,
+            in_buf: unsafe { bytes_from_raw(in_buf as *const c_char, in_bufsz) },
+            out_size: out_bufsz,
+        };
+        file_system.ioctl(
+            ctx,
+            ino,
+            unsafe { fi.as_mut().unwrap() },
+            &ioctl,
+            ReplyIoctl::new(ctx),
+        );
     }
-    fn poll<T: FileSystem>(
+    extern "C" fn poll<D: Dispatch>(
         req: *mut FuseReq,
         ino: u64,
         fi: *mut FuseFileInfo,
         ph: *mut FusePollhandle,
     ) {
-        let file_system = filesystem!(req);
-        let ctx = ctx!(req);
-        match file_system.poll(ctx, ino, unsafe { fi.as_mut().unwrap() }, unsafe {
-            ph.as_mut().unwrap()
-        }) {
-            Ok(revents) => unsafe {
-                let _ret = fuse_reply_poll(req, revents);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        let mut file_system = filesystem!(req);
+        let ctx = ctx!(req);
+        let session = unsafe { SessionData::from_userdata(req_userdata(req)) }
+            .session
+            .clone();
+        file_system.poll(
+            ctx,
+            ino,
+            unsafe { fi.as_mut().unwrap() },
+            unsafe { PollHandle::from_raw(ph, session) },
+            ReplyPoll::new(ctx),
+        );
     }
-    fn write_buf<T: FileSystem>(
+    extern "C" fn write_buf<D: Dispatch>(
         req: *mut FuseReq,
         ino: u64,
         bufv: *mut FuseBufvec,
         off: off_t,
         fi: *mut FuseFileInfo,
     ) {
-        let file_system = filesystem!(req);
-        let ctx = ctx!(req);
-        match file_system.write_buf(ctx, ino, unsafe { bufv.as_mut().unwrap() }, off, unsafe {
-            fi.as_mut().unwrap()
-        }) {
-            Ok(count) => unsafe {
-                let _ret = fuse_reply_write(req, count);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
+        let mut file_system = filesystem!(req);
+        let ctx = ctx!(req);
+        file_system.write_buf(
+            ctx,
+            ino,
+            unsafe { bufv.as_mut().unwrap() },
+            off,
+            unsafe { fi.as_mut().unwrap() },
+            ReplyWrite::new(ctx),
+        );
+    }
+    extern "C" fn retrieve_reply<D: Dispatch>(
+        req: *mut FuseReq,
+        cookie: *mut c_void,
+        ino: u64,
+        offset: off_t,
+        bufv: *mut FuseBufvec,
+    ) {
+        let mut file_system = filesystem!(req);
+        let ctx = ctx!(req);
+        match unsafe { bufv.as_mut().unwrap() }.copy_to_vec() {
+            Ok(data) => file_system.retrieve_reply(ctx, cookie as u64, ino, offset, &data),
+            Err(err) => error!("retrieve_reply: failed to copy data: {}", err),
         }
+        reply_none(ctx);
     }
-    fn forget_multi<T: FileSystem>(req: *mut FuseReq, count: size_t, forgets: *mut FuseForgetData) {
-        let file_system = filesystem!(req);
+    extern "C" fn forget_multi<D: Dispatch>(
+        req: *mut FuseReq,
+        count: size_t,
+        forgets: *mut FuseForgetData,
+    ) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
         let fs = (0..count)
             .map(|i| unsafe { forgets.offset(i as isize).read() })
             .collect();
         file_system.forget_multi(ctx, fs);
-        unsafe {
-            fuse_reply_none(req);
-        }
+        reply_none(ctx);
     }
-    fn flock<T: FileSystem>(req: *mut FuseReq, ino: u64, fi: *mut FuseFileInfo, op: c_int) {
-        let file_system = filesystem!(req);
+    extern "C" fn flock<D: Dispatch>(
+        req: *mut FuseReq,
+        ino: u64,
+        fi: *mut FuseFileInfo,
+        op: c_int,
+    ) {
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.flock(ctx, ino, unsafe { fi.as_mut().unwrap() }, op) {
-            Ok(..) => unsafe {
-                fuse_reply_err(req, 0);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        file_system.flock(
+            ctx,
+            ino,
+            unsafe { fi.as_mut().unwrap() },
+            op,
+            ReplyEmpty::new(ctx),
+        );
     }
-    fn fallocate<T: FileSystem>(
+    extern "C" fn fallocate<D: Dispatch>(
         req: *mut FuseReq,
         ino: u64,
         mode: c_int,
@@ -797,39 +832,37 @@ impl FuseOps {
         length: off_t,
         fi: *mut FuseFileInfo,
     ) {
-        let file_system = filesystem!(req);
-        let ctx = ctx!(req);
-        match file_system.fallocate(ctx, ino, mode, offset, length, unsafe {
-            fi.as_mut().unwrap()
-        }) {
-            Ok(..) => unsafe {
-                fuse_reply_err(req, 0);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        let mut file_system = filesystem!(req);
+        let ctx = ctx!(req);
+        file_system.fallocate(
+            ctx,
+            ino,
+            mode,
+            offset,
+            length,
+            unsafe { fi.as_mut().unwrap() },
+            ReplyEmpty::new(ctx),
+        );
     }
-    fn readdirplus<T: FileSystem>(
+    extern "C" fn readdirplus<D: Dispatch>(
         req: *mut FuseReq,
         ino: u64,
         size: size_t,
         off: off_t,
         fi: *mut FuseFileInfo,
     ) {
-        let file_system = filesystem!(req);
-        let ctx = ctx!(req);
-        match file_system.readdirplus(ctx, ino, size, off, unsafe { fi.as_mut().unwrap() }) {
-            Ok(message) => unsafe {
-                let buf = CString::new(&message[..]).unwrap();
-                let _ret = fuse_reply_buf(req, buf.as_ptr(), message.len());
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+        let mut file_system = filesystem!(req);
+        let ctx = ctx!(req);
+        file_system.readdirplus(
+            ctx,
+            ino,
+            size,
+            off,
+            unsafe { fi.as_mut().unwrap() },
+            ReplyDirectoryPlus::new(ctx, size),
+        );
     }
-    fn copy_file_range<T: FileSystem>(
+    extern "C" fn copy_file_range<D: Dispatch>(
         req: *mut FuseReq,
         ino_in: u64,
         off_in: off_t,
@@ -840,9 +873,9 @@ impl FuseOps {
         len: size_t,
         flags: c_int,
     ) {
-        let file_system = filesystem!(req);
+        let mut file_system = filesystem!(req);
         let ctx = ctx!(req);
-        match file_system.copy_file_range(
+        file_system.copy_file_range(
             ctx,
             ino_in,
             off_in,
@@ -852,75 +885,261 @@ impl FuseOps {
             unsafe { fi_out.as_mut().unwrap() },
             len,
             flags,
-        ) {
-            Ok(count) => unsafe {
-                let _ret = fuse_reply_write(req, count);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
-        }
+            ReplyWrite::new(ctx),
+        );
     }
-    fn lseek<T: FileSystem>(
+    extern "C" fn lseek<D: Dispatch>(
         req: *mut FuseReq,
         ino: u64,
         off: off_t,
         whence: c_int,
         fi: *mut FuseFileInfo,
     ) {
-        let file_system = filesystem!(req);
-        let ctx = ctx!(req);
-        match file_system.lseek(ctx, ino, off, whence, unsafe { fi.as_mut().unwrap() }) {
-            Ok(off) => unsafe {
-                let _ret = fuse_reply_lseek(req, off);
-            },
-            Err(e) => unsafe {
-                fuse_reply_err(req, e);
-            },
+        let mut file_system = filesystem!(req);
+        let ctx = ctx!(req);
+        file_system.lseek(
+            ctx,
+            ino,
+            off,
+            whence,
+            unsafe { fi.as_mut().unwrap() },
+            ReplyLseek::new(ctx),
+        );
+    }
+}
+
+/// Settings for the worker pool of [`Fuse::run_mt`].
+#[derive(Debug, Clone)]
+pub struct LoopConfig {
+    /// Open a separate `/dev/fuse` file descriptor for each worker thread.
+    pub clone_fd: bool,
+    /// Number of idle worker threads kept around; surplus threads exit.
+    pub max_idle_threads: u32,
+}
+
+impl Default for LoopConfig {
+    fn default() -> Self {
+        Self {
+            clone_fd: false,
+            max_idle_threads: 10,
         }
     }
 }
 
+/// Configures how a filesystem is mounted.
+pub struct FuseBuilder {
+    mountpoint: String,
+    ops: Option<u64>,
+    options: MountOptions,
+    splice: Option<SpliceConfig>,
+    signal_handlers: bool,
+}
+
+impl FuseBuilder {
+    pub fn new(mountpoint: &str) -> Self {
+        Self {
+            mountpoint: mountpoint.to_string(),
+            ops: None,
+            options: MountOptions::new(),
+            splice: None,
+            signal_handlers: true,
+        }
+    }
+    /// Passes `options` to libfuse, see [`MountCommand::parse`] to take them from a command line.
+    pub fn options(mut self, options: MountOptions) -> Self {
+        self.options = options;
+        self
+    }
+    /// Chooses where requests and replies are spliced, applied to the connection
+    /// before the `init` of the filesystem. Without it the defaults of libfuse stay,
+    /// which splice requests only for filesystems that implement `write_buf`.
+    pub fn splice(mut self, splice: SpliceConfig) -> Self {
+        self.splice = Some(splice);
+        self
+    }
+    /// Whether to install the handlers of libfuse that end the session on `SIGINT`,
+    /// `SIGTERM` and `SIGHUP`, on by default. They are process-wide and only one
+    /// session can have them, so turn them off for mounts that aren't the main one
+    /// of the process, e.g. in tests.
+    pub fn signal_handlers(mut self, install: bool) -> Self {
+        self.signal_handlers = install;
+        self
+    }
+    /// Registers exactly `ops` instead of the operations inferred by [`register_ops!`].
+    pub fn ops(mut self, ops: u64) -> Self {
+        self.ops = Some(ops);
+        self
+    }
+    pub fn mount<T: FileSystem>(self, file_system: &mut T) -> Result<Fuse, MountError> {
+        let ops = self.ops.unwrap_or_else(T::ops);
+        Fuse::mount::<Exclusive<T>>(self, file_system as *mut T as *mut c_void, ops, false)
+    }
+    /// Mounts a filesystem that answers requests through reply objects.
+    pub fn mount_deferred<T: DeferredFileSystem>(
+        self,
+        file_system: &mut T,
+    ) -> Result<Fuse, MountError> {
+        let ops = self.ops.unwrap_or_else(T::ops);
+        Fuse::mount::<Deferred<T>>(self, file_system as *mut T as *mut c_void, ops, false)
+    }
+    /// Mounts a filesystem that can serve requests from several threads, see [`Fuse::run_mt`].
+    pub fn mount_sync<T: SyncFileSystem>(self, file_system: &T) -> Result<Fuse, MountError> {
+        let ops = self.ops.unwrap_or_else(T::ops);
+        Fuse::mount::<Shared<T>>(self, file_system as *const T as *mut c_void, ops, true)
+    }
+}
+
 pub struct Fuse {
     session: &'static mut FuseSession,
+    shared: SharedSession,
+    thread_safe: bool,
+    signal_handlers: bool,
+    // Dropped after the session is destroyed in `drop`.
+    _data: Box<SessionData>,
 }
 impl Fuse {
-    pub fn new<T: FileSystem>(mountpoint: &str, file_system: &mut T, ops: u64) -> Self {
-        let arg0 = CString::new(env::args().nth(0).unwrap()).unwrap();
-        let mountpoint = CString::new(mountpoint).unwrap();
-        let c_argv: Vec<*const c_char> = vec![arg0.as_ptr()];
+    /// Mounts `file_system` with the operations registered by [`register_ops!`].
+    pub fn new<T: FileSystem>(mountpoint: &str, file_system: &mut T) -> Result<Self, MountError> {
+        FuseBuilder::new(mountpoint).mount(file_system)
+    }
+    /// Mounts a filesystem that answers requests through reply objects.
+    pub fn new_deferred<T: DeferredFileSystem>(
+        mountpoint: &str,
+        file_system: &mut T,
+    ) -> Result<Self, MountError> {
+        FuseBuilder::new(mountpoint).mount_deferred(file_system)
+    }
+    /// Mounts a filesystem that can serve requests from several threads, see [`Fuse::run_mt`].
+    pub fn new_sync<T: SyncFileSystem>(
+        mountpoint: &str,
+        file_system: &T,
+    ) -> Result<Self, MountError> {
+        FuseBuilder::new(mountpoint).mount_sync(file_system)
+    }
+    pub fn builder(mountpoint: &str) -> FuseBuilder {
+        FuseBuilder::new(mountpoint)
+    }
+    fn mount<D: Dispatch>(
+        builder: FuseBuilder,
+        userdata: *mut c_void,
+        ops: u64,
+        thread_safe: bool,
+    ) -> Result<Self, MountError> {
+        if ops == 0 {
+            warn!("no operations are registered, did you forget register_ops!?");
+        }
+        match Path::new(&builder.mountpoint).metadata() {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => return Err(MountError::NotADirectory(builder.mountpoint.into())),
+            Err(err) => {
+                let path = builder.mountpoint.into();
+                return Err(match err.kind() {
+                    io::ErrorKind::NotFound => MountError::MissingMountpoint(path),
+                    io::ErrorKind::PermissionDenied => MountError::PermissionDenied(path),
+                    _ => MountError::Io(path, err),
+                });
+            }
+        }
+        let args = builder
+            .options
+            .to_args(&env::args().nth(0).unwrap_or_default())
+            .into_iter()
+            .map(CString::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mountpoint = CString::new(builder.mountpoint.as_str())?;
+        let c_argv: Vec<*const c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
         let mut fuse_args = FuseArgs {
-            argc: 1 as c_int,
+            argc: c_argv.len() as c_int,
             argv: c_argv.as_ptr(),
             allocated: 0 as c_int,
         };
-        let op = FuseOps::fuse_low_level_ops::<T>(ops);
-
-        let session = unsafe {
+        // `init` applies the splice settings.
+        let ops = match builder.splice {
+            Some(_) => ops | FuseOpFlag::Init,
+            None => ops,
+        };
+        let op = FuseOps::fuse_low_level_ops::<D>(ops);
+        let shared = shared_session();
+        let mut data = Box::new(SessionData {
+            file_system: userdata,
+            splice: builder.splice,
+            session: shared.clone(),
+            next_unique: AtomicU64::new(1),
+        });
+        unsafe {
             let session = fuse_session_new(
                 fuse_args.borrow_mut(),
                 op.borrow(),
                 size_of::<FuseLowLevelOps>(),
-                file_system.borrow_mut() as *mut T as *mut c_void,
+                &mut *data as *mut SessionData as *mut c_void,
             );
-            let _ = fuse_set_signal_handlers(session);
-            let _ = fuse_session_mount(session, mountpoint.as_ptr());
-            session.as_mut().unwrap()
-        };
-        Fuse { session }
+            let session = session.as_mut().ok_or(MountError::Session)?;
+            if builder.signal_handlers && fuse_set_signal_handlers(session) != 0 {
+                fuse_session_destroy(session);
+                return Err(MountError::SignalHandlers);
+            }
+            if fuse_session_mount(session, mountpoint.as_ptr()) != 0 {
+                if builder.signal_handlers {
+                    fuse_remove_signal_handlers(session);
+                }
+                fuse_session_destroy(session);
+                return Err(MountError::Mount(builder.mountpoint.into()));
+            }
+            open_session(&shared, session);
+            Ok(Fuse {
+                shared,
+                session,
+                thread_safe,
+                signal_handlers: builder.signal_handlers,
+                _data: data,
+            })
+        }
     }
+    /// Returns a handle to invalidate or fill the caches of the kernel, also from other threads.
+    pub fn notifier(&self) -> Notifier {
+        Notifier::new(self.shared.clone())
+    }
+    /// Serves requests on the calling thread until the filesystem is unmounted.
     pub fn run(&mut self) {
         let sess = self.session.borrow_mut();
-        let mut buf = FuseBuf::new();
+        let mut buf = ReceiveBuf::new();
         unsafe {
             while fuse_session_exited(sess) == 0 {
-                let res = fuse_session_receive_buf(sess, buf.borrow_mut());
+                let res = fuse_session_receive_buf(sess, &mut *buf);
                 if res == -EINTR {
                     continue;
-                } else if res < 0 {
+                } else if res <= 0 {
+                    // Zero once the filesystem is unmounted.
+                    if res < 0 {
+                        error!("fuse_session_receive_buf: {}", Errno::new(res));
+                    }
                     break;
                 }
-                let _ = fuse_session_process_buf(sess, buf.borrow());
+                let _ = fuse_session_process_buf(sess, &*buf);
+            }
+            fuse_session_reset(sess);
+        }
+    }
+    /// Serves requests from a pool of worker threads until the filesystem is unmounted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the filesystem was not mounted with [`Fuse::new_sync`], because a
+    /// [`FileSystem`] takes `&mut self` and must not be entered from several threads.
+    pub fn run_mt(&mut self, config: &LoopConfig) {
+        assert!(
+            self.thread_safe,
+            "run_mt requires a SyncFileSystem mounted with Fuse::new_sync"
+        );
+        let sess = self.session.borrow_mut();
+        let mut loop_config = FuseLoopConfig {
+            clone_fd: config.clone_fd as c_int,
+            max_idle_threads: config.max_idle_threads,
+        };
+        unsafe {
+            let res = fuse_session_loop_mt_32(sess, loop_config.borrow_mut());
+            if res < 0 {
+                error!("fuse_session_loop_mt: {}", res);
             }
             fuse_session_reset(sess);
         }
@@ -929,10 +1148,13 @@ impl Fuse {
 
 impl Drop for Fuse {
     fn drop(&mut self) {
+        close_session(&self.shared);
         let sess = self.session.borrow_mut();
         unsafe {
             fuse_session_unmount(sess);
-            fuse_remove_signal_handlers(sess);
+            if self.signal_handlers {
+                fuse_remove_signal_handlers(sess);
+            }
             fuse_session_destroy(sess);
         }
     }
@@ -0,0 +1,398 @@
+/// Implements a filesystem trait and registers exactly the operations it defines.
+///
+/// Wrap an `impl` of [`FileSystem`](crate::FileSystem),
+/// [`SyncFileSystem`](crate::SyncFileSystem) or
+/// [`DeferredFileSystem`](crate::DeferredFileSystem) in this macro and it fills in the
+/// trait's `ops()` mask from the names of the methods written in the block.
+/// [`Fuse::new`](crate::Fuse::new) hands those operations, and only those, to libfuse,
+/// so an operation that is not implemented is never registered by accident and an
+/// implemented one is never forgotten.
+///
+/// The macro only understands an `impl` without generics or a `where` clause whose
+/// items are all methods. Other impls write `ops()` by hand, e.g.
+/// `FuseOpFlag::Lookup | FuseOpFlag::Getattr`.
+///
+/// ```ignore
+/// rusfuse::register_ops! {
+///     impl FileSystem for HelloFs {
+///         fn lookup(&mut self, ctx: &Request, parent: u64, name: &[u8]) -> Result<FuseEntryParam, Errno> {
+///             // ...
+///         }
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! register_ops {
+    (
+        $(#[$impl_meta:meta])*
+        impl $($trait_:ident)::+ for $ty:ty {
+            $(
+                $(#[$fn_meta:meta])*
+                fn $name:ident ($($args:tt)*) $(-> $ret:ty)? $body:block
+            )*
+        }
+    ) => {
+        $(#[$impl_meta])*
+        impl $($trait_)::+ for $ty {
+            fn ops() -> u64 {
+                0 $(| $crate::__op_flag!($name))*
+            }
+            $(
+                $(#[$fn_meta])*
+                fn $name ($($args)*) $(-> $ret)? $body
+            )*
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __op_flag {
+    (init) => {
+        $crate::FuseOpFlag::Init as u64
+    };
+    (destroy) => {
+        $crate::FuseOpFlag::Destroy as u64
+    };
+    (lookup) => {
+        $crate::FuseOpFlag::Lookup as u64
+    };
+    (forget) => {
+        $crate::FuseOpFlag::Forget as u64
+    };
+    (getattr) => {
+        $crate::FuseOpFlag::Getattr as u64
+    };
+    (setattr) => {
+        $crate::FuseOpFlag::Setattr as u64
+    };
+    (readlink) => {
+        $crate::FuseOpFlag::Readlink as u64
+    };
+    (mknod) => {
+        $crate::FuseOpFlag::Mknod as u64
+    };
+    (mkdir) => {
+        $crate::FuseOpFlag::Mkdir as u64
+    };
+    (unlink) => {
+        $crate::FuseOpFlag::Unlink as u64
+    };
+    (rmdir) => {
+        $crate::FuseOpFlag::Rmdir as u64
+    };
+    (symlink) => {
+        $crate::FuseOpFlag::Symlink as u64
+    };
+    (rename) => {
+        $crate::FuseOpFlag::Rename as u64
+    };
+    (link) => {
+        $crate::FuseOpFlag::Link as u64
+    };
+    (open) => {
+        $crate::FuseOpFlag::Open as u64
+    };
+    (read) => {
+        $crate::FuseOpFlag::Read as u64
+    };
+    (write) => {
+        $crate::FuseOpFlag::Write as u64
+    };
+    (flush) => {
+        $crate::FuseOpFlag::Flush as u64
+    };
+    (release) => {
+        $crate::FuseOpFlag::Release as u64
+    };
+    (fsync) => {
+        $crate::FuseOpFlag::Fsync as u64
+    };
+    (opendir) => {
+        $crate::FuseOpFlag::Opendir as u64
+    };
+    (readdir) => {
+        $crate::FuseOpFlag::Readdir as u64
+    };
+    (releasedir) => {
+        $crate::FuseOpFlag::Releasedir as u64
+    };
+    (fsyncdir) => {
+        $crate::FuseOpFlag::Fsyncdir as u64
+    };
+    (statfs) => {
+        $crate::FuseOpFlag::Statfs as u64
+    };
+    (setxattr) => {
+        $crate::FuseOpFlag::Setxattr as u64
+    };
+    (getxattr) => {
+        $crate::FuseOpFlag::Getxattr as u64
+    };
+    (listxattr) => {
+        $crate::FuseOpFlag::Listxattr as u64
+    };
+    (removexattr) => {
+        $crate::FuseOpFlag::Removexattr as u64
+    };
+    (access) => {
+        $crate::FuseOpFlag::Access as u64
+    };
+    (create) => {
+        $crate::FuseOpFlag::Create as u64
+    };
+    (getlk) => {
+        $crate::FuseOpFlag::Getlk as u64
+    };
+    (setlk) => {
+        $crate::FuseOpFlag::Setlk as u64
+    };
+    (bmap) => {
+        $crate::FuseOpFlag::Bmap as u64
+    };
+    (poll) => {
+        $crate::FuseOpFlag::Poll as u64
+    };
+    (write_buf) => {
+        $crate::FuseOpFlag::WriteBuf as u64
+    };
+    (forget_multi) => {
+        $crate::FuseOpFlag::ForgetMulti as u64
+    };
+    (flock) => {
+        $crate::FuseOpFlag::Flock as u64
+    };
+    (fallocate) => {
+        $crate::FuseOpFlag::Fallocate as u64
+    };
+    (readdirplus) => {
+        $crate::FuseOpFlag::Readdirplus as u64
+    };
+    (copy_file_range) => {
+        $crate::FuseOpFlag::CopyFileRange as u64
+    };
+    (lseek) => {
+        $crate::FuseOpFlag::Lseek as u64
+    };
+    (ioctl) => {
+        $crate::FuseOpFlag::Ioctl as u64
+    };
+    (retrieve_reply) => {
+        $crate::FuseOpFlag::RetrieveReply as u64
+    };
+}
+
+/// Calls `$callback!` with the operations that [`FileSystem`](crate::FileSystem),
+/// [`SyncFileSystem`](crate::SyncFileSystem) and
+/// [`DeferredFileSystem`](crate::DeferredFileSystem) share, so their methods are
+/// declared once.
+///
+/// `$callback!` gets the tokens after its name first. The arguments follow the
+/// `&Request` of the call. `answered` operations give the value of their `Ok` and
+/// the reply object that takes it, `unanswered` ones get no reply. `init`, `destroy`, `readdir` and `readdirplus` differ between the traits
+/// and are written out in each.
+macro_rules! operations {
+    ($callback:ident $($prefix:tt)*) => {
+        $callback! {
+            $($prefix)*
+            answered {
+                fn lookup(parent: u64, name: &[u8]) -> FuseEntryParam, ReplyEntry;
+                fn getattr(ino: u64, fi: Option<&mut FuseFileInfo>) -> (FuseAttr, f64), ReplyAttr;
+                fn setattr(
+                    ino: u64,
+                    attr: &SetAttrRequest,
+                    fi: Option<&mut FuseFileInfo>,
+                ) -> (FuseAttr, f64), ReplyAttr;
+                fn readlink(ino: u64) -> Vec<u8>, ReplyReadlink;
+                fn mknod(
+                    parent: u64,
+                    name: &[u8],
+                    mode: u32,
+                    rdev: u64,
+                ) -> FuseEntryParam, ReplyEntry;
+                fn mkdir(parent: u64, name: &[u8], mode: u32) -> FuseEntryParam, ReplyEntry;
+                fn unlink(parent: u64, name: &[u8]) -> (), ReplyEmpty;
+                fn rmdir(parent: u64, name: &[u8]) -> (), ReplyEmpty;
+                fn symlink(link: &[u8], parent: u64, name: &[u8]) -> FuseEntryParam, ReplyEntry;
+                #[allow(clippy::too_many_arguments)]
+                fn rename(
+                    parent: u64,
+                    name: &[u8],
+                    newparent: u64,
+                    newname: &[u8],
+                    flags: u32,
+                ) -> (), ReplyEmpty;
+                fn link(ino: u64, newparent: u64, newname: &[u8]) -> FuseEntryParam, ReplyEntry;
+                fn open(ino: u64, fi: FuseFileInfo) -> FuseFileInfo, ReplyOpen;
+                /// At most `size` bytes from `off`, fewer only at the end of the file.
+                /// See [`ReadReply`](crate::ReadReply) for data that doesn't need to be copied.
+                fn read(
+                    ino: u64,
+                    size: usize,
+                    off: i64,
+                    fi: &mut FuseFileInfo,
+                ) -> ReadReply<'_>, ReplyData;
+                #[allow(clippy::too_many_arguments)]
+                fn write(
+                    ino: u64,
+                    buf: &[u8],
+                    size: usize,
+                    off: i64,
+                    fi: &mut FuseFileInfo,
+                ) -> usize, ReplyWrite;
+                fn flush(ino: u64, fi: &mut FuseFileInfo) -> (), ReplyEmpty;
+                fn release(ino: u64, fi: &mut FuseFileInfo) -> (), ReplyEmpty;
+                fn fsync(ino: u64, datasync: i32, fi: &mut FuseFileInfo) -> (), ReplyEmpty;
+                fn opendir(ino: u64, fi: &mut FuseFileInfo) -> FuseFileInfo, ReplyOpen;
+                fn releasedir(ino: u64, fi: &mut FuseFileInfo) -> (), ReplyEmpty;
+                fn fsyncdir(ino: u64, datasync: i32, fi: &mut FuseFileInfo) -> (), ReplyEmpty;
+                fn statfs(ino: u64) -> FuseStatvfs, ReplyStatfs;
+                #[allow(clippy::too_many_arguments)]
+                fn setxattr(
+                    ino: u64,
+                    name: &[u8],
+                    value: &[u8],
+                    size: usize,
+                    flags: i32,
+                ) -> (), ReplyEmpty;
+                fn getxattr(ino: u64, name: &[u8], size: usize) -> Vec<u8>, ReplyXattr;
+                fn listxattr(ino: u64, size: usize) -> Vec<u8>, ReplyXattr;
+                fn removexattr(ino: u64, name: &[u8]) -> (), ReplyEmpty;
+                fn access(ino: u64, mask: i32) -> (), ReplyEmpty;
+                fn create(
+                    parent: u64,
+                    name: &[u8],
+                    mode: u32,
+                    fi: &mut FuseFileInfo,
+                ) -> FuseEntryParam, ReplyCreate;
+                fn getlk(
+                    ino: u64,
+                    fi: &mut FuseFileInfo,
+                    lock: &mut FuseLock,
+                ) -> FuseLock, ReplyLock;
+                fn setlk(
+                    ino: u64,
+                    fi: &mut FuseFileInfo,
+                    lock: &mut FuseLock,
+                    sleep: i32,
+                ) -> (), ReplyEmpty;
+                fn bmap(ino: u64, blocksize: usize, idx: u64) -> u64, ReplyBmap;
+                /// Answer with at most `ioctl.out_size` bytes of data.
+                fn ioctl(
+                    ino: u64,
+                    fi: &mut FuseFileInfo,
+                    ioctl: &IoctlRequest,
+                ) -> IoctlOutput, ReplyIoctl;
+                /// `ph` is given when the caller waits for an event; keep it and call
+                /// [`PollHandle::notify`](crate::PollHandle::notify) once the file becomes ready.
+                fn poll(ino: u64, fi: &mut FuseFileInfo, ph: Option<PollHandle>) -> u32, ReplyPoll;
+                /// Like [`write`](Self::write) with the data in a
+                /// [`FuseBufvec`](crate::FuseBufvec), which may be the pipe of a spliced
+                /// request. libfuse calls it instead of `write` when both are implemented.
+                fn write_buf(
+                    ino: u64,
+                    bufv: &mut FuseBufvec,
+                    off: i64,
+                    fi: &FuseFileInfo,
+                ) -> usize, ReplyWrite;
+                fn flock(ino: u64, fi: &mut FuseFileInfo, op: i32) -> (), ReplyEmpty;
+                #[allow(clippy::too_many_arguments)]
+                fn fallocate(
+                    ino: u64,
+                    mode: i32,
+                    offset: i64,
+                    length: i64,
+                    fi: &mut FuseFileInfo,
+                ) -> (), ReplyEmpty;
+                #[allow(clippy::too_many_arguments)]
+                fn copy_file_range(
+                    ino_in: u64,
+                    off_in: i64,
+                    fi_in: &mut FuseFileInfo,
+                    ino_out: u64,
+                    off_out: i64,
+                    fi_out: &mut FuseFileInfo,
+                    len: usize,
+                    flags: i32,
+                ) -> usize, ReplyWrite;
+                fn lseek(ino: u64, off: i64, whence: i32, fi: &mut FuseFileInfo) -> i64, ReplyLseek;
+            }
+            unanswered {
+                fn forget(forget: FuseForgetData);
+                /// Receives the data asked for with
+                /// [`Notifier::retrieve`](crate::Notifier::retrieve).
+                fn retrieve_reply(cookie: u64, ino: u64, offset: i64, data: &[u8]);
+                fn forget_multi(forgets: Vec<FuseForgetData>);
+            }
+        }
+    };
+}
+
+/// The methods of [`operations!`] for `FileSystem` and `SyncFileSystem`, which fail
+/// with `ENOSYS` until implemented. Takes the type of `self` in parentheses first.
+macro_rules! default_methods {
+    (
+        ($receiver:ty)
+        answered {
+            $(
+                $(#[$meta:meta])*
+                fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty, $reply:ty;
+            )*
+        }
+        unanswered {
+            $(
+                $(#[$unanswered_meta:meta])*
+                fn $unanswered:ident($($unanswered_arg:ident: $unanswered_ty:ty),* $(,)?);
+            )*
+        }
+    ) => {
+        $(
+            $(#[$meta])*
+            #[allow(unused_variables)]
+            fn $name(self: $receiver, ctx: &Request, $($arg: $ty),*) -> Result<$ret, Errno> {
+                Err(Errno::ENOSYS)
+            }
+        )*
+        $(
+            $(#[$unanswered_meta])*
+            #[allow(unused_variables)]
+            fn $unanswered(self: $receiver, ctx: &Request, $($unanswered_arg: $unanswered_ty),*) {}
+        )*
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Errno, FileSystem, FuseAttr, FuseFileInfo, FuseOpFlag, Request};
+
+    struct Fs;
+
+    register_ops! {
+        impl FileSystem for Fs {
+            fn getattr(
+                &mut self,
+                _ctx: &Request,
+                _ino: u64,
+                _fi: Option<&mut FuseFileInfo>,
+            ) -> Result<(FuseAttr, f64), Errno> {
+                Err(Errno::ENOENT)
+            }
+            fn unlink(
+                &mut self,
+                _ctx: &Request,
+                _parent: u64,
+                _name: &[u8],
+            ) -> Result<(), Errno> {
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn ops_are_inferred_from_methods() {
+        assert_eq!(
+            Fs::ops(),
+            FuseOpFlag::Getattr as u64 | FuseOpFlag::Unlink as u64
+        );
+    }
+}
@@ -0,0 +1,371 @@
+use std::fmt;
+
+/// Options handed to libfuse when a filesystem is mounted.
+///
+/// They end up as `-o` options in the argument vector of `fuse_session_new`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MountOptions {
+    allow_other: bool,
+    allow_root: bool,
+    default_permissions: bool,
+    auto_unmount: bool,
+    read_only: bool,
+    debug: bool,
+    fsname: Option<String>,
+    subtype: Option<String>,
+    options: Vec<String>,
+}
+
+// Only meaningful to mount(8) and fstab.
+const IGNORED: &[&str] = &[
+    "defaults", "auto", "noauto", "user", "nouser", "users", "_netdev", "nofail",
+];
+
+impl MountOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Lets users other than the one who mounted access the filesystem.
+    pub fn allow_other(mut self) -> Self {
+        self.allow_other = true;
+        self
+    }
+    /// Like `allow_other`, but only for root.
+    pub fn allow_root(mut self) -> Self {
+        self.allow_root = true;
+        self
+    }
+    /// Lets the kernel check permissions from the file mode instead of the filesystem.
+    pub fn default_permissions(mut self) -> Self {
+        self.default_permissions = true;
+        self
+    }
+    /// Unmounts the filesystem when the process exits, even on a crash.
+    pub fn auto_unmount(mut self) -> Self {
+        self.auto_unmount = true;
+        self
+    }
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+    /// Makes libfuse print every request and reply.
+    pub fn debug(mut self) -> Self {
+        self.debug = true;
+        self
+    }
+    /// Name shown as the source of the mount, for example in `/proc/mounts`.
+    pub fn fsname(mut self, fsname: &str) -> Self {
+        self.fsname = Some(fsname.to_string());
+        self
+    }
+    /// Shown as `fuse.<subtype>` for the type of the mount.
+    pub fn subtype(mut self, subtype: &str) -> Self {
+        self.subtype = Some(subtype.to_string());
+        self
+    }
+    /// Passes any other option as it is, e.g. `max_read=131072`.
+    pub fn option(mut self, option: &str) -> Self {
+        self.options.push(option.to_string());
+        self
+    }
+
+    /// The `-o` options in the order libfuse receives them.
+    pub fn to_options(&self) -> Vec<String> {
+        let mut options = Vec::new();
+        let flags = [
+            (self.allow_other, "allow_other"),
+            (self.allow_root, "allow_root"),
+            (self.default_permissions, "default_permissions"),
+            (self.auto_unmount, "auto_unmount"),
+            (self.read_only, "ro"),
+            (self.debug, "debug"),
+        ];
+        for (set, name) in flags.iter() {
+            if *set {
+                options.push(name.to_string());
+            }
+        }
+        if let Some(fsname) = &self.fsname {
+            options.push(format!("fsname={}", fsname));
+        }
+        if let Some(subtype) = &self.subtype {
+            options.push(format!("subtype={}", subtype));
+        }
+        options.extend(self.options.iter().cloned());
+        options
+    }
+
+    /// The argument vector for `fuse_session_new`, starting with the program name.
+    pub fn to_args(&self, program: &str) -> Vec<String> {
+        let mut args = vec![program.to_string()];
+        let options = self.to_options();
+        if !options.is_empty() {
+            args.push("-o".to_string());
+            args.push(
+                options
+                    .iter()
+                    .map(|option| escape(option))
+                    .collect::<Vec<_>>()
+                    .join(","),
+            );
+        }
+        args
+    }
+
+    fn push(&mut self, option: &str) {
+        match option {
+            "allow_other" => self.allow_other = true,
+            "allow_root" => self.allow_root = true,
+            "default_permissions" => self.default_permissions = true,
+            "auto_unmount" => self.auto_unmount = true,
+            "ro" => self.read_only = true,
+            "rw" => self.read_only = false,
+            "debug" => self.debug = true,
+            _ if IGNORED.contains(&option) => {}
+            _ if option.starts_with("x-") || option.starts_with("comment=") => {}
+            _ => {
+                if let Some(fsname) = option.strip_prefix("fsname=") {
+                    self.fsname = Some(fsname.to_string());
+                } else if let Some(subtype) = option.strip_prefix("subtype=") {
+                    self.subtype = Some(subtype.to_string());
+                } else {
+                    self.options.push(option.to_string());
+                }
+            }
+        }
+    }
+}
+
+// libfuse splits `-o` on commas not preceded by a backslash.
+fn escape(option: &str) -> String {
+    option.replace('\\', "\\\\").replace(',', "\\,")
+}
+
+fn split_options(options: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut chars = options.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => current.extend(chars.next()),
+            ',' => result.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    result.push(current);
+    result
+        .into_iter()
+        .filter(|option| !option.is_empty())
+        .collect()
+}
+
+/// A command line in the form `mount.fuse` helpers are called with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountCommand {
+    /// The device or source, without the `type#` prefix.
+    pub source: Option<String>,
+    pub mountpoint: String,
+    pub options: MountOptions,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MountCommandError {
+    MissingMountpoint,
+    MissingValue(String),
+    UnexpectedArgument(String),
+}
+
+impl fmt::Display for MountCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MountCommandError::MissingMountpoint => write!(f, "no mountpoint given"),
+            MountCommandError::MissingValue(arg) => write!(f, "{} requires a value", arg),
+            MountCommandError::UnexpectedArgument(arg) => {
+                write!(f, "unexpected argument {:?}", arg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MountCommandError {}
+
+impl MountCommand {
+    /// Parses the arguments after the program name,
+    /// `[type#]source mountpoint [-o options] [-t type[.subtype]] [-fnsv]`.
+    ///
+    /// A lone argument is taken as the mountpoint. `type#` or `-t fuse.subtype` set the
+    /// subtype unless the options name one.
+    pub fn parse<I, S>(args: I) -> Result<Self, MountCommandError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut options = MountOptions::new();
+        let mut positional = Vec::new();
+        let mut subtype = None;
+        let mut args = args.into_iter().map(|arg| arg.as_ref().to_string());
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" | "-t" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| MountCommandError::MissingValue(arg.clone()))?;
+                    if arg == "-o" {
+                        split_options(&value).iter().for_each(|o| options.push(o));
+                    } else {
+                        subtype = fs_subtype(&value);
+                    }
+                }
+                "-d" => options.debug = true,
+                // Fake, no-mtab, sloppy and verbose of mount(8).
+                "-f" | "-n" | "-s" | "-v" => {}
+                _ if arg.starts_with("-o") => {
+                    split_options(&arg[2..])
+                        .iter()
+                        .for_each(|o| options.push(o));
+                }
+                _ if arg.starts_with("-t") => subtype = fs_subtype(&arg[2..]),
+                _ if arg.starts_with('-') => {
+                    return Err(MountCommandError::UnexpectedArgument(arg));
+                }
+                _ => positional.push(arg),
+            }
+        }
+        let (source, mountpoint) = match positional.len() {
+            0 => return Err(MountCommandError::MissingMountpoint),
+            1 => (None, positional.pop().unwrap()),
+            2 => {
+                let mountpoint = positional.pop().unwrap();
+                (positional.pop(), mountpoint)
+            }
+            _ => return Err(MountCommandError::UnexpectedArgument(positional.remove(2))),
+        };
+        let source = source.map(|source| match source.find('#') {
+            Some(i) => {
+                subtype = Some(source[..i].to_string());
+                source[i + 1..].to_string()
+            }
+            None => source,
+        });
+        if options.subtype.is_none() {
+            options.subtype = subtype.filter(|subtype| !subtype.is_empty());
+        }
+        if let (None, Some(source)) = (&options.fsname, &source) {
+            if !source.is_empty() {
+                options.fsname = Some(source.clone());
+            }
+        }
+        Ok(MountCommand {
+            source: source.filter(|source| !source.is_empty()),
+            mountpoint,
+            options,
+        })
+    }
+}
+
+// `fuse.sshfs` -> `sshfs`, a plain `fuse` has no subtype.
+fn fs_subtype(fs_type: &str) -> Option<String> {
+    let mut parts = fs_type.splitn(2, '.');
+    parts.next();
+    parts.next().map(|subtype| subtype.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mount_options::{MountCommand, MountCommandError, MountOptions};
+
+    #[test]
+    fn builder_produces_args() {
+        let options = MountOptions::new()
+            .allow_other()
+            .default_permissions()
+            .read_only()
+            .fsname("tank,1")
+            .subtype("memfs")
+            .option("max_read=131072");
+        assert_eq!(
+            options.to_args("prog"),
+            vec![
+                "prog",
+                "-o",
+                "allow_other,default_permissions,ro,fsname=tank\\,1,subtype=memfs,max_read=131072",
+            ]
+        );
+    }
+
+    #[test]
+    fn no_options_no_flag() {
+        assert_eq!(MountOptions::new().to_args("prog"), vec!["prog"]);
+    }
+
+    #[test]
+    fn parse_fstab_style() {
+        let cmd = MountCommand::parse(&[
+            "memfs#tank",
+            "/mnt/tank",
+            "-o",
+            "rw,noauto,user,allow_other,x-systemd.automount,max_read=4096",
+        ])
+        .unwrap();
+        assert_eq!(cmd.source.as_deref(), Some("tank"));
+        assert_eq!(cmd.mountpoint, "/mnt/tank");
+        assert_eq!(
+            cmd.options,
+            MountOptions::new()
+                .allow_other()
+                .fsname("tank")
+                .subtype("memfs")
+                .option("max_read=4096")
+        );
+    }
+
+    #[test]
+    fn parse_type_and_flags() {
+        let cmd =
+            MountCommand::parse(&["-n", "src", "/mnt", "-t", "fuse.memfs", "-oro,debug"]).unwrap();
+        assert_eq!(
+            cmd.options,
+            MountOptions::new()
+                .read_only()
+                .debug()
+                .fsname("src")
+                .subtype("memfs")
+        );
+    }
+
+    #[test]
+    fn parse_options_override_source() {
+        let cmd = MountCommand::parse(&["a#b", "/mnt", "-o", "fsname=x\\,y,subtype=z"]).unwrap();
+        assert_eq!(cmd.options, MountOptions::new().fsname("x,y").subtype("z"));
+    }
+
+    #[test]
+    fn parse_mountpoint_only() {
+        let cmd = MountCommand::parse(&["/mnt"]).unwrap();
+        assert_eq!(cmd.source, None);
+        assert_eq!(cmd.mountpoint, "/mnt");
+        assert_eq!(cmd.options, MountOptions::new());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let none: [&str; 0] = [];
+        assert_eq!(
+            MountCommand::parse(&none),
+            Err(MountCommandError::MissingMountpoint)
+        );
+        assert_eq!(
+            MountCommand::parse(&["/mnt", "-o"]),
+            Err(MountCommandError::MissingValue("-o".to_string()))
+        );
+        assert_eq!(
+            MountCommand::parse(&["/mnt", "--bogus"]),
+            Err(MountCommandError::UnexpectedArgument("--bogus".to_string()))
+        );
+        assert_eq!(
+            MountCommand::parse(&["a", "b", "c"]),
+            Err(MountCommandError::UnexpectedArgument("c".to_string()))
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::ptr::null_mut;
+use std::sync::{Arc, RwLock};
+
+use libc::{c_char, c_void};
+
+use crate::errno::Errno;
+use crate::fuse::{
+    fuse_lowlevel_notify_delete, fuse_lowlevel_notify_inval_entry,
+    fuse_lowlevel_notify_inval_inode, fuse_lowlevel_notify_poll, fuse_lowlevel_notify_retrieve,
+    fuse_lowlevel_notify_store, fuse_pollhandle_destroy, FuseBufvec, FusePollhandle, FuseSession,
+};
+
+#[derive(Debug)]
+pub(crate) struct SessionPtr(*mut FuseSession);
+
+// The notify functions of libfuse may be called from any thread.
+unsafe impl Send for SessionPtr {}
+unsafe impl Sync for SessionPtr {}
+
+/// The session of a [`Fuse`](crate::Fuse), shared with its notifiers until it is unmounted.
+pub(crate) type SharedSession = Arc<RwLock<SessionPtr>>;
+
+/// Fails with `ENODEV` until [`open_session`] sets the session.
+pub(crate) fn shared_session() -> SharedSession {
+    Arc::new(RwLock::new(SessionPtr(null_mut())))
+}
+
+pub(crate) fn open_session(shared: &SharedSession, session: *mut FuseSession) {
+    let mut shared = shared.write().unwrap_or_else(|err| err.into_inner());
+    shared.0 = session;
+}
+
+/// Makes the notifiers and poll handles of a session fail with `ENODEV` from now on.
+pub(crate) fn close_session(session: &SharedSession) {
+    let mut session = session.write().unwrap_or_else(|err| err.into_inner());
+    session.0 = null_mut();
+}
+
+/// Tells the kernel that data it cached for the filesystem has changed.
+///
+/// Obtained from [`Fuse::notifier`](crate::Fuse::notifier), it can be cloned and
+/// moved to other threads. Once the filesystem is unmounted every call fails with
+/// `ENODEV`. Other errors come from the kernel, e.g. `ENOENT` when the
+/// kernel has nothing cached for the inode or entry.
+///
+/// Invalidating an entry or deleting it while the kernel waits for the reply to an
+/// operation on the same directory deadlocks, so don't call these from inside such
+/// an operation of a single-threaded loop.
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    session: SharedSession,
+}
+
+impl Notifier {
+    pub(crate) fn new(session: SharedSession) -> Self {
+        Self { session }
+    }
+
+    fn notify<F: FnOnce(*mut FuseSession) -> i32>(&self, f: F) -> Result<(), Errno> {
+        let session = self.session.read().unwrap_or_else(|err| err.into_inner());
+        if session.0.is_null() {
+            return Err(Errno::ENODEV);
+        }
+        match f(session.0) {
+            res if res < 0 => Err(Errno::new(res)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Drops the cached attributes of an inode and the cached data from `off`
+    /// for `len` bytes. A negative `off` only drops the attributes, a `len` of zero
+    /// invalidates up to the end of the file.
+    pub fn inval_inode(&self, ino: u64, off: i64, len: i64) -> Result<(), Errno> {
+        self.notify(|session| unsafe { fuse_lowlevel_notify_inval_inode(session, ino, off, len) })
+    }
+
+    /// Drops the cached lookup of `name` in `parent`.
+    pub fn inval_entry(&self, parent: u64, name: &[u8]) -> Result<(), Errno> {
+        let name = nul_terminated(name);
+        self.notify(|session| unsafe {
+            fuse_lowlevel_notify_inval_entry(
+                session,
+                parent,
+                name.as_ptr() as *const c_char,
+                name.len() - 1,
+            )
+        })
+    }
+
+    /// Tells the kernel that `name` in `parent`, which was `child`, has been removed.
+    ///
+    /// Unlike [`Notifier::inval_entry`], the dentry is also dropped when it is still in use.
+    pub fn delete(&self, parent: u64, child: u64, name: &[u8]) -> Result<(), Errno> {
+        let name = nul_terminated(name);
+        self.notify(|session| unsafe {
+            fuse_lowlevel_notify_delete(
+                session,
+                parent,
+                child,
+                name.as_ptr() as *const c_char,
+                name.len() - 1,
+            )
+        })
+    }
+
+    /// Puts `data` into the page cache of an inode at `offset`.
+    pub fn store(&self, ino: u64, offset: i64, data: &[u8]) -> Result<(), Errno> {
+        let mut bufv = FuseBufvec::new(data.as_ptr() as *mut c_void, data.len());
+        self.notify(|session| unsafe {
+            fuse_lowlevel_notify_store(session, ino, offset, &mut bufv, 0)
+        })
+    }
+
+    /// Asks the kernel for `size` bytes of the page cache of an inode at `offset`.
+    ///
+    /// The data arrives in `retrieve_reply` of the filesystem together with `cookie`.
+    pub fn retrieve(&self, ino: u64, size: usize, offset: i64, cookie: u64) -> Result<(), Errno> {
+        self.notify(|session| unsafe {
+            fuse_lowlevel_notify_retrieve(session, ino, size, offset, cookie as *mut c_void)
+        })
+    }
+}
+
+/// Wakes up a `poll`, `select` or `epoll` waiting on a file.
+///
+/// Handed to `poll` of the filesystem, it may be stored and notified later from
+/// any thread. It is released when dropped, so a handle that is replaced by the
+/// one of a newer `poll` can simply be overwritten. Once the filesystem is
+/// unmounted, notifying fails with `ENODEV`.
+#[derive(Debug)]
+pub struct PollHandle {
+    ph: *mut FusePollhandle,
+    session: SharedSession,
+}
+
+unsafe impl Send for PollHandle {}
+unsafe impl Sync for PollHandle {}
+
+impl PollHandle {
+    pub(crate) unsafe fn from_raw(ph: *mut FusePollhandle, session: SharedSession) -> Option<Self> {
+        if ph.is_null() {
+            None
+        } else {
+            Some(Self { ph, session })
+        }
+    }
+
+    /// Tells the waiting process to poll the file again.
+    pub fn notify(&self) -> Result<(), Errno> {
+        // The handle points to the session, keep it from being destroyed meanwhile.
+        let session = self.session.read().unwrap_or_else(|err| err.into_inner());
+        if session.0.is_null() {
+            return Err(Errno::ENODEV);
+        }
+        match unsafe { fuse_lowlevel_notify_poll(self.ph) } {
+            res if res < 0 => Err(Errno::new(res)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for PollHandle {
+    fn drop(&mut self) {
+        unsafe {
+            fuse_pollhandle_destroy(self.ph);
+        }
+    }
+}
+
+// libfuse sends the name together with the NUL byte that follows it.
+fn nul_terminated(name: &[u8]) -> Vec<u8> {
+    let mut name = name.to_vec();
+    name.push(0);
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr::null_mut;
+
+    use crate::notify::{close_session, open_session, shared_session, PollHandle};
+    use crate::Errno;
+
+    #[test]
+    fn poll_handles_fail_after_unmount() {
+        let session = shared_session();
+        // Never dereferenced, the session is closed before the handle is notified.
+        open_session(&session, 8 as *mut _);
+        let ph = PollHandle {
+            ph: null_mut(),
+            session: session.clone(),
+        };
+        close_session(&session);
+        assert_eq!(ph.notify(), Err(Errno::ENODEV));
+    }
+}
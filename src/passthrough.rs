@@ -0,0 +1,978 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::Path;
+use std::ptr::null_mut;
+use std::time::UNIX_EPOCH;
+
+use libc::{
+    c_char, c_int, c_long, c_uint, c_void, dirent64, off64_t, stat, timespec, AT_EMPTY_PATH,
+    AT_FDCWD, AT_REMOVEDIR, AT_SYMLINK_FOLLOW, AT_SYMLINK_NOFOLLOW, DIR, F_OFD_GETLK, F_OFD_SETLK,
+    F_OFD_SETLKW, O_CLOEXEC, O_CREAT, O_DIRECTORY, O_NOFOLLOW, O_PATH, O_RDONLY, PATH_MAX,
+    UTIME_NOW, UTIME_OMIT,
+};
+
+use crate::errno::Errno;
+use crate::fuse::{
+    FuseAttr, FuseBufvec, FuseEntryParam, FuseFileInfo, FuseForgetData, FuseLock, FuseStatvfs,
+};
+use crate::reply::{DirectoryPlusReply, DirectoryReply, ReadReply};
+use crate::request::Request;
+use crate::setattr::{SetAttrRequest, TimeOrNow};
+use crate::FileSystem;
+
+const ROOT_INO: u64 = 1;
+
+struct Inode {
+    // Opened with `O_PATH`, only good for the `*at` calls and `/proc/self/fd`.
+    fd: OwnedFd,
+    // `st_dev` and `st_ino` in the source directory.
+    key: (u64, u64),
+    nlookup: u64,
+    is_symlink: bool,
+}
+
+// The offsets of a directory stream are the `d_off` cookies of the source
+// filesystem, which stay valid while the directory changes.
+struct DirHandle {
+    stream: *mut DIR,
+    // Where the stream is, the `d_off` of the last entry read or zero.
+    offset: i64,
+}
+
+// The stream is only used through `&mut PassthroughFs`.
+unsafe impl Send for DirHandle {}
+
+impl Drop for DirHandle {
+    fn drop(&mut self) {
+        unsafe {
+            libc::closedir(self.stream);
+        }
+    }
+}
+
+/// Mirrors a directory of the host, like `passthrough_ll` of libfuse.
+///
+/// Every inode the kernel knows is kept open as an `O_PATH` descriptor until it
+/// is forgotten, and open files and directories are real descriptors whose
+/// numbers are the file handles. Operations run with the credentials of the
+/// process, so mount with `default_permissions` to have the kernel check the
+/// ones of the caller. Locks are open file description locks, which conflict
+/// between opens rather than between processes.
+pub struct PassthroughFs {
+    inodes: HashMap<u64, Inode>,
+    by_key: HashMap<(u64, u64), u64>,
+    next_ino: u64,
+    dirs: HashMap<u64, DirHandle>,
+    timeout: f64,
+}
+
+impl PassthroughFs {
+    /// Opens `source`, which becomes the root of the filesystem.
+    pub fn new<P: AsRef<Path>>(source: P) -> io::Result<Self> {
+        let path = CString::new(source.as_ref().as_os_str().as_bytes())?;
+        let fd = unsafe { libc::open(path.as_ptr(), O_PATH | O_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let st = stat_fd(fd.as_raw_fd()).map_err(io::Error::from)?;
+        let key = (st.st_dev as u64, st.st_ino as u64);
+        let root = Inode {
+            fd,
+            key,
+            // Never forgotten.
+            nlookup: 2,
+            is_symlink: false,
+        };
+        Ok(Self {
+            inodes: vec![(ROOT_INO, root)].into_iter().collect(),
+            by_key: vec![(key, ROOT_INO)].into_iter().collect(),
+            next_ino: ROOT_INO + 1,
+            dirs: HashMap::new(),
+            timeout: 1.0,
+        })
+    }
+
+    /// How long the kernel may cache attributes and lookups, one second by default.
+    /// Use zero when the source directory is also changed from outside.
+    pub fn timeout(mut self, timeout: f64) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn inode(&self, ino: u64) -> Result<&Inode, Errno> {
+        self.inodes.get(&ino).ok_or(Errno::ESTALE)
+    }
+
+    fn fd(&self, ino: u64) -> Result<RawFd, Errno> {
+        Ok(self.inode(ino)?.fd.as_raw_fd())
+    }
+
+    fn attr(&self, ino: u64, st: &stat) -> FuseAttr {
+        let mut attr = FuseAttr::new(st);
+        attr.ino = ino;
+        attr
+    }
+
+    fn entry(&self, ino: u64, st: &stat) -> FuseEntryParam {
+        FuseEntryParam::new(self.attr(ino, st), 0, self.timeout, self.timeout)
+    }
+
+    fn do_lookup(&mut self, parent: u64, name: &[u8]) -> Result<FuseEntryParam, Errno> {
+        let name = c_name(name)?;
+        let fd = check(unsafe {
+            libc::openat(
+                self.fd(parent)?,
+                name.as_ptr(),
+                O_PATH | O_NOFOLLOW | O_CLOEXEC,
+            )
+        })?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let st = stat_fd(fd.as_raw_fd())?;
+        let key = (st.st_dev as u64, st.st_ino as u64);
+        let ino = match self.by_key.get(&key) {
+            Some(&ino) => {
+                self.inodes.get_mut(&ino).unwrap().nlookup += 1;
+                ino
+            }
+            None => {
+                let ino = self.next_ino;
+                self.next_ino += 1;
+                let inode = Inode {
+                    fd,
+                    key,
+                    nlookup: 1,
+                    is_symlink: st.st_mode & libc::S_IFMT == libc::S_IFLNK,
+                };
+                self.inodes.insert(ino, inode);
+                self.by_key.insert(key, ino);
+                ino
+            }
+        };
+        Ok(self.entry(ino, &st))
+    }
+
+    fn forget_one(&mut self, ino: u64, nlookup: u64) {
+        if ino == ROOT_INO {
+            return;
+        }
+        if let Some(inode) = self.inodes.get_mut(&ino) {
+            inode.nlookup = inode.nlookup.saturating_sub(nlookup);
+            if inode.nlookup == 0 {
+                let key = inode.key;
+                self.inodes.remove(&ino);
+                self.by_key.remove(&key);
+            }
+        }
+    }
+
+    // Reads the directory stream of `fh` from `off` and passes each entry to `add`
+    // until it reports a full reply.
+    fn read_dir<F>(&mut self, fh: u64, off: i64, mut add: F) -> Result<(), Errno>
+    where
+        F: FnMut(&mut Self, &CStr, &dirent64) -> Result<bool, Errno>,
+    {
+        let dir = self.dirs.get(&fh).ok_or(Errno::EBADF)?;
+        let (stream, mut offset) = (dir.stream, dir.offset);
+        if offset != off {
+            unsafe { libc::seekdir(stream, off as c_long) };
+            offset = off;
+        }
+        let mut empty = true;
+        let result = loop {
+            // `readdir` returns null at the end as well as on errors.
+            unsafe { *libc::__errno_location() = 0 };
+            let entry = match unsafe { libc::readdir64(stream).as_ref() } {
+                Some(entry) => entry,
+                None if empty && io::Error::last_os_error().raw_os_error() != Some(0) => {
+                    break Err(last_errno())
+                }
+                None => break Ok(()),
+            };
+            let name = unsafe { CStr::from_ptr(entry.d_name.as_ptr()) };
+            match add(self, name, entry) {
+                Ok(false) => {
+                    offset = entry.d_off;
+                    empty = false;
+                }
+                // Read the entry again for the next reply.
+                Ok(true) => {
+                    unsafe { libc::seekdir(stream, offset as c_long) };
+                    break Ok(());
+                }
+                Err(err) => {
+                    unsafe { libc::seekdir(stream, offset as c_long) };
+                    break if empty { Err(err) } else { Ok(()) };
+                }
+            }
+        };
+        if let Some(dir) = self.dirs.get_mut(&fh) {
+            dir.offset = offset;
+        }
+        result
+    }
+
+    // xattrs of symlinks can't be reached through `/proc/self/fd`.
+    fn xattr_path(&self, ino: u64) -> Result<CString, Errno> {
+        let inode = self.inode(ino)?;
+        if inode.is_symlink {
+            return Err(Errno::EPERM);
+        }
+        Ok(proc_path(inode.fd.as_raw_fd()))
+    }
+}
+
+fn last_errno() -> Errno {
+    io::Error::last_os_error().into()
+}
+
+fn check<T: Ord + Default>(res: T) -> Result<T, Errno> {
+    if res < T::default() {
+        Err(last_errno())
+    } else {
+        Ok(res)
+    }
+}
+
+fn c_name(name: &[u8]) -> Result<CString, Errno> {
+    CString::new(name).map_err(|_| Errno::EINVAL)
+}
+
+// Reopens an `O_PATH` descriptor, or reaches what it refers to by path.
+fn proc_path(fd: RawFd) -> CString {
+    CString::new(format!("/proc/self/fd/{}", fd)).unwrap()
+}
+
+const EMPTY: *const c_char = b"\0".as_ptr() as *const c_char;
+
+fn stat_fd(fd: RawFd) -> Result<stat, Errno> {
+    let mut st = unsafe { std::mem::zeroed::<stat>() };
+    check(unsafe { libc::fstatat(fd, EMPTY, &mut st, AT_EMPTY_PATH | AT_SYMLINK_NOFOLLOW) })?;
+    Ok(st)
+}
+
+fn to_timespec(time: Option<TimeOrNow>) -> timespec {
+    let time = match time {
+        None => {
+            return timespec {
+                tv_sec: 0,
+                tv_nsec: UTIME_OMIT,
+            }
+        }
+        Some(TimeOrNow::Now) => {
+            return timespec {
+                tv_sec: 0,
+                tv_nsec: UTIME_NOW,
+            }
+        }
+        Some(TimeOrNow::SpecificTime(time)) => time,
+    };
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => timespec {
+            tv_sec: d.as_secs() as _,
+            tv_nsec: d.subsec_nanos() as _,
+        },
+        Err(err) => {
+            let d = err.duration();
+            let (sec, nsec) = (-(d.as_secs() as i64), d.subsec_nanos() as i64);
+            if nsec == 0 {
+                timespec {
+                    tv_sec: sec as _,
+                    tv_nsec: 0,
+                }
+            } else {
+                timespec {
+                    tv_sec: (sec - 1) as _,
+                    tv_nsec: (1_000_000_000 - nsec) as _,
+                }
+            }
+        }
+    }
+}
+
+// Asks for the size first and retries when the value grows in between.
+fn read_xattr<F: Fn(*mut c_void, usize) -> isize>(read: F) -> Result<Vec<u8>, Errno> {
+    loop {
+        let size = check(read(null_mut(), 0))? as usize;
+        let mut value = vec![0u8; size];
+        match read(value.as_mut_ptr() as *mut c_void, size) {
+            n if n >= 0 => {
+                value.truncate(n as usize);
+                return Ok(value);
+            }
+            _ => {
+                let err = last_errno();
+                if err != Errno::ERANGE {
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+register_ops! {
+    impl FileSystem for PassthroughFs {
+        fn lookup(
+            &mut self,
+            _ctx: &Request,
+            parent: u64,
+            name: &[u8],
+        ) -> Result<FuseEntryParam, Errno> {
+            self.do_lookup(parent, name)
+        }
+        fn forget(&mut self, _ctx: &Request, forget: FuseForgetData) {
+            self.forget_one(forget.ino, forget.nlookup);
+        }
+        fn forget_multi(&mut self, _ctx: &Request, forgets: Vec<FuseForgetData>) {
+            for forget in forgets {
+                self.forget_one(forget.ino, forget.nlookup);
+            }
+        }
+        fn getattr(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            _fi: Option<&mut FuseFileInfo>,
+        ) -> Result<(FuseAttr, f64), Errno> {
+            let st = stat_fd(self.fd(ino)?)?;
+            Ok((self.attr(ino, &st), self.timeout))
+        }
+        fn setattr(
+            &mut self,
+            ctx: &Request,
+            ino: u64,
+            attr: &SetAttrRequest,
+            fi: Option<&mut FuseFileInfo>,
+        ) -> Result<(FuseAttr, f64), Errno> {
+            let inode = self.inode(ino)?;
+            let fd = inode.fd.as_raw_fd();
+            let fh = fi.map(|fi| fi.fh() as RawFd);
+            let path = proc_path(fd);
+            if let Some(mode) = attr.mode {
+                check(match fh {
+                    Some(fh) => unsafe { libc::fchmod(fh, mode) },
+                    None => unsafe { libc::chmod(path.as_ptr(), mode) },
+                })?;
+            }
+            if attr.uid.is_some() || attr.gid.is_some() {
+                // -1 keeps the owner or group as it is.
+                let uid = attr.uid.unwrap_or(u32::MAX);
+                let gid = attr.gid.unwrap_or(u32::MAX);
+                check(unsafe {
+                    libc::fchownat(fd, EMPTY, uid, gid, AT_EMPTY_PATH | AT_SYMLINK_NOFOLLOW)
+                })?;
+            }
+            if let Some(size) = attr.size {
+                check(match fh {
+                    Some(fh) => unsafe { libc::ftruncate(fh, size as i64) },
+                    None => unsafe { libc::truncate(path.as_ptr(), size as i64) },
+                })?;
+            }
+            if attr.atime.is_some() || attr.mtime.is_some() {
+                let times = [to_timespec(attr.atime), to_timespec(attr.mtime)];
+                check(match fh {
+                    Some(fh) => unsafe { libc::futimens(fh, times.as_ptr()) },
+                    None if inode.is_symlink => unsafe {
+                        libc::utimensat(fd, EMPTY, times.as_ptr(), AT_EMPTY_PATH)
+                    },
+                    None => unsafe { libc::utimensat(AT_FDCWD, path.as_ptr(), times.as_ptr(), 0) },
+                })?;
+            }
+            self.getattr(ctx, ino, None)
+        }
+        fn readlink(&mut self, _ctx: &Request, ino: u64) -> Result<Vec<u8>, Errno> {
+            let mut buf = vec![0u8; PATH_MAX as usize + 1];
+            let len = check(unsafe {
+                libc::readlinkat(
+                    self.fd(ino)?,
+                    EMPTY,
+                    buf.as_mut_ptr() as *mut c_char,
+                    buf.len(),
+                )
+            })? as usize;
+            if len == buf.len() {
+                return Err(Errno::ENAMETOOLONG);
+            }
+            buf.truncate(len);
+            Ok(buf)
+        }
+        fn mknod(
+            &mut self,
+            _ctx: &Request,
+            parent: u64,
+            name: &[u8],
+            mode: u32,
+            rdev: u64,
+        ) -> Result<FuseEntryParam, Errno> {
+            let c = c_name(name)?;
+            check(unsafe { libc::mknodat(self.fd(parent)?, c.as_ptr(), mode, rdev) })?;
+            self.do_lookup(parent, name)
+        }
+        fn mkdir(
+            &mut self,
+            _ctx: &Request,
+            parent: u64,
+            name: &[u8],
+            mode: u32,
+        ) -> Result<FuseEntryParam, Errno> {
+            let c = c_name(name)?;
+            check(unsafe { libc::mkdirat(self.fd(parent)?, c.as_ptr(), mode) })?;
+            self.do_lookup(parent, name)
+        }
+        fn unlink(&mut self, _ctx: &Request, parent: u64, name: &[u8]) -> Result<(), Errno> {
+            let name = c_name(name)?;
+            check(unsafe { libc::unlinkat(self.fd(parent)?, name.as_ptr(), 0) })?;
+            Ok(())
+        }
+        fn rmdir(&mut self, _ctx: &Request, parent: u64, name: &[u8]) -> Result<(), Errno> {
+            let name = c_name(name)?;
+            check(unsafe { libc::unlinkat(self.fd(parent)?, name.as_ptr(), AT_REMOVEDIR) })?;
+            Ok(())
+        }
+        fn symlink(
+            &mut self,
+            _ctx: &Request,
+            link: &[u8],
+            parent: u64,
+            name: &[u8],
+        ) -> Result<FuseEntryParam, Errno> {
+            let (c, link) = (c_name(name)?, c_name(link)?);
+            check(unsafe { libc::symlinkat(link.as_ptr(), self.fd(parent)?, c.as_ptr()) })?;
+            self.do_lookup(parent, name)
+        }
+        fn rename(
+            &mut self,
+            _ctx: &Request,
+            parent: u64,
+            name: &[u8],
+            newparent: u64,
+            newname: &[u8],
+            flags: u32,
+        ) -> Result<(), Errno> {
+            let (name, newname) = (c_name(name)?, c_name(newname)?);
+            let (fd, newfd) = (self.fd(parent)?, self.fd(newparent)?);
+            // `RENAME_NOREPLACE`, `RENAME_EXCHANGE` and `RENAME_WHITEOUT` need renameat2.
+            check(unsafe {
+                libc::syscall(
+                    libc::SYS_renameat2,
+                    fd,
+                    name.as_ptr(),
+                    newfd,
+                    newname.as_ptr(),
+                    flags,
+                )
+            })?;
+            Ok(())
+        }
+        fn link(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            newparent: u64,
+            newname: &[u8],
+        ) -> Result<FuseEntryParam, Errno> {
+            let name = c_name(newname)?;
+            let fd = self.fd(ino)?;
+            // Linking the descriptor itself with `AT_EMPTY_PATH` needs CAP_DAC_READ_SEARCH.
+            check(unsafe {
+                libc::linkat(
+                    AT_FDCWD,
+                    proc_path(fd).as_ptr(),
+                    self.fd(newparent)?,
+                    name.as_ptr(),
+                    AT_SYMLINK_FOLLOW,
+                )
+            })?;
+            let st = stat_fd(fd)?;
+            self.inodes.get_mut(&ino).unwrap().nlookup += 1;
+            Ok(self.entry(ino, &st))
+        }
+        fn open(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            mut fi: FuseFileInfo,
+        ) -> Result<FuseFileInfo, Errno> {
+            let flags = (fi.flags().bits() & !O_NOFOLLOW) | O_CLOEXEC;
+            let fh = check(unsafe { libc::open(proc_path(self.fd(ino)?).as_ptr(), flags) })?;
+            fi.set_fh(fh as u64);
+            Ok(fi)
+        }
+        fn read(
+            &mut self,
+            _ctx: &Request,
+            _ino: u64,
+            size: usize,
+            off: i64,
+            fi: &mut FuseFileInfo,
+        ) -> Result<ReadReply<'_>, Errno> {
+            // libfuse splices the range from the file when the kernel allows it.
+            Ok(ReadReply::Fd {
+                fd: fi.fh() as RawFd,
+                offset: off,
+                size,
+            })
+        }
+        fn write(
+            &mut self,
+            _ctx: &Request,
+            _ino: u64,
+            buf: &[u8],
+            _size: usize,
+            off: i64,
+            fi: &mut FuseFileInfo,
+        ) -> Result<usize, Errno> {
+            let fh = fi.fh() as RawFd;
+            let len = buf.len();
+            let n = check(unsafe { libc::pwrite(fh, buf.as_ptr() as *const c_void, len, off) })?;
+            Ok(n as usize)
+        }
+        fn write_buf(
+            &mut self,
+            _ctx: &Request,
+            _ino: u64,
+            bufv: &mut FuseBufvec,
+            off: i64,
+            fi: &FuseFileInfo,
+        ) -> Result<usize, Errno> {
+            // Spliced from the pipe of the request when the kernel allows it.
+            bufv.copy_to_fd(fi.fh() as RawFd, Some(off), 0)
+        }
+        fn flush(&mut self, _ctx: &Request, _ino: u64, fi: &mut FuseFileInfo) -> Result<(), Errno> {
+            // Closing a duplicate reports the errors of `close(2)` without closing the file.
+            let fd = check(unsafe { libc::dup(fi.fh() as RawFd) })?;
+            check(unsafe { libc::close(fd) })?;
+            Ok(())
+        }
+        fn release(
+            &mut self,
+            _ctx: &Request,
+            _ino: u64,
+            fi: &mut FuseFileInfo,
+        ) -> Result<(), Errno> {
+            drop(unsafe { OwnedFd::from_raw_fd(fi.fh() as RawFd) });
+            Ok(())
+        }
+        fn fsync(
+            &mut self,
+            _ctx: &Request,
+            _ino: u64,
+            datasync: i32,
+            fi: &mut FuseFileInfo,
+        ) -> Result<(), Errno> {
+            let fh = fi.fh() as RawFd;
+            check(unsafe {
+                if datasync != 0 {
+                    libc::fdatasync(fh)
+                } else {
+                    libc::fsync(fh)
+                }
+            })?;
+            Ok(())
+        }
+        fn opendir(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            _fi: &mut FuseFileInfo,
+        ) -> Result<FuseFileInfo, Errno> {
+            let dot = b".\0".as_ptr() as *const c_char;
+            let fd = check(unsafe {
+                libc::openat(self.fd(ino)?, dot, O_RDONLY | O_DIRECTORY | O_CLOEXEC)
+            })?;
+            let stream = unsafe { libc::fdopendir(fd) };
+            if stream.is_null() {
+                let err = last_errno();
+                drop(unsafe { OwnedFd::from_raw_fd(fd) });
+                return Err(err);
+            }
+            let mut fi = FuseFileInfo::new();
+            fi.set_fh(fd as u64);
+            self.dirs.insert(fi.fh(), DirHandle { stream, offset: 0 });
+            Ok(fi)
+        }
+        fn readdir(
+            &mut self,
+            _ctx: &Request,
+            _ino: u64,
+            off: i64,
+            fi: &mut FuseFileInfo,
+            reply: &mut DirectoryReply,
+        ) -> Result<(), Errno> {
+            self.read_dir(fi.fh(), off, |_, name, entry| {
+                let attr = FuseAttr {
+                    ino: entry.d_ino,
+                    // `DT_*` are the `S_IF*` bits of the mode shifted down.
+                    mode: (entry.d_type as u32) << 12,
+                    ..FuseAttr::default()
+                };
+                Ok(reply.add(name.to_bytes(), entry.d_off, &attr))
+            })
+        }
+        fn readdirplus(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            off: i64,
+            fi: &mut FuseFileInfo,
+            reply: &mut DirectoryPlusReply,
+        ) -> Result<(), Errno> {
+            self.read_dir(fi.fh(), off, |fs, name, entry| {
+                let name = name.to_bytes();
+                if name == b"." || name == b".." {
+                    // Without an inode number the kernel counts no lookup.
+                    let attr = FuseAttr {
+                        ino: entry.d_ino,
+                        mode: (entry.d_type as u32) << 12,
+                        ..FuseAttr::default()
+                    };
+                    let mut param = FuseEntryParam::new(attr, 0, 0.0, 0.0);
+                    param.ino = 0;
+                    return Ok(reply.add(name, &param, entry.d_off));
+                }
+                let param = match fs.do_lookup(ino, name) {
+                    Ok(param) => param,
+                    // Removed since it was read.
+                    Err(Errno::ENOENT) => return Ok(false),
+                    Err(err) => return Err(err),
+                };
+                let full = reply.add(name, &param, entry.d_off);
+                if full {
+                    fs.forget_one(param.ino, 1);
+                }
+                Ok(full)
+            })
+        }
+        fn releasedir(
+            &mut self,
+            _ctx: &Request,
+            _ino: u64,
+            fi: &mut FuseFileInfo,
+        ) -> Result<(), Errno> {
+            self.dirs.remove(&fi.fh());
+            Ok(())
+        }
+        fn fsyncdir(
+            &mut self,
+            _ctx: &Request,
+            _ino: u64,
+            datasync: i32,
+            fi: &mut FuseFileInfo,
+        ) -> Result<(), Errno> {
+            let dir = self.dirs.get(&fi.fh()).ok_or(Errno::EBADF)?;
+            let fd = unsafe { libc::dirfd(dir.stream) };
+            check(unsafe {
+                if datasync != 0 {
+                    libc::fdatasync(fd)
+                } else {
+                    libc::fsync(fd)
+                }
+            })?;
+            Ok(())
+        }
+        fn statfs(&mut self, _ctx: &Request, ino: u64) -> Result<FuseStatvfs, Errno> {
+            let mut st = unsafe { std::mem::zeroed::<libc::statvfs>() };
+            check(unsafe { libc::fstatvfs(self.fd(ino)?, &mut st) })?;
+            Ok(FuseStatvfs::new(&st))
+        }
+        fn setxattr(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            name: &[u8],
+            value: &[u8],
+            _size: usize,
+            flags: i32,
+        ) -> Result<(), Errno> {
+            let (path, name) = (self.xattr_path(ino)?, c_name(name)?);
+            check(unsafe {
+                libc::setxattr(
+                    path.as_ptr(),
+                    name.as_ptr(),
+                    value.as_ptr() as *const c_void,
+                    value.len(),
+                    flags,
+                )
+            })?;
+            Ok(())
+        }
+        fn getxattr(
+            &mut self,
+            _ctx: &Request,
+            ino: u64,
+            name: &[u8],
+            _size: usize,
+        ) -> Result<Vec<u8>, Errno> {
+            let (path, name) = (self.xattr_path(ino)?, c_name(name)?);
+            read_xattr(|buf, size| unsafe {
+                libc::getxattr(path.as_ptr(), name.as_ptr(), buf, size)
+            })
+        }
+        fn listxattr(&mut self, _ctx: &Request, ino: u64, _size: usize) -> Result<Vec<u8>, Errno> {
+            let path = self.xattr_path(ino)?;
+            read_xattr(|buf, size| unsafe {
+                libc::listxattr(path.as_ptr(), buf as *mut c_char, size)
+            })
+        }
+        fn removexattr(&mut self, _ctx: &Request, ino: u64, name: &[u8]) -> Result<(), Errno> {
+            let (path, name) = (self.xattr_path(ino)?, c_name(name)?);
+            check(unsafe { libc::removexattr(path.as_ptr(), name.as_ptr()) })?;
+            Ok(())
+        }
+        fn access(&mut self, _ctx: &Request, ino: u64, mask: i32) -> Result<(), Errno> {
+            let path = proc_path(self.fd(ino)?);
+            check(unsafe { libc::faccessat(AT_FDCWD, path.as_ptr(), mask, 0) })?;
+            Ok(())
+        }
+        fn create(
+            &mut self,
+            _ctx: &Request,
+            parent: u64,
+            name: &[u8],
+            mode: u32,
+            fi: &mut FuseFileInfo,
+        ) -> Result<FuseEntryParam, Errno> {
+            let c = c_name(name)?;
+            let flags = (fi.flags().bits() | O_CREAT | O_CLOEXEC) & !O_NOFOLLOW;
+            let fh = check(unsafe { libc::openat(self.fd(parent)?, c.as_ptr(), flags, mode) })?;
+            match self.do_lookup(parent, name) {
+                Ok(entry) => {
+                    fi.set_fh(fh as u64);
+                    Ok(entry)
+                }
+                Err(err) => {
+                    drop(unsafe { OwnedFd::from_raw_fd(fh) });
+                    Err(err)
+                }
+            }
+        }
+        fn getlk(
+            &mut self,
+            _ctx: &Request,
+            _ino: u64,
+            fi: &mut FuseFileInfo,
+            lock: &mut FuseLock,
+        ) -> Result<FuseLock, Errno> {
+            let mut flock = lock.convert();
+            flock.l_pid = 0;
+            check(unsafe { libc::fcntl(fi.fh() as RawFd, F_OFD_GETLK, &mut flock) })?;
+            Ok(FuseLock::new(&flock))
+        }
+        fn setlk(
+            &mut self,
+            _ctx: &Request,
+            _ino: u64,
+            fi: &mut FuseFileInfo,
+            lock: &mut FuseLock,
+            sleep: i32,
+        ) -> Result<(), Errno> {
+            let mut flock = lock.convert();
+            flock.l_pid = 0;
+            // Waiting holds up every other request of a single-threaded loop.
+            let cmd = if sleep != 0 {
+                F_OFD_SETLKW
+            } else {
+                F_OFD_SETLK
+            };
+            check(unsafe { libc::fcntl(fi.fh() as RawFd, cmd, &mut flock) })?;
+            Ok(())
+        }
+        fn flock(
+            &mut self,
+            _ctx: &Request,
+            _ino: u64,
+            fi: &mut FuseFileInfo,
+            op: i32,
+        ) -> Result<(), Errno> {
+            check(unsafe { libc::flock(fi.fh() as RawFd, op) })?;
+            Ok(())
+        }
+        fn fallocate(
+            &mut self,
+            _ctx: &Request,
+            _ino: u64,
+            mode: i32,
+            offset: i64,
+            length: i64,
+            fi: &mut FuseFileInfo,
+        ) -> Result<(), Errno> {
+            check(unsafe { libc::fallocate(fi.fh() as RawFd, mode, offset, length) })?;
+            Ok(())
+        }
+        fn copy_file_range(
+            &mut self,
+            _ctx: &Request,
+            _ino_in: u64,
+            off_in: i64,
+            fi_in: &mut FuseFileInfo,
+            _ino_out: u64,
+            off_out: i64,
+            fi_out: &mut FuseFileInfo,
+            len: usize,
+            flags: i32,
+        ) -> Result<usize, Errno> {
+            let (mut off_in, mut off_out) = (off_in as off64_t, off_out as off64_t);
+            let n = check(unsafe {
+                libc::syscall(
+                    libc::SYS_copy_file_range,
+                    fi_in.fh() as RawFd,
+                    &mut off_in,
+                    fi_out.fh() as RawFd,
+                    &mut off_out,
+                    len,
+                    flags as c_uint,
+                )
+            })?;
+            Ok(n as usize)
+        }
+        fn lseek(
+            &mut self,
+            _ctx: &Request,
+            _ino: u64,
+            off: i64,
+            whence: i32,
+            fi: &mut FuseFileInfo,
+        ) -> Result<i64, Errno> {
+            check(unsafe { libc::lseek(fi.fh() as RawFd, off, whence as c_int) })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::passthrough::PassthroughFs;
+    use crate::testing::{DirEntry, MockSession};
+    use crate::{FileSystem, FuseBufvec, FuseCtx, FuseFileInfo, OFlag, Request, SetAttrRequest};
+    use libc::c_void;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const CTX: FuseCtx = FuseCtx {
+        uid: 0,
+        gid: 0,
+        pid: 0,
+        umask: 0o022,
+    };
+
+    fn ctx() -> Request {
+        Request::from(CTX)
+    }
+
+    fn source_dir() -> PathBuf {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "rusfuse-passthrough-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn create_write_read() {
+        let dir = source_dir();
+        let mut fs = PassthroughFs::new(&dir).unwrap();
+        let mut fi = FuseFileInfo::new();
+        fi.set_flags(OFlag::RDWR);
+        let entry = fs.create(&ctx(), 1, b"file", 0o644, &mut fi).unwrap();
+        assert_eq!(fs.write(&ctx(), entry.ino, b"hello", 5, 0, &mut fi), Ok(5));
+        assert_eq!(
+            fs.read(&ctx(), entry.ino, 16, 1, &mut fi)
+                .unwrap()
+                .to_vec()
+                .unwrap(),
+            b"ello"
+        );
+        let mut bufv = FuseBufvec::new(b"HE".as_ptr() as *mut c_void, 2);
+        assert_eq!(fs.write_buf(&ctx(), entry.ino, &mut bufv, 0, &fi), Ok(2));
+        fs.release(&ctx(), entry.ino, &mut fi).unwrap();
+
+        assert_eq!(fs.lookup(&ctx(), 1, b"file").unwrap().ino, entry.ino);
+        assert_eq!(fs.getattr(&ctx(), entry.ino, None).unwrap().0.size, 5);
+        assert_eq!(fs::read(dir.join("file")).unwrap(), b"HEllo");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn readdir_keeps_offsets() {
+        let dir = source_dir();
+        for name in &["a", "b", "c"] {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        let mut fs = PassthroughFs::new(&dir).unwrap();
+        let mut session = MockSession::new(&mut fs);
+        let names = |entries: Vec<DirEntry>| {
+            let mut names: Vec<_> = entries.into_iter().map(|entry| entry.name).collect();
+            names.sort();
+            names
+        };
+        let all = vec![&b"."[..], b"..", b"a", b"b", b"c"];
+        assert_eq!(names(session.readdir_all(1, 4096).unwrap()), all);
+        // A page holds one entry, the rest of the listing continues from the cookies.
+        assert_eq!(names(session.readdir_all(1, 40).unwrap()), all);
+        drop(session);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn readdirplus_looks_up_entries() {
+        let dir = source_dir();
+        fs::write(dir.join("a"), b"data").unwrap();
+        let mut fs = PassthroughFs::new(&dir).unwrap();
+        let mut session = MockSession::new(&mut fs);
+        let mut fi = session.opendir(1, &mut FuseFileInfo::new()).unwrap_open();
+        let entries = session.readdirplus(1, 4096, 0, &mut fi).unwrap_direntplus();
+        session.releasedir(1, &mut fi);
+        drop(session);
+        let a = entries.iter().find(|e| e.entry.name == b"a").unwrap();
+        assert_eq!((a.size, a.mode & libc::S_IFMT), (4, libc::S_IFREG));
+        assert_eq!(fs.lookup(&ctx(), 1, b"a").unwrap().ino, a.nodeid);
+        let dot = entries.iter().find(|e| e.entry.name == b".").unwrap();
+        assert_eq!(dot.nodeid, 0);
+
+        // Both lookups are forgotten together, like the kernel would.
+        fs.forget_one(a.nodeid, 2);
+        assert!(fs.getattr(&ctx(), a.nodeid, None).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn link_and_forget() {
+        let dir = source_dir();
+        fs::write(dir.join("a"), b"data").unwrap();
+        let mut fs = PassthroughFs::new(&dir).unwrap();
+        let ino = fs.lookup(&ctx(), 1, b"a").unwrap().ino;
+        let entry = fs.link(&ctx(), ino, 1, b"b").unwrap();
+        assert_eq!((entry.ino, entry.attr.st_nlink), (ino, 2));
+        fs.rename(&ctx(), 1, b"b", 1, b"c", 0).unwrap();
+        fs.unlink(&ctx(), 1, b"a").unwrap();
+        assert_eq!(fs.getattr(&ctx(), ino, None).unwrap().0.nlink, 1);
+
+        fs.forget_one(ino, 2);
+        assert!(fs.getattr(&ctx(), ino, None).is_err());
+        assert_ne!(fs.lookup(&ctx(), 1, b"c").unwrap().ino, ino);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn setattr_truncate_and_chmod() {
+        let dir = source_dir();
+        fs::write(dir.join("a"), b"0123456789").unwrap();
+        let mut fs = PassthroughFs::new(&dir).unwrap();
+        let ino = fs.lookup(&ctx(), 1, b"a").unwrap().ino;
+        let attr = SetAttrRequest {
+            mode: Some(0o600),
+            size: Some(3),
+            ..SetAttrRequest::default()
+        };
+        let (attr, _) = fs.setattr(&ctx(), ino, &attr, None).unwrap();
+        assert_eq!((attr.size, attr.mode & 0o777), (3, 0o600));
+        assert_eq!(fs::read(dir.join("a")).unwrap(), b"012");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
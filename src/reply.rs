@@ -0,0 +1,676 @@
+use std::borrow::Borrow;
+use std::mem;
+use std::ptr::null_mut;
+use std::sync::mpsc::Sender;
+
+use std::os::unix::io::RawFd;
+
+use libc::{c_char, c_int, c_void, iovec};
+
+use crate::capture::{self, Reply};
+use crate::errno::Errno;
+use crate::fuse::{
+    fuse_add_direntry, fuse_add_direntry_plus, fuse_reply_attr, fuse_reply_bmap, fuse_reply_buf,
+    fuse_reply_create, fuse_reply_data, fuse_reply_entry, fuse_reply_err, fuse_reply_ioctl,
+    fuse_reply_ioctl_retry, fuse_reply_iov, fuse_reply_lock, fuse_reply_lseek, fuse_reply_none,
+    fuse_reply_open, fuse_reply_poll, fuse_reply_readlink, fuse_reply_statfs, fuse_reply_write,
+    fuse_reply_xattr, FuseBufvec, FuseReq,
+};
+use crate::ioctl::IoctlIovec;
+use crate::request::{CancellationToken, Request};
+use crate::utils::{bytes_as_ptr, pread_full};
+use crate::{FuseAttr, FuseEntryParam, FuseFileInfo, FuseLock, FuseStatvfs};
+
+/// Where the answer to a request goes.
+#[derive(Debug)]
+enum Target {
+    Req(*mut FuseReq),
+    /// Replies captured by a [`MockSession`](crate::testing::MockSession).
+    Capture(Sender<Reply>),
+    Answered,
+}
+
+/// One of the `fuse_reply_*` functions of libfuse with its arguments.
+enum Answer<'a> {
+    Err(Errno),
+    Ok,
+    Entry(&'a FuseEntryParam),
+    Attr(&'a FuseAttr, f64),
+    Readlink(&'a [u8]),
+    Open(&'a FuseFileInfo),
+    Create(&'a FuseEntryParam, &'a FuseFileInfo),
+    Buf(&'a [u8]),
+    Read(&'a ReadReply<'a>),
+    Write(usize),
+    Statfs(&'a FuseStatvfs),
+    Xattr(usize),
+    Lock(&'a FuseLock),
+    Bmap(u64),
+    Poll(u32),
+    Ioctl(i32, &'a [u8]),
+    IoctlRetry(&'a [IoctlIovec], &'a [IoctlIovec]),
+    Lseek(i64),
+}
+
+/// Owns a request until it is answered exactly once.
+///
+/// A request that is dropped without an answer is failed with `EIO`, so the
+/// calling process never hangs on a forgotten reply. An error answered after the
+/// request was interrupted becomes `EINTR`.
+#[derive(Debug)]
+struct ReplyRaw {
+    target: Target,
+    cancellation: CancellationToken,
+}
+
+// libfuse allows replying to a request from any thread.
+unsafe impl Send for ReplyRaw {}
+
+impl ReplyRaw {
+    fn new(request: &Request) -> Self {
+        let cancellation = request.cancellation();
+        let req = request.raw();
+        if req.is_null() {
+            // A request of a `MockSession`, its trampoline runs on this thread.
+            let sender = capture::sender().expect("no request to reply to");
+            return Self {
+                target: Target::Capture(sender),
+                cancellation,
+            };
+        }
+        unsafe { cancellation.register(req) };
+        Self {
+            target: Target::Req(req),
+            cancellation,
+        }
+    }
+    /// The request for functions of libfuse that build a reply, null if it is captured.
+    fn req(&self) -> *mut FuseReq {
+        match self.target {
+            Target::Req(req) => req,
+            _ => null_mut(),
+        }
+    }
+    fn send(&mut self, answer: Answer) {
+        match mem::replace(&mut self.target, Target::Answered) {
+            Target::Req(req) => unsafe {
+                self.release(req);
+                // No interrupt reaches the token after this, so the answer is final.
+                reply_raw(req, self.interrupted(answer))
+            },
+            Target::Capture(sender) => {
+                let _ = sender.send(Reply::from(self.interrupted(answer)));
+            }
+            Target::Answered => unreachable!("request answered twice"),
+        }
+    }
+    fn error(mut self, err: Errno) {
+        self.send(Answer::Err(err));
+    }
+    fn into_raw(mut self) -> *mut FuseReq {
+        match mem::replace(&mut self.target, Target::Answered) {
+            Target::Req(req) => {
+                unsafe { self.release(req) };
+                req
+            }
+            _ => null_mut(),
+        }
+    }
+    // Detaches the `Request` and the interrupt callback before `req` is answered.
+    unsafe fn release(&self, req: *mut FuseReq) {
+        self.cancellation.answer();
+        self.cancellation.unregister(req);
+    }
+    fn interrupted<'a>(&self, answer: Answer<'a>) -> Answer<'a> {
+        match answer {
+            Answer::Err(_) if self.cancellation.is_cancelled() => Answer::Err(Errno::EINTR),
+            answer => answer,
+        }
+    }
+}
+
+impl Drop for ReplyRaw {
+    fn drop(&mut self) {
+        if !matches!(self.target, Target::Answered) {
+            warn!("reply dropped without an answer, replying EIO");
+            self.send(Answer::Err(Errno::EIO));
+        }
+    }
+}
+
+/// Ends a request that has no reply, e.g. `forget`.
+pub(crate) fn reply_none(request: &Request) {
+    let req = request.raw();
+    if req.is_null() {
+        if let Some(sender) = capture::sender() {
+            let _ = sender.send(Reply::None);
+        }
+    } else {
+        unsafe { fuse_reply_none(req) }
+    }
+}
+
+unsafe fn reply_raw(req: *mut FuseReq, answer: Answer) {
+    match answer {
+        Answer::Err(err) => fuse_reply_err(req, err.code()),
+        Answer::Ok => fuse_reply_err(req, 0),
+        Answer::Entry(entry) => fuse_reply_entry(req, entry),
+        Answer::Attr(attr, timeout) => fuse_reply_attr(req, attr.convert().borrow(), timeout),
+        Answer::Readlink(link) => {
+            let mut link = link.to_vec();
+            link.push(0);
+            fuse_reply_readlink(req, bytes_as_ptr(&link))
+        }
+        Answer::Open(fi) => fuse_reply_open(req, fi),
+        Answer::Create(entry, fi) => fuse_reply_create(req, entry, fi),
+        Answer::Buf(data) => fuse_reply_buf(req, bytes_as_ptr(data), data.len()),
+        Answer::Read(ReadReply::Owned(data)) => fuse_reply_buf(req, bytes_as_ptr(data), data.len()),
+        Answer::Read(ReadReply::Slice(data)) => fuse_reply_buf(req, bytes_as_ptr(data), data.len()),
+        Answer::Read(ReadReply::Iov(slices)) => {
+            let iov: Vec<iovec> = slices
+                .iter()
+                .map(|slice| iovec {
+                    iov_base: slice.as_ptr() as *mut c_void,
+                    iov_len: slice.len(),
+                })
+                .collect();
+            fuse_reply_iov(req, iov.as_ptr(), iov.len() as c_int)
+        }
+        Answer::Read(&ReadReply::Fd { fd, offset, size }) => {
+            let mut bufv = FuseBufvec::from_fd(fd, Some(offset), size);
+            fuse_reply_data(req, &mut bufv, 0)
+        }
+        Answer::Write(count) => fuse_reply_write(req, count),
+        Answer::Statfs(stbuf) => fuse_reply_statfs(req, stbuf.convert().borrow()),
+        Answer::Xattr(size) => fuse_reply_xattr(req, size),
+        Answer::Lock(lock) => fuse_reply_lock(req, lock.convert().borrow()),
+        Answer::Bmap(idx) => fuse_reply_bmap(req, idx),
+        Answer::Poll(revents) => fuse_reply_poll(req, revents),
+        Answer::Ioctl(result, data) => {
+            fuse_reply_ioctl(req, result, data.as_ptr() as *const c_void, data.len())
+        }
+        Answer::IoctlRetry(input, output) => {
+            let input: Vec<iovec> = input.iter().map(to_iovec).collect();
+            let output: Vec<iovec> = output.iter().map(to_iovec).collect();
+            fuse_reply_ioctl_retry(
+                req,
+                input.as_ptr(),
+                input.len(),
+                output.as_ptr(),
+                output.len(),
+            )
+        }
+        Answer::Lseek(off) => fuse_reply_lseek(req, off),
+    };
+}
+
+impl From<Answer<'_>> for Reply {
+    fn from(answer: Answer) -> Self {
+        match answer {
+            Answer::Err(err) => Reply::Error(err),
+            Answer::Ok => Reply::Ok,
+            Answer::Entry(entry) => Reply::Entry(entry.clone()),
+            Answer::Attr(attr, timeout) => Reply::Attr(*attr, timeout),
+            Answer::Readlink(link) => Reply::Readlink(link.to_vec()),
+            Answer::Open(fi) => Reply::Open(fi.clone()),
+            Answer::Create(entry, fi) => Reply::Create(entry.clone(), fi.clone()),
+            Answer::Buf(data) => Reply::Data(data.to_vec()),
+            Answer::Read(data) => match data.to_vec() {
+                Ok(data) => Reply::Data(data),
+                Err(err) => Reply::Error(err),
+            },
+            Answer::Write(count) => Reply::Written(count),
+            Answer::Statfs(stbuf) => Reply::Statfs(stbuf.clone()),
+            Answer::Xattr(size) => Reply::XattrSize(size),
+            Answer::Lock(lock) => Reply::Lock(lock.clone()),
+            Answer::Bmap(idx) => Reply::Bmap(idx),
+            Answer::Poll(revents) => Reply::Poll(revents),
+            Answer::Ioctl(result, data) => Reply::Ioctl(result, data.to_vec()),
+            Answer::IoctlRetry(input, output) => Reply::IoctlRetry(input.to_vec(), output.to_vec()),
+            Answer::Lseek(off) => Reply::Lseek(off),
+        }
+    }
+}
+
+macro_rules! reply {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(Debug)]
+        pub struct $name {
+            raw: ReplyRaw,
+        }
+
+        impl $name {
+            pub(crate) fn new(request: &Request) -> Self {
+                Self {
+                    raw: ReplyRaw::new(request),
+                }
+            }
+            /// Fails the request with the given errno.
+            pub fn error(self, err: Errno) {
+                self.raw.error(err);
+            }
+            /// Gives up the request to answer it with a `fuse_reply_*` function of
+            /// libfuse, which must then be called exactly once. Null for a captured reply.
+            pub fn into_raw(self) -> *mut FuseReq {
+                self.raw.into_raw()
+            }
+        }
+    };
+}
+
+reply!(
+    /// Reply for operations that only report success or failure.
+    ReplyEmpty
+);
+
+impl ReplyEmpty {
+    pub fn ok(mut self) {
+        self.raw.send(Answer::Ok);
+    }
+}
+
+reply!(
+    /// Reply for `lookup`, `mknod`, `mkdir`, `symlink` and `link`.
+    ReplyEntry
+);
+
+impl ReplyEntry {
+    pub fn entry(mut self, entry: &FuseEntryParam) {
+        self.raw.send(Answer::Entry(entry));
+    }
+}
+
+reply!(
+    /// Reply for `getattr` and `setattr`.
+    ReplyAttr
+);
+
+impl ReplyAttr {
+    pub fn attr(mut self, attr: &FuseAttr, timeout: f64) {
+        self.raw.send(Answer::Attr(attr, timeout));
+    }
+}
+
+reply!(
+    /// Reply for `readlink`.
+    ReplyReadlink
+);
+
+impl ReplyReadlink {
+    pub fn readlink(mut self, link: &[u8]) {
+        self.raw.send(Answer::Readlink(link));
+    }
+}
+
+reply!(
+    /// Reply for `open` and `opendir`.
+    ReplyOpen
+);
+
+impl ReplyOpen {
+    pub fn opened(mut self, fi: &FuseFileInfo) {
+        self.raw.send(Answer::Open(fi));
+    }
+}
+
+reply!(
+    /// Reply for `create`.
+    ReplyCreate
+);
+
+impl ReplyCreate {
+    pub fn created(mut self, entry: &FuseEntryParam, fi: &FuseFileInfo) {
+        self.raw.send(Answer::Create(entry, fi));
+    }
+}
+
+reply!(
+    /// Reply for `read`.
+    ReplyData
+);
+
+impl ReplyData {
+    pub fn data(mut self, data: &[u8]) {
+        self.raw.send(Answer::Buf(data));
+    }
+    /// Sends the data without copying it, see [`ReadReply`].
+    pub fn read(mut self, data: &ReadReply) {
+        self.raw.send(Answer::Read(data));
+    }
+}
+
+/// The data returned by `read`.
+///
+/// libfuse writes memory to the kernel straight from the given buffers. A range
+/// of a file is spliced into the kernel without passing through user space when
+/// the kernel supports it, and read into a buffer otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadReply<'a> {
+    Owned(Vec<u8>),
+    Slice(&'a [u8]),
+    /// Buffers sent one after the other, with `fuse_reply_iov`.
+    Iov(Vec<&'a [u8]>),
+    /// `size` bytes of `fd` from `offset`, fewer at the end of the file. `fd` must
+    /// stay open until the reply is sent.
+    Fd {
+        fd: RawFd,
+        offset: i64,
+        size: usize,
+    },
+}
+
+impl ReadReply<'_> {
+    /// Copies the data into memory.
+    pub fn to_vec(&self) -> Result<Vec<u8>, Errno> {
+        match self {
+            ReadReply::Owned(data) => Ok(data.clone()),
+            ReadReply::Slice(data) => Ok(data.to_vec()),
+            ReadReply::Iov(slices) => Ok(slices.concat()),
+            &ReadReply::Fd { fd, offset, size } => {
+                let mut data = vec![0u8; size];
+                let len = pread_full(fd, &mut data, offset)?;
+                data.truncate(len);
+                Ok(data)
+            }
+        }
+    }
+}
+
+impl From<Vec<u8>> for ReadReply<'_> {
+    fn from(data: Vec<u8>) -> Self {
+        ReadReply::Owned(data)
+    }
+}
+
+impl<'a> From<&'a [u8]> for ReadReply<'a> {
+    fn from(data: &'a [u8]) -> Self {
+        ReadReply::Slice(data)
+    }
+}
+
+reply!(
+    /// Reply for `write`, `write_buf` and `copy_file_range`.
+    ReplyWrite
+);
+
+impl ReplyWrite {
+    pub fn written(mut self, count: usize) {
+        self.raw.send(Answer::Write(count));
+    }
+}
+
+reply!(
+    /// Reply for `statfs`.
+    ReplyStatfs
+);
+
+impl ReplyStatfs {
+    pub fn statfs(mut self, stbuf: &FuseStatvfs) {
+        self.raw.send(Answer::Statfs(stbuf));
+    }
+}
+
+reply!(
+    /// Reply for `getxattr` and `listxattr`.
+    ///
+    /// When the caller asked with a size of zero, answer with [`ReplyXattr::size`];
+    /// otherwise answer with [`ReplyXattr::data`].
+    ReplyXattr
+);
+
+impl ReplyXattr {
+    pub fn size(mut self, size: usize) {
+        self.raw.send(Answer::Xattr(size));
+    }
+    pub fn data(mut self, data: &[u8]) {
+        self.raw.send(Answer::Buf(data));
+    }
+    /// Answers with the size or the data depending on the size requested by the caller.
+    pub fn value(self, requested: usize, value: &[u8]) {
+        if requested == 0 {
+            self.size(value.len())
+        } else if value.len() > requested {
+            self.error(Errno::ERANGE)
+        } else {
+            self.data(value)
+        }
+    }
+}
+
+reply!(
+    /// Reply for `getlk`.
+    ReplyLock
+);
+
+impl ReplyLock {
+    pub fn locked(mut self, lock: &FuseLock) {
+        self.raw.send(Answer::Lock(lock));
+    }
+}
+
+reply!(
+    /// Reply for `bmap`.
+    ReplyBmap
+);
+
+impl ReplyBmap {
+    pub fn bmap(mut self, idx: u64) {
+        self.raw.send(Answer::Bmap(idx));
+    }
+}
+
+reply!(
+    /// Reply for `poll`.
+    ReplyPoll
+);
+
+impl ReplyPoll {
+    pub fn poll(mut self, revents: u32) {
+        self.raw.send(Answer::Poll(revents));
+    }
+}
+
+reply!(
+    /// Reply for `ioctl`.
+    ReplyIoctl
+);
+
+impl ReplyIoctl {
+    /// Answers with the value returned by `ioctl(2)` and the data copied back to the caller.
+    pub fn ioctl(mut self, result: i32, data: &[u8]) {
+        self.raw.send(Answer::Ioctl(result, data));
+    }
+    /// Asks for the request to be sent again with `input` and `output` copied,
+    /// only allowed for unrestricted ioctls.
+    pub fn retry(mut self, input: &[IoctlIovec], output: &[IoctlIovec]) {
+        self.raw.send(Answer::IoctlRetry(input, output));
+    }
+}
+
+fn to_iovec(iov: &IoctlIovec) -> iovec {
+    iovec {
+        iov_base: iov.base as *mut c_void,
+        iov_len: iov.len,
+    }
+}
+
+reply!(
+    /// Reply for `lseek`.
+    ReplyLseek
+);
+
+impl ReplyLseek {
+    pub fn offset(mut self, off: i64) {
+        self.raw.send(Answer::Lseek(off));
+    }
+}
+
+/// The entries of a `readdir` reply, packed into a buffer of the size requested by the kernel.
+///
+/// The kernel passes the `offset` of the last entry it received back as `off` to
+/// continue after it, so each offset must identify a position in the directory that
+/// stays valid while entries are added and removed, and must not be zero.
+#[derive(Debug)]
+pub struct DirectoryReply {
+    req: *mut FuseReq,
+    size: usize,
+    buf: Vec<u8>,
+}
+
+// Only handed to libfuse to pack entries, which it may do on any thread.
+unsafe impl Send for DirectoryReply {}
+
+impl DirectoryReply {
+    pub(crate) fn new(req: *mut FuseReq, size: usize) -> Self {
+        Self {
+            req,
+            size,
+            buf: Vec::with_capacity(size),
+        }
+    }
+    /// Adds an entry and returns `true` if the buffer is full and the entry was not added.
+    ///
+    /// Only the inode number and the file type of `attr` reach the kernel.
+    pub fn add(&mut self, name: &[u8], offset: i64, attr: &FuseAttr) -> bool {
+        let mut name = name.to_vec();
+        name.push(0);
+        let rest = self.size - self.buf.len();
+        unsafe {
+            let entsize = fuse_add_direntry(
+                self.req,
+                self.buf.as_mut_ptr().add(self.buf.len()) as *mut c_char,
+                rest,
+                bytes_as_ptr(&name),
+                attr.convert().borrow(),
+                offset,
+            );
+            if entsize > rest {
+                return true;
+            }
+            self.buf.set_len(self.buf.len() + entsize);
+        }
+        false
+    }
+    /// Whether no entry was added, an empty reply tells the kernel the directory has ended.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
+
+/// Reply for `readdir`, see [`DirectoryReply`] for the entries.
+#[derive(Debug)]
+pub struct ReplyDirectory {
+    raw: ReplyRaw,
+    entries: DirectoryReply,
+}
+
+impl ReplyDirectory {
+    pub(crate) fn new(request: &Request, size: usize) -> Self {
+        let raw = ReplyRaw::new(request);
+        let entries = DirectoryReply::new(raw.req(), size);
+        Self { raw, entries }
+    }
+    /// Adds an entry and returns `true` if the buffer is full and the entry was not added.
+    pub fn add(&mut self, name: &[u8], offset: i64, attr: &FuseAttr) -> bool {
+        self.entries.add(name, offset, attr)
+    }
+    pub fn entries(&mut self) -> &mut DirectoryReply {
+        &mut self.entries
+    }
+    /// Sends the entries added so far.
+    pub fn ok(mut self) {
+        self.raw.send(Answer::Buf(&self.entries.buf));
+    }
+    pub fn error(self, err: Errno) {
+        self.raw.error(err);
+    }
+    /// Gives up the request like [`ReplyEmpty::into_raw`], dropping the entries.
+    pub fn into_raw(self) -> *mut FuseReq {
+        self.raw.into_raw()
+    }
+}
+
+/// The entries of a `readdirplus` reply, each with the attributes of a `lookup`.
+///
+/// Offsets work as for [`DirectoryReply`]. Every entry that is added, except `.`
+/// and `..`, counts as a lookup of its inode: the kernel sends a `forget` for it
+/// later like for an entry returned by `lookup`. An entry that doesn't fit is not
+/// added and not counted, and an `ino` of zero adds no lookup either.
+#[derive(Debug)]
+pub struct DirectoryPlusReply {
+    req: *mut FuseReq,
+    size: usize,
+    buf: Vec<u8>,
+}
+
+// Only handed to libfuse to pack entries, which it may do on any thread.
+unsafe impl Send for DirectoryPlusReply {}
+
+impl DirectoryPlusReply {
+    pub(crate) fn new(req: *mut FuseReq, size: usize) -> Self {
+        Self {
+            req,
+            size,
+            buf: Vec::with_capacity(size),
+        }
+    }
+    /// Adds an entry and returns `true` if the buffer is full and the entry was not added.
+    pub fn add(&mut self, name: &[u8], entry: &FuseEntryParam, offset: i64) -> bool {
+        let mut name = name.to_vec();
+        name.push(0);
+        let rest = self.size - self.buf.len();
+        unsafe {
+            let entsize = fuse_add_direntry_plus(
+                self.req,
+                self.buf.as_mut_ptr().add(self.buf.len()) as *mut c_char,
+                rest,
+                bytes_as_ptr(&name),
+                entry,
+                offset,
+            );
+            if entsize > rest {
+                return true;
+            }
+            self.buf.set_len(self.buf.len() + entsize);
+        }
+        false
+    }
+    /// Whether no entry was added, an empty reply tells the kernel the directory has ended.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
+
+/// Reply for `readdirplus`, see [`DirectoryPlusReply`] for the entries.
+#[derive(Debug)]
+pub struct ReplyDirectoryPlus {
+    raw: ReplyRaw,
+    entries: DirectoryPlusReply,
+}
+
+impl ReplyDirectoryPlus {
+    pub(crate) fn new(request: &Request, size: usize) -> Self {
+        let raw = ReplyRaw::new(request);
+        let entries = DirectoryPlusReply::new(raw.req(), size);
+        Self { raw, entries }
+    }
+    /// Adds an entry and returns `true` if the buffer is full and the entry was not added.
+    pub fn add(&mut self, name: &[u8], entry: &FuseEntryParam, offset: i64) -> bool {
+        self.entries.add(name, entry, offset)
+    }
+    pub fn entries(&mut self) -> &mut DirectoryPlusReply {
+        &mut self.entries
+    }
+    /// Sends the entries added so far.
+    pub fn ok(mut self) {
+        self.raw.send(Answer::Buf(&self.entries.buf));
+    }
+    pub fn error(self, err: Errno) {
+        self.raw.error(err);
+    }
+    /// Gives up the request like [`ReplyEmpty::into_raw`], dropping the entries.
+    pub fn into_raw(self) -> *mut FuseReq {
+        self.raw.into_raw()
+    }
+}
@@ -0,0 +1,281 @@
+use std::fmt;
+use std::mem;
+use std::ops::Deref;
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use libc::{c_int, c_void, gid_t};
+
+use crate::capture;
+use crate::errno::Errno;
+use crate::fuse::{fuse_req_ctx, fuse_req_getgroups, fuse_req_interrupt_func, FuseCtx, FuseReq};
+
+/// The request an operation answers, handed to every method of the filesystem.
+///
+/// It dereferences to the [`FuseCtx`] of the calling process, so `req.uid` reads
+/// like before, and tells whether the caller gave up waiting: when the process is
+/// interrupted by a signal, e.g. Ctrl-C during a long `read`, the kernel sends an
+/// interrupt for the request. An operation that fails after that is answered with
+/// `EINTR`, whatever errno it returns.
+pub struct Request {
+    raw: *mut FuseReq,
+    unique: u64,
+    ctx: FuseCtx,
+    // Of a request that doesn't come from libfuse.
+    groups: Vec<gid_t>,
+    cancellation: CancellationToken,
+}
+
+impl Request {
+    /// # Safety
+    ///
+    /// `req` must be a request of libfuse that isn't answered yet, or the fake
+    /// request of a [`MockSession`](crate::testing::MockSession).
+    pub(crate) unsafe fn new(req: *mut FuseReq, unique: u64) -> Self {
+        let captured = capture::captured(req, |captured| Self {
+            raw: null_mut(),
+            unique,
+            ctx: captured.ctx,
+            groups: captured.groups.clone(),
+            cancellation: captured.cancellation.clone(),
+        });
+        if let Some(request) = captured {
+            return request;
+        }
+        Self {
+            raw: req,
+            unique,
+            ctx: *fuse_req_ctx(req).as_ref().unwrap(),
+            groups: Vec::new(),
+            cancellation: CancellationToken::new(),
+        }
+    }
+    /// An id for log lines about the request, counted from 1 in each session and
+    /// zero for a request that doesn't come from one.
+    ///
+    /// libfuse doesn't tell the `unique` of the kernel, which it prints in its debug
+    /// output, so the two don't match.
+    pub fn unique(&self) -> u64 {
+        self.unique
+    }
+    /// The supplementary groups of the calling process, from `fuse_req_getgroups`.
+    ///
+    /// libfuse reads them from `/proc`, so this costs a few system calls and fails
+    /// with `ENOENT` once the process has exited. A request that is already answered
+    /// fails with `EINVAL`.
+    pub fn groups(&self) -> Result<Vec<gid_t>, Errno> {
+        if self.raw.is_null() {
+            return Ok(self.groups.clone());
+        }
+        let answered = self.cancellation.answered();
+        if *answered {
+            return Err(Errno::EINVAL);
+        }
+        let mut groups = Vec::new();
+        loop {
+            let size = groups.capacity() as c_int;
+            let res = unsafe { fuse_req_getgroups(self.raw, size, groups.as_mut_ptr()) };
+            if res < 0 {
+                return Err(Errno::new(res));
+            }
+            if res <= size {
+                unsafe { groups.set_len(res as usize) };
+                return Ok(groups);
+            }
+            // Truncated, `res` is the total.
+            groups.reserve_exact(res as usize);
+        }
+    }
+    /// The `fuse_req_t` of libfuse, null for a request that doesn't come from it.
+    ///
+    /// It is valid until the request is answered. To answer it with a function of
+    /// libfuse instead of the reply object, take it out with `into_raw` of the reply.
+    pub fn raw(&self) -> *mut FuseReq {
+        self.raw
+    }
+    /// Whether the kernel interrupted the request.
+    pub fn interrupted(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+    /// A token that can be polled from other threads until the request is answered.
+    pub fn cancellation(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+    /// Calls `f` once the request is interrupted, right away if it already is.
+    ///
+    /// `f` runs on the thread that receives the interrupt, while libfuse holds a lock
+    /// of the request, so it should only wake up the work and return. It is dropped
+    /// without being called when the request is answered first.
+    pub fn on_interrupt<F: FnOnce() + Send + 'static>(&self, f: F) {
+        self.cancellation.on_cancel(f);
+    }
+}
+
+/// A request of the calling process with nothing waiting on it, e.g. to call
+/// methods of a filesystem directly in unit tests.
+impl From<FuseCtx> for Request {
+    fn from(ctx: FuseCtx) -> Self {
+        Self {
+            raw: null_mut(),
+            unique: 0,
+            ctx,
+            groups: Vec::new(),
+            cancellation: CancellationToken::new(),
+        }
+    }
+}
+
+impl Deref for Request {
+    type Target = FuseCtx;
+    fn deref(&self) -> &FuseCtx {
+        &self.ctx
+    }
+}
+
+impl fmt::Debug for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Request")
+            .field("unique", &self.unique)
+            .field("ctx", &self.ctx)
+            .field("interrupted", &self.interrupted())
+            .finish()
+    }
+}
+
+#[derive(Default)]
+struct Cancellation {
+    cancelled: AtomicBool,
+    callbacks: Mutex<Vec<Box<dyn FnOnce() + Send>>>,
+    // Set by the reply before libfuse frees the request.
+    answered: Mutex<bool>,
+}
+
+impl Cancellation {
+    /// Marks the request as interrupted and runs the callbacks, once.
+    fn cancel(&self) {
+        let callbacks = {
+            let mut callbacks = self.callbacks.lock().unwrap_or_else(|err| err.into_inner());
+            if self.cancelled.swap(true, Ordering::AcqRel) {
+                return;
+            }
+            mem::take(&mut *callbacks)
+        };
+        for callback in callbacks {
+            callback();
+        }
+    }
+}
+
+/// Tells whether a request was interrupted, obtained from [`Request::cancellation`].
+///
+/// It can be cloned and moved to the threads working on the request, which poll it
+/// with [`check`](Self::check) between steps.
+#[derive(Clone)]
+pub struct CancellationToken(Arc<Cancellation>);
+
+impl CancellationToken {
+    pub(crate) fn new() -> Self {
+        Self(Arc::default())
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Acquire)
+    }
+    /// Fails with `EINTR` once the request is interrupted, to bail out with `?`.
+    pub fn check(&self) -> Result<(), Errno> {
+        if self.is_cancelled() {
+            Err(Errno::EINTR)
+        } else {
+            Ok(())
+        }
+    }
+    /// Calls `f` once the request is interrupted, see [`Request::on_interrupt`].
+    pub fn on_cancel<F: FnOnce() + Send + 'static>(&self, f: F) {
+        let mut callbacks = self
+            .0
+            .callbacks
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        if !self.is_cancelled() {
+            callbacks.push(Box::new(f));
+            return;
+        }
+        drop(callbacks);
+        f();
+    }
+    pub(crate) fn cancel(&self) {
+        self.0.cancel();
+    }
+    /// Held while the request is used through libfuse, so the reply waits for it.
+    fn answered(&self) -> MutexGuard<'_, bool> {
+        self.0
+            .answered
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+    /// Marks the request as answered, it can't be used through libfuse after this.
+    pub(crate) fn answer(&self) {
+        *self.answered() = true;
+    }
+    /// Has libfuse cancel the token when `req` is interrupted, until [`unregister`](Self::unregister).
+    ///
+    /// # Safety
+    ///
+    /// `req` must not be answered yet, and must be unregistered before it is.
+    pub(crate) unsafe fn register(&self, req: *mut FuseReq) {
+        let data = Arc::as_ptr(&self.0) as *mut c_void;
+        fuse_req_interrupt_func(req, Some(interrupted), data);
+    }
+    /// # Safety
+    ///
+    /// `req` must not be answered yet.
+    pub(crate) unsafe fn unregister(&self, req: *mut FuseReq) {
+        // Waits for a callback that is running, libfuse calls it under the same lock.
+        fuse_req_interrupt_func(req, None, null_mut());
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CancellationToken")
+            .field(&self.is_cancelled())
+            .finish()
+    }
+}
+
+extern "C" fn interrupted(_req: *mut FuseReq, data: *mut c_void) {
+    // Kept alive by the reply, which unregisters it before answering.
+    let cancellation = unsafe { (data as *const Cancellation).as_ref().unwrap() };
+    cancellation.cancel();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use crate::request::CancellationToken;
+    use crate::Errno;
+
+    #[test]
+    fn callbacks_run_once() {
+        let token = CancellationToken::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        token.on_cancel(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(token.check(), Ok(()));
+        token.cancel();
+        token.cancel();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(token.clone().check(), Err(Errno::EINTR));
+
+        // Registered late, called right away.
+        let counter = calls.clone();
+        token.on_cancel(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
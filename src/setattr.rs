@@ -0,0 +1,238 @@
+use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libc::{c_int, stat};
+
+// `FUSE_SET_ATTR_*` of libfuse, the bits of `to_set` in `setattr`.
+pub(crate) const FUSE_SET_ATTR_MODE: c_int = 1 << 0;
+pub(crate) const FUSE_SET_ATTR_UID: c_int = 1 << 1;
+pub(crate) const FUSE_SET_ATTR_GID: c_int = 1 << 2;
+pub(crate) const FUSE_SET_ATTR_SIZE: c_int = 1 << 3;
+pub(crate) const FUSE_SET_ATTR_ATIME: c_int = 1 << 4;
+pub(crate) const FUSE_SET_ATTR_MTIME: c_int = 1 << 5;
+pub(crate) const FUSE_SET_ATTR_ATIME_NOW: c_int = 1 << 7;
+pub(crate) const FUSE_SET_ATTR_MTIME_NOW: c_int = 1 << 8;
+pub(crate) const FUSE_SET_ATTR_CTIME: c_int = 1 << 10;
+
+/// A timestamp given to `utimensat(2)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeOrNow {
+    SpecificTime(SystemTime),
+    /// `UTIME_NOW`, the current time of the filesystem.
+    Now,
+}
+
+/// The attributes to change in `setattr`, `None` for the ones to keep.
+///
+/// `chmod(2)` sets `mode`, `chown(2)` `uid` and `gid`, `truncate(2)` `size` and
+/// `utimensat(2)` `atime` and `mtime`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SetAttrRequest {
+    /// The new mode, including the file type bits.
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub size: Option<u64>,
+    pub atime: Option<TimeOrNow>,
+    pub mtime: Option<TimeOrNow>,
+    pub ctime: Option<SystemTime>,
+}
+
+impl SetAttrRequest {
+    // The field types of `stat` differ between targets.
+    #[allow(clippy::unnecessary_cast)]
+    pub(crate) fn new(attr: &stat, to_set: c_int) -> Self {
+        let is_set = |bit: c_int| to_set & bit != 0;
+        let time = |bit: c_int, now: c_int, sec: i64, nsec: i64| {
+            if !is_set(bit) {
+                None
+            } else if is_set(now) {
+                Some(TimeOrNow::Now)
+            } else {
+                Some(TimeOrNow::SpecificTime(system_time(sec, nsec)))
+            }
+        };
+        Self {
+            mode: Some(attr.st_mode as u32).filter(|_| is_set(FUSE_SET_ATTR_MODE)),
+            uid: Some(attr.st_uid as u32).filter(|_| is_set(FUSE_SET_ATTR_UID)),
+            gid: Some(attr.st_gid as u32).filter(|_| is_set(FUSE_SET_ATTR_GID)),
+            size: Some(attr.st_size as u64).filter(|_| is_set(FUSE_SET_ATTR_SIZE)),
+            atime: time(
+                FUSE_SET_ATTR_ATIME,
+                FUSE_SET_ATTR_ATIME_NOW,
+                attr.st_atime as i64,
+                attr.st_atime_nsec as i64,
+            ),
+            mtime: time(
+                FUSE_SET_ATTR_MTIME,
+                FUSE_SET_ATTR_MTIME_NOW,
+                attr.st_mtime as i64,
+                attr.st_mtime_nsec as i64,
+            ),
+            ctime: Some(system_time(attr.st_ctime as i64, attr.st_ctime_nsec as i64))
+                .filter(|_| is_set(FUSE_SET_ATTR_CTIME)),
+        }
+    }
+    /// The `stat` and `to_set` bits that libfuse hands to `setattr` for this request.
+    // The field types of `stat` differ between targets.
+    #[allow(clippy::unnecessary_cast)]
+    pub(crate) fn to_raw(&self) -> (stat, c_int) {
+        let mut attr: stat = unsafe { mem::zeroed() };
+        let mut to_set = 0;
+        if let Some(mode) = self.mode {
+            attr.st_mode = mode as _;
+            to_set |= FUSE_SET_ATTR_MODE;
+        }
+        if let Some(uid) = self.uid {
+            attr.st_uid = uid as _;
+            to_set |= FUSE_SET_ATTR_UID;
+        }
+        if let Some(gid) = self.gid {
+            attr.st_gid = gid as _;
+            to_set |= FUSE_SET_ATTR_GID;
+        }
+        if let Some(size) = self.size {
+            attr.st_size = size as _;
+            to_set |= FUSE_SET_ATTR_SIZE;
+        }
+        match self.atime {
+            Some(TimeOrNow::SpecificTime(time)) => {
+                let (sec, nsec) = timespec(time);
+                attr.st_atime = sec as _;
+                attr.st_atime_nsec = nsec as _;
+                to_set |= FUSE_SET_ATTR_ATIME;
+            }
+            Some(TimeOrNow::Now) => to_set |= FUSE_SET_ATTR_ATIME | FUSE_SET_ATTR_ATIME_NOW,
+            None => {}
+        }
+        match self.mtime {
+            Some(TimeOrNow::SpecificTime(time)) => {
+                let (sec, nsec) = timespec(time);
+                attr.st_mtime = sec as _;
+                attr.st_mtime_nsec = nsec as _;
+                to_set |= FUSE_SET_ATTR_MTIME;
+            }
+            Some(TimeOrNow::Now) => to_set |= FUSE_SET_ATTR_MTIME | FUSE_SET_ATTR_MTIME_NOW,
+            None => {}
+        }
+        if let Some(ctime) = self.ctime {
+            let (sec, nsec) = timespec(ctime);
+            attr.st_ctime = sec as _;
+            attr.st_ctime_nsec = nsec as _;
+            to_set |= FUSE_SET_ATTR_CTIME;
+        }
+        (attr, to_set)
+    }
+}
+
+fn system_time(sec: i64, nsec: i64) -> SystemTime {
+    let nsec = Duration::from_nanos(nsec as u64);
+    if sec >= 0 {
+        UNIX_EPOCH + Duration::from_secs(sec as u64) + nsec
+    } else {
+        UNIX_EPOCH - Duration::from_secs(sec.unsigned_abs()) + nsec
+    }
+}
+
+// The inverse of `system_time`, with `nsec` below a second also before the epoch.
+fn timespec(time: SystemTime) -> (i64, i64) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => (since.as_secs() as i64, since.subsec_nanos() as i64),
+        Err(err) => {
+            let before = err.duration();
+            match before.subsec_nanos() {
+                0 => (-(before.as_secs() as i64), 0),
+                nsec => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nsec as i64),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::setattr::*;
+    use std::mem;
+
+    fn attr() -> stat {
+        let mut attr: stat = unsafe { mem::zeroed() };
+        attr.st_mode = libc::S_IFREG | 0o640;
+        attr.st_uid = 1000;
+        attr.st_gid = 100;
+        attr.st_size = 4096;
+        attr.st_atime = 1_600_000_000;
+        attr.st_atime_nsec = 5;
+        attr.st_mtime = -1;
+        attr.st_mtime_nsec = 500_000_000;
+        attr.st_ctime = 1_700_000_000;
+        attr
+    }
+
+    #[test]
+    fn nothing_set() {
+        assert_eq!(SetAttrRequest::new(&attr(), 0), SetAttrRequest::default());
+    }
+
+    #[test]
+    fn chmod_chown_truncate() {
+        let to_set = FUSE_SET_ATTR_MODE | FUSE_SET_ATTR_UID | FUSE_SET_ATTR_SIZE;
+        let req = SetAttrRequest::new(&attr(), to_set);
+        assert_eq!(req.mode, Some(libc::S_IFREG | 0o640));
+        assert_eq!(req.uid, Some(1000));
+        assert_eq!(req.gid, None);
+        assert_eq!(req.size, Some(4096));
+        assert_eq!(req.atime, None);
+    }
+
+    #[test]
+    fn utimens() {
+        let to_set = FUSE_SET_ATTR_ATIME
+            | FUSE_SET_ATTR_MTIME
+            | FUSE_SET_ATTR_MTIME_NOW
+            | FUSE_SET_ATTR_CTIME;
+        let req = SetAttrRequest::new(&attr(), to_set);
+        assert_eq!(
+            req.atime,
+            Some(TimeOrNow::SpecificTime(
+                UNIX_EPOCH + Duration::new(1_600_000_000, 5)
+            ))
+        );
+        assert_eq!(req.mtime, Some(TimeOrNow::Now));
+        assert_eq!(
+            req.ctime,
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+    }
+
+    #[test]
+    fn time_before_epoch() {
+        let req = SetAttrRequest::new(&attr(), FUSE_SET_ATTR_MTIME);
+        assert_eq!(
+            req.mtime,
+            Some(TimeOrNow::SpecificTime(
+                UNIX_EPOCH - Duration::from_millis(500)
+            ))
+        );
+    }
+
+    #[test]
+    fn back_to_raw() {
+        let all = FUSE_SET_ATTR_MODE
+            | FUSE_SET_ATTR_UID
+            | FUSE_SET_ATTR_GID
+            | FUSE_SET_ATTR_SIZE
+            | FUSE_SET_ATTR_ATIME
+            | FUSE_SET_ATTR_MTIME
+            | FUSE_SET_ATTR_CTIME;
+        let req = SetAttrRequest::new(&attr(), all);
+        let (attr, to_set) = req.to_raw();
+        assert_eq!(to_set, all);
+        assert_eq!(SetAttrRequest::new(&attr, to_set), req);
+
+        let req = SetAttrRequest {
+            atime: Some(TimeOrNow::Now),
+            ..SetAttrRequest::default()
+        };
+        let (attr, to_set) = req.to_raw();
+        assert_eq!(SetAttrRequest::new(&attr, to_set), req);
+    }
+}
@@ -0,0 +1,139 @@
+use crate::connection::ConnectionInfo;
+use crate::errno::Errno;
+use crate::filesystem::FileSystem;
+use crate::fuse::{
+    FuseAttr, FuseBufvec, FuseEntryParam, FuseFileInfo, FuseForgetData, FuseLock, FuseStatvfs,
+};
+use crate::ioctl::{IoctlOutput, IoctlRequest};
+use crate::notify::PollHandle;
+use crate::reply::{DirectoryPlusReply, DirectoryReply, ReadReply};
+use crate::request::Request;
+use crate::setattr::SetAttrRequest;
+use std::ops::{Deref, DerefMut};
+
+/// A variant of [`FileSystem`] whose methods take `&self`.
+///
+/// Implementations are shared between the worker threads of
+/// [`Fuse::run_mt`](crate::Fuse::run_mt), so requests are handled in parallel and
+/// any mutable state has to be synchronized by the implementation itself.
+pub trait SyncFileSystem: Send + Sync {
+    /// Operations registered with libfuse, a mask of [`FuseOpFlag`](crate::FuseOpFlag) values.
+    ///
+    /// Generated by [`register_ops!`](crate::register_ops), or written by hand where the
+    /// macro doesn't apply; operations left out of the mask are answered by libfuse
+    /// itself, usually with `ENOSYS`.
+    fn ops() -> u64
+    where
+        Self: Sized;
+    /// Called once before any other operation to inspect and adjust the connection.
+    fn init(&self, _conn: &mut ConnectionInfo) -> Result<(), Errno> {
+        Err(Errno::ENOSYS)
+    }
+    fn destroy(&self) -> Result<(), Errno> {
+        Err(Errno::ENOSYS)
+    }
+    /// Adds the entries after `off`, which is zero or the offset of the last entry
+    /// returned, until [`DirectoryReply::add`] reports that the reply is full.
+    fn readdir(
+        &self,
+        _ctx: &Request,
+        _ino: u64,
+        _off: i64,
+        _fi: &mut FuseFileInfo,
+        _reply: &mut DirectoryReply,
+    ) -> Result<(), Errno> {
+        Err(Errno::ENOSYS)
+    }
+    /// Like [`readdir`](Self::readdir) with the attributes of every entry, which
+    /// spares the kernel a `lookup` per entry. See [`DirectoryPlusReply`] for the
+    /// lookup counts.
+    fn readdirplus(
+        &self,
+        _ctx: &Request,
+        _ino: u64,
+        _off: i64,
+        _fi: &mut FuseFileInfo,
+        _reply: &mut DirectoryPlusReply,
+    ) -> Result<(), Errno> {
+        Err(Errno::ENOSYS)
+    }
+    operations!(default_methods(&Self));
+}
+
+/// Exposes a shared [`SyncFileSystem`] as a [`FileSystem`] for the duration of one request.
+pub(crate) struct SharedRef<'a, T>(pub(crate) &'a T);
+
+impl<'a, T: SyncFileSystem> Deref for SharedRef<'a, T> {
+    type Target = dyn FileSystem + 'a;
+    fn deref(&self) -> &Self::Target {
+        self
+    }
+}
+
+impl<'a, T: SyncFileSystem> DerefMut for SharedRef<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self
+    }
+}
+
+// Passes the operations of `operations!` on to the shared filesystem.
+macro_rules! forward_methods {
+    (
+        answered {
+            $(
+                $(#[$meta:meta])*
+                fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty, $reply:ty;
+            )*
+        }
+        unanswered {
+            $(
+                $(#[$unanswered_meta:meta])*
+                fn $unanswered:ident($($unanswered_arg:ident: $unanswered_ty:ty),* $(,)?);
+            )*
+        }
+    ) => {
+        $(
+            fn $name(&mut self, ctx: &Request, $($arg: $ty),*) -> Result<$ret, Errno> {
+                self.0.$name(ctx, $($arg),*)
+            }
+        )*
+        $(
+            fn $unanswered(&mut self, ctx: &Request, $($unanswered_arg: $unanswered_ty),*) {
+                self.0.$unanswered(ctx, $($unanswered_arg),*)
+            }
+        )*
+    };
+}
+
+impl<T: SyncFileSystem> FileSystem for SharedRef<'_, T> {
+    fn ops() -> u64 {
+        T::ops()
+    }
+    fn init(&mut self, conn: &mut ConnectionInfo) -> Result<(), Errno> {
+        self.0.init(conn)
+    }
+    fn destroy(&mut self) -> Result<(), Errno> {
+        self.0.destroy()
+    }
+    fn readdir(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        off: i64,
+        fi: &mut FuseFileInfo,
+        reply: &mut DirectoryReply,
+    ) -> Result<(), Errno> {
+        self.0.readdir(ctx, ino, off, fi, reply)
+    }
+    fn readdirplus(
+        &mut self,
+        ctx: &Request,
+        ino: u64,
+        off: i64,
+        fi: &mut FuseFileInfo,
+        reply: &mut DirectoryPlusReply,
+    ) -> Result<(), Errno> {
+        self.0.readdirplus(ctx, ino, off, fi, reply)
+    }
+    operations!(forward_methods);
+}
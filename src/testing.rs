@@ -0,0 +1,171 @@
+//! Helpers to test filesystems, through a real mount or a [`MockSession`].
+//!
+//! ```no_run
+//! use std::fs;
+//!
+//! let mount = match rusfuse::testing::mount_temp(rusfuse::InMemoryFs::new()) {
+//!     Some(mount) => mount,
+//!     // No FUSE in this environment.
+//!     None => return,
+//! };
+//! fs::write(mount.path().join("a"), b"hello").unwrap();
+//! assert_eq!(fs::read(mount.path().join("a")).unwrap(), b"hello");
+//! ```
+
+use std::ffi::{CString, OsStr};
+use std::fs::{self, OpenOptions};
+use std::os::unix::ffi::OsStrExt;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+use crate::{FileSystem, Fuse, FuseBuilder, MountOptions};
+
+mod mock;
+
+pub use self::mock::{DirEntry, DirEntryPlus, MockSession};
+pub use crate::capture::{PendingReply, Reply};
+
+/// A filesystem mounted on a temporary directory by [`mount_temp`].
+///
+/// Dropping it unmounts the filesystem, waits for the thread serving it and
+/// removes the directory.
+pub struct TempMount<T> {
+    path: PathBuf,
+    thread: Option<JoinHandle<T>>,
+}
+
+/// Mounts `file_system` on a new directory under [`std::env::temp_dir`] and serves
+/// it from a background thread.
+///
+/// Returns `None` when `/dev/fuse` is missing or can't be opened, so tests can skip
+/// themselves in containers without FUSE. The signal handlers of libfuse are left
+/// out, see [`FuseBuilder::signal_handlers`].
+///
+/// # Panics
+///
+/// Panics if the directory can't be created or mounting fails for another reason.
+pub fn mount_temp<T: FileSystem + Send + 'static>(file_system: T) -> Option<TempMount<T>> {
+    mount_temp_with(file_system, MountOptions::new())
+}
+
+/// Like [`mount_temp`], with the given mount options.
+pub fn mount_temp_with<T: FileSystem + Send + 'static>(
+    file_system: T,
+    options: MountOptions,
+) -> Option<TempMount<T>> {
+    mount_temp_with_builder(file_system, move |builder| builder.options(options))
+}
+
+/// Like [`mount_temp`], with the builder set up by `configure` before mounting.
+///
+/// ```no_run
+/// use rusfuse::{InMemoryFs, SpliceConfig};
+///
+/// let mount = rusfuse::testing::mount_temp_with_builder(InMemoryFs::new(), |builder| {
+///     builder.splice(SpliceConfig::all())
+/// });
+/// ```
+pub fn mount_temp_with_builder<T, F>(mut file_system: T, configure: F) -> Option<TempMount<T>>
+where
+    T: FileSystem + Send + 'static,
+    F: FnOnce(FuseBuilder) -> FuseBuilder + Send + 'static,
+{
+    if let Err(err) = OpenOptions::new().read(true).write(true).open("/dev/fuse") {
+        eprintln!("skipping the mount, /dev/fuse is unusable: {}", err);
+        return None;
+    }
+    let path = temp_dir().expect("failed to create a temporary mountpoint");
+    let mountpoint = path.to_string_lossy().into_owned();
+    let (sender, receiver) = mpsc::channel();
+    let thread = thread::spawn(move || {
+        let builder = Fuse::builder(&mountpoint).signal_handlers(false);
+        match configure(builder).mount(&mut file_system) {
+            Ok(mut fuse) => {
+                let _ = sender.send(Ok(()));
+                fuse.run();
+            }
+            Err(err) => {
+                let _ = sender.send(Err(err));
+            }
+        }
+        file_system
+    });
+    match receiver.recv() {
+        Ok(Ok(())) => Some(TempMount {
+            path,
+            thread: Some(thread),
+        }),
+        Ok(Err(err)) => {
+            let _ = fs::remove_dir(&path);
+            panic!("failed to mount on {}: {}", path.display(), err);
+        }
+        Err(_) => {
+            let _ = fs::remove_dir(&path);
+            match thread.join() {
+                Err(payload) => panic::resume_unwind(payload),
+                Ok(_) => unreachable!("the mount thread returned without a result"),
+            }
+        }
+    }
+}
+
+impl<T> TempMount<T> {
+    /// The mountpoint.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Unmounts the filesystem and gives it back, to inspect its state.
+    pub fn unmount(mut self) -> T {
+        match self.stop() {
+            Ok(file_system) => file_system,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    fn stop(&mut self) -> thread::Result<T> {
+        let thread = self.thread.take().expect("unmounted twice");
+        unmount(&self.path);
+        let result = thread.join();
+        let _ = fs::remove_dir(&self.path);
+        result
+    }
+}
+
+impl<T> Drop for TempMount<T> {
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            // Don't panic while the test may already be unwinding.
+            let _ = self.stop();
+        }
+    }
+}
+
+// Once the kernel drops the connection the session loop returns and the thread ends.
+fn unmount(path: &Path) {
+    let fusermount = Command::new("fusermount3")
+        .arg("-u")
+        .arg("-z")
+        .arg(path)
+        .stderr(Stdio::null())
+        .status();
+    if !matches!(fusermount, Ok(status) if status.success()) {
+        let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        unsafe { libc::umount2(path.as_ptr(), libc::MNT_DETACH) };
+    }
+}
+
+fn temp_dir() -> std::io::Result<PathBuf> {
+    let template = std::env::temp_dir().join("rusfuse-XXXXXX");
+    let template = CString::new(template.into_os_string().as_bytes())?;
+    let raw = template.into_raw();
+    let res = unsafe { libc::mkdtemp(raw) };
+    let path = unsafe { CString::from_raw(raw) };
+    if res.is_null() {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(PathBuf::from(OsStr::from_bytes(path.as_bytes())))
+}
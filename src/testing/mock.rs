@@ -0,0 +1,864 @@
+use std::convert::TryInto;
+use std::ffi::CString;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr::null_mut;
+use std::sync::atomic::AtomicU64;
+use std::sync::mpsc;
+
+use libc::{c_char, c_void, dev_t, gid_t, mode_t, statvfs};
+
+use crate::capture::{self, Captured, PendingReply, Reply};
+use crate::connection::ConnectionInfo;
+use crate::deferred_filesystem::DeferredFileSystem;
+use crate::errno::Errno;
+use crate::fuse::{
+    FileType, FuseAttr, FuseBufvec, FuseConnInfo, FuseCtx, FuseEntryParam, FuseFileInfo,
+    FuseForgetData, FuseLock, FuseLowLevelOps, FuseReq, FuseStatvfs,
+};
+use crate::ioctl::IoctlRequest;
+use crate::notify::shared_session;
+use crate::request::CancellationToken;
+use crate::setattr::SetAttrRequest;
+use crate::{
+    Deferred, Dispatch, Exclusive, FileSystem, FuseCapFlag, FuseOps, SessionData, Shared,
+    SyncFileSystem,
+};
+
+macro_rules! unwrap_reply {
+    ($(#[$meta:meta])* $name:ident, $variant:ident($($field:ident),+) -> $ty:ty) => {
+        $(#[$meta])*
+        pub fn $name(self) -> $ty {
+            match self {
+                Reply::$variant($($field),+) => ($($field),+),
+                other => panic!(
+                    concat!("expected Reply::", stringify!($variant), ", got {:?}"),
+                    other
+                ),
+            }
+        }
+    };
+}
+
+impl Reply {
+    /// The errno of an error reply.
+    pub fn err(&self) -> Option<Errno> {
+        match self {
+            Reply::Error(err) => Some(*err),
+            _ => None,
+        }
+    }
+    unwrap_reply!(unwrap_entry, Entry(entry) -> FuseEntryParam);
+    unwrap_reply!(unwrap_attr, Attr(attr, timeout) -> (FuseAttr, f64));
+    unwrap_reply!(unwrap_readlink, Readlink(link) -> Vec<u8>);
+    unwrap_reply!(unwrap_open, Open(fi) -> FuseFileInfo);
+    unwrap_reply!(unwrap_create, Create(entry, fi) -> (FuseEntryParam, FuseFileInfo));
+    unwrap_reply!(unwrap_data, Data(data) -> Vec<u8>);
+    unwrap_reply!(unwrap_written, Written(count) -> usize);
+    unwrap_reply!(unwrap_statfs, Statfs(statfs) -> FuseStatvfs);
+    unwrap_reply!(unwrap_xattr_size, XattrSize(size) -> usize);
+    unwrap_reply!(unwrap_lseek, Lseek(off) -> i64);
+    /// Decodes the entries that `readdir` packed with `fuse_add_direntry`.
+    pub fn unwrap_dirents(self) -> Vec<DirEntry> {
+        DirEntry::parse(&self.unwrap_data()).expect("truncated readdir reply")
+    }
+    /// Decodes the entries that `readdirplus` packed with `fuse_add_direntry_plus`.
+    pub fn unwrap_direntplus(self) -> Vec<DirEntryPlus> {
+        DirEntryPlus::parse(&self.unwrap_data()).expect("truncated readdirplus reply")
+    }
+}
+
+/// An entry of a `readdir` reply, `struct fuse_dirent` of the kernel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub ino: u64,
+    /// The offset to continue reading after this entry.
+    pub off: i64,
+    /// The file type bits of the mode, e.g. `S_IFDIR`.
+    pub mode: u32,
+    pub name: Vec<u8>,
+}
+
+impl DirEntry {
+    // ino, off, namelen and type, followed by the name padded to 8 bytes.
+    const HEADER: usize = 24;
+
+    /// Splits the buffer of a `readdir` reply into its entries.
+    ///
+    /// Fails with `EIO`, like the kernel, when the buffer ends inside an entry.
+    pub fn parse(mut buf: &[u8]) -> Result<Vec<DirEntry>, Errno> {
+        let mut entries = Vec::new();
+        while !buf.is_empty() {
+            let (entry, size) = Self::parse_one(buf)?;
+            entries.push(entry);
+            buf = &buf[size.min(buf.len())..];
+        }
+        Ok(entries)
+    }
+
+    // The first entry and its padded size.
+    fn parse_one(buf: &[u8]) -> Result<(DirEntry, usize), Errno> {
+        if buf.len() < Self::HEADER {
+            return Err(Errno::EIO);
+        }
+        let namelen = u32_at(buf, 16) as usize;
+        let name = buf[Self::HEADER..].get(..namelen).ok_or(Errno::EIO)?;
+        let entry = DirEntry {
+            ino: u64_at(buf, 0),
+            off: u64_at(buf, 8) as i64,
+            mode: u32_at(buf, 20) << 12,
+            name: name.to_vec(),
+        };
+        Ok((entry, (Self::HEADER + namelen + 7) & !7))
+    }
+
+    pub fn file_type(&self) -> FileType {
+        FileType::new(self.mode)
+    }
+}
+
+/// An entry of a `readdirplus` reply, `struct fuse_direntplus` of the kernel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntryPlus {
+    /// The inode the kernel counts a lookup for, zero for none.
+    pub nodeid: u64,
+    pub generation: u64,
+    pub size: u64,
+    pub mode: u32,
+    pub nlink: u32,
+    pub entry: DirEntry,
+}
+
+impl DirEntryPlus {
+    // `struct fuse_entry_out` with its `struct fuse_attr`, then the dirent.
+    const HEADER: usize = 128;
+
+    /// Splits the buffer of a `readdirplus` reply into its entries, see [`DirEntry::parse`].
+    pub fn parse(mut buf: &[u8]) -> Result<Vec<DirEntryPlus>, Errno> {
+        let mut entries = Vec::new();
+        while !buf.is_empty() {
+            if buf.len() < Self::HEADER {
+                return Err(Errno::EIO);
+            }
+            let (entry, size) = DirEntry::parse_one(&buf[Self::HEADER..])?;
+            entries.push(DirEntryPlus {
+                nodeid: u64_at(buf, 0),
+                generation: u64_at(buf, 8),
+                size: u64_at(buf, 48),
+                mode: u32_at(buf, 100),
+                nlink: u32_at(buf, 104),
+                entry,
+            });
+            buf = &buf[(Self::HEADER + size).min(buf.len())..];
+        }
+        Ok(entries)
+    }
+}
+
+fn u64_at(buf: &[u8], at: usize) -> u64 {
+    u64::from_ne_bytes(buf[at..at + 8].try_into().unwrap())
+}
+
+fn u32_at(buf: &[u8], at: usize) -> u32 {
+    u32::from_ne_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+/// Sends requests to a filesystem without a kernel mount and captures the replies.
+///
+/// Requests take the path of the mounted filesystem from the point where libfuse
+/// calls the trampolines, with a fake `fuse_req_t` in place of one of libfuse:
+/// names and data are handed over as C arguments and converted like the ones of the
+/// kernel, e.g. `readdir` entries are paged and packed with `fuse_add_direntry`,
+/// and the reply objects record what they would send to libfuse. Operations the
+/// filesystem doesn't register are answered like libfuse does, mostly with `ENOSYS`.
+///
+/// ```no_run
+/// use rusfuse::testing::MockSession;
+/// use rusfuse::InMemoryFs;
+///
+/// let mut fs = InMemoryFs::new();
+/// let mut session = MockSession::new(&mut fs);
+/// let entry = session.mkdir(1, b"dir", 0o755).unwrap_entry();
+/// assert_eq!(session.lookup(1, b"dir").unwrap_entry().ino, entry.ino);
+/// ```
+pub struct MockSession<'a> {
+    ops: FuseLowLevelOps,
+    data: Box<SessionData>,
+    // Called directly, the trampolines drop what they return.
+    init: unsafe fn(*mut c_void, &mut ConnectionInfo) -> Result<(), Errno>,
+    destroy: unsafe fn(*mut c_void) -> Result<(), Errno>,
+    ctx: FuseCtx,
+    groups: Vec<gid_t>,
+    interrupt_next: bool,
+    conn: FuseConnInfo,
+    file_system: PhantomData<&'a mut ()>,
+}
+
+unsafe fn init<D: Dispatch>(userdata: *mut c_void, conn: &mut ConnectionInfo) -> Result<(), Errno> {
+    D::file_system(userdata).init(conn)
+}
+
+unsafe fn destroy<D: Dispatch>(userdata: *mut c_void) -> Result<(), Errno> {
+    D::file_system(userdata).destroy()
+}
+
+// Names reach the trampolines NUL-terminated, like from libfuse.
+fn c_name(name: &[u8]) -> CString {
+    CString::new(name).expect("names can't contain NUL")
+}
+
+impl<'a> MockSession<'a> {
+    pub fn new<T: FileSystem>(file_system: &'a mut T) -> Self {
+        Self::with::<Exclusive<T>>(file_system as *mut T as *mut c_void, T::ops())
+    }
+    pub fn new_sync<T: SyncFileSystem>(file_system: &'a T) -> Self {
+        Self::with::<Shared<T>>(file_system as *const T as *mut c_void, T::ops())
+    }
+    pub fn new_deferred<T: DeferredFileSystem>(file_system: &'a mut T) -> Self {
+        Self::with::<Deferred<T>>(file_system as *mut T as *mut c_void, T::ops())
+    }
+    fn with<D: Dispatch>(file_system: *mut c_void, ops: u64) -> Self {
+        let capable = FuseCapFlag::AsyncRead
+            | FuseCapFlag::PosixLocks
+            | FuseCapFlag::AtomicOTrunc
+            | FuseCapFlag::ExportSupport
+            | FuseCapFlag::FlockLocks
+            | FuseCapFlag::IoctlDir
+            | FuseCapFlag::AutoInvalData
+            | FuseCapFlag::Readdirplus
+            | FuseCapFlag::ReaddirplusAuto
+            | FuseCapFlag::AsyncDio
+            | FuseCapFlag::WritebackCache
+            | FuseCapFlag::ParallelDirops
+            | FuseCapFlag::CacheSymlinks;
+        Self {
+            ops: FuseOps::fuse_low_level_ops::<D>(ops),
+            data: Box::new(SessionData {
+                file_system,
+                splice: None,
+                session: shared_session(),
+                next_unique: AtomicU64::new(1),
+            }),
+            init: init::<D>,
+            destroy: destroy::<D>,
+            ctx: FuseCtx {
+                uid: unsafe { libc::getuid() },
+                gid: unsafe { libc::getgid() },
+                pid: unsafe { libc::getpid() },
+                umask: 0o022,
+            },
+            groups: Vec::new(),
+            interrupt_next: false,
+            conn: FuseConnInfo {
+                proto_major: 7,
+                proto_minor: 31,
+                max_write: 128 * 1024,
+                max_read: 0,
+                max_readahead: 128 * 1024,
+                capable,
+                // What libfuse enables by default out of the above.
+                want: capable
+                    & !(FuseCapFlag::WritebackCache as u32)
+                    & !(FuseCapFlag::CacheSymlinks as u32),
+                max_background: 12,
+                congestion_threshold: 9,
+                time_gran: 1,
+                reserved: [0; 22],
+            },
+            file_system: PhantomData,
+        }
+    }
+    /// Sends the following requests on behalf of another process, the current one by default.
+    pub fn ctx(mut self, ctx: FuseCtx) -> Self {
+        self.ctx = ctx;
+        self
+    }
+    /// Supplementary groups of the caller, as returned by [`Request::groups`](crate::Request::groups), none by default.
+    pub fn groups(mut self, groups: Vec<gid_t>) -> Self {
+        self.groups = groups;
+        self
+    }
+    /// Interrupts the next request before the filesystem sees it.
+    pub fn interrupt_next(&mut self) {
+        self.interrupt_next = true;
+    }
+    /// The connection as left by `init`.
+    pub fn connection(&mut self) -> &mut ConnectionInfo {
+        unsafe { ConnectionInfo::from_raw(&mut self.conn) }
+    }
+
+    // Runs the trampoline `op` with a fake request and captures the reply, `ENOSYS`
+    // when the operation isn't registered.
+    fn call<O, F: FnOnce(O, *mut FuseReq)>(&mut self, op: Option<O>, f: F) -> Reply {
+        let op = match op {
+            Some(op) => op,
+            None => return Reply::Error(Errno::ENOSYS),
+        };
+        let (sender, receiver) = mpsc::channel();
+        let cancellation = CancellationToken::new();
+        if mem::replace(&mut self.interrupt_next, false) {
+            cancellation.cancel();
+        }
+        let captured = Captured {
+            userdata: &mut *self.data as *mut SessionData as *mut c_void,
+            ctx: self.ctx,
+            groups: self.groups.clone(),
+            sender,
+            cancellation: cancellation.clone(),
+        };
+        capture::with_fake_req(captured, |req| f(op, req));
+        match receiver.try_recv() {
+            Ok(reply) => reply,
+            Err(_) => Reply::Pending(PendingReply::new(receiver, cancellation)),
+        }
+    }
+
+    pub fn init(&mut self) -> Result<(), Errno> {
+        if self.ops.init.is_none() {
+            return Ok(());
+        }
+        let conn = unsafe { ConnectionInfo::from_raw(&mut self.conn) };
+        unsafe { (self.init)(self.data.file_system, conn) }
+    }
+    pub fn destroy(&mut self) -> Result<(), Errno> {
+        if self.ops.destroy.is_none() {
+            return Ok(());
+        }
+        unsafe { (self.destroy)(self.data.file_system) }
+    }
+    pub fn lookup(&mut self, parent: u64, name: &[u8]) -> Reply {
+        let name = c_name(name);
+        self.call(self.ops.lookup, |f, req| f(req, parent, name.as_ptr()))
+    }
+    pub fn forget(&mut self, ino: u64, nlookup: u64) -> Reply {
+        if self.ops.forget.is_none() {
+            return Reply::None;
+        }
+        self.call(self.ops.forget, |f, req| f(req, ino, nlookup))
+    }
+    pub fn getattr(&mut self, ino: u64, fi: Option<&mut FuseFileInfo>) -> Reply {
+        let fi = fi.map_or(null_mut(), |fi| fi as *mut _);
+        self.call(self.ops.getattr, |f, req| f(req, ino, fi))
+    }
+    pub fn setattr(
+        &mut self,
+        ino: u64,
+        attr: &SetAttrRequest,
+        fi: Option<&mut FuseFileInfo>,
+    ) -> Reply {
+        let (mut attr, to_set) = attr.to_raw();
+        let fi = fi.map_or(null_mut(), |fi| fi as *mut _);
+        self.call(self.ops.setattr, |f, req| {
+            f(req, ino, &mut attr, to_set, fi)
+        })
+    }
+    pub fn readlink(&mut self, ino: u64) -> Reply {
+        self.call(self.ops.readlink, |f, req| f(req, ino))
+    }
+    pub fn mknod(&mut self, parent: u64, name: &[u8], mode: u32, rdev: u64) -> Reply {
+        let name = c_name(name);
+        self.call(self.ops.mknod, |f, req| {
+            f(req, parent, name.as_ptr(), mode as mode_t, rdev as dev_t)
+        })
+    }
+    pub fn mkdir(&mut self, parent: u64, name: &[u8], mode: u32) -> Reply {
+        let name = c_name(name);
+        self.call(self.ops.mkdir, |f, req| {
+            f(req, parent, name.as_ptr(), mode as mode_t)
+        })
+    }
+    pub fn unlink(&mut self, parent: u64, name: &[u8]) -> Reply {
+        let name = c_name(name);
+        self.call(self.ops.unlink, |f, req| f(req, parent, name.as_ptr()))
+    }
+    pub fn rmdir(&mut self, parent: u64, name: &[u8]) -> Reply {
+        let name = c_name(name);
+        self.call(self.ops.rmdir, |f, req| f(req, parent, name.as_ptr()))
+    }
+    pub fn symlink(&mut self, link: &[u8], parent: u64, name: &[u8]) -> Reply {
+        let (link, name) = (c_name(link), c_name(name));
+        self.call(self.ops.symlink, |f, req| {
+            f(req, link.as_ptr(), parent, name.as_ptr())
+        })
+    }
+    pub fn rename(
+        &mut self,
+        parent: u64,
+        name: &[u8],
+        newparent: u64,
+        newname: &[u8],
+        flags: u32,
+    ) -> Reply {
+        let (name, newname) = (c_name(name), c_name(newname));
+        self.call(self.ops.rename, |f, req| {
+            f(
+                req,
+                parent,
+                name.as_ptr(),
+                newparent,
+                newname.as_ptr(),
+                flags,
+            )
+        })
+    }
+    pub fn link(&mut self, ino: u64, newparent: u64, newname: &[u8]) -> Reply {
+        let newname = c_name(newname);
+        self.call(self.ops.link, |f, req| {
+            f(req, ino, newparent, newname.as_ptr())
+        })
+    }
+    pub fn open(&mut self, ino: u64, mut fi: FuseFileInfo) -> Reply {
+        // libfuse opens files of filesystems without `open` itself.
+        if self.ops.open.is_none() {
+            return Reply::Open(fi);
+        }
+        self.call(self.ops.open, |f, req| f(req, ino, &mut fi))
+    }
+    pub fn read(&mut self, ino: u64, size: usize, off: i64, fi: &mut FuseFileInfo) -> Reply {
+        self.call(self.ops.read, |f, req| f(req, ino, size, off, fi))
+    }
+    pub fn write(&mut self, ino: u64, buf: &[u8], off: i64, fi: &mut FuseFileInfo) -> Reply {
+        self.call(self.ops.write, |f, req| {
+            f(req, ino, buf.as_ptr() as *const c_char, buf.len(), off, fi)
+        })
+    }
+    pub fn flush(&mut self, ino: u64, fi: &mut FuseFileInfo) -> Reply {
+        self.call(self.ops.flush, |f, req| f(req, ino, fi))
+    }
+    pub fn release(&mut self, ino: u64, fi: &mut FuseFileInfo) -> Reply {
+        if self.ops.release.is_none() {
+            return Reply::Ok;
+        }
+        self.call(self.ops.release, |f, req| f(req, ino, fi))
+    }
+    pub fn fsync(&mut self, ino: u64, datasync: i32, fi: &mut FuseFileInfo) -> Reply {
+        self.call(self.ops.fsync, |f, req| f(req, ino, datasync, fi))
+    }
+    pub fn opendir(&mut self, ino: u64, fi: &mut FuseFileInfo) -> Reply {
+        if self.ops.opendir.is_none() {
+            return Reply::Open(fi.clone());
+        }
+        self.call(self.ops.opendir, |f, req| f(req, ino, fi))
+    }
+    /// A `readdir` with a buffer of `size` bytes, decode the reply with [`Reply::unwrap_dirents`].
+    pub fn readdir(&mut self, ino: u64, size: usize, off: i64, fi: &mut FuseFileInfo) -> Reply {
+        self.call(self.ops.readdir, |f, req| f(req, ino, size, off, fi))
+    }
+    /// Lists a directory like the kernel: `opendir`, then `readdir` with buffers of
+    /// `size` bytes from the offset of the last entry until the reply is empty, then
+    /// `releasedir`.
+    pub fn readdir_all(&mut self, ino: u64, size: usize) -> Result<Vec<DirEntry>, Errno> {
+        let mut fi = match self.opendir(ino, &mut FuseFileInfo::new()) {
+            Reply::Open(fi) => fi,
+            reply => return Err(reply.err().unwrap_or(Errno::EIO)),
+        };
+        let mut entries: Vec<DirEntry> = Vec::new();
+        let result = loop {
+            let off = entries.last().map_or(0, |entry| entry.off);
+            match self.readdir(ino, size, off, &mut fi) {
+                Reply::Data(data) if data.is_empty() => break Ok(entries),
+                Reply::Data(data) => match DirEntry::parse(&data) {
+                    Ok(page) => entries.extend(page),
+                    Err(err) => break Err(err),
+                },
+                reply => break Err(reply.err().unwrap_or(Errno::EIO)),
+            }
+        };
+        self.releasedir(ino, &mut fi);
+        result
+    }
+    pub fn releasedir(&mut self, ino: u64, fi: &mut FuseFileInfo) -> Reply {
+        if self.ops.releasedir.is_none() {
+            return Reply::Ok;
+        }
+        self.call(self.ops.releasedir, |f, req| f(req, ino, fi))
+    }
+    pub fn fsyncdir(&mut self, ino: u64, datasync: i32, fi: &mut FuseFileInfo) -> Reply {
+        self.call(self.ops.fsyncdir, |f, req| f(req, ino, datasync, fi))
+    }
+    pub fn statfs(&mut self, ino: u64) -> Reply {
+        if self.ops.statfs.is_none() {
+            // What libfuse answers for filesystems without `statfs`.
+            let mut st: statvfs = unsafe { mem::zeroed() };
+            st.f_namemax = 255;
+            st.f_bsize = 512;
+            return Reply::Statfs(FuseStatvfs::new(&st));
+        }
+        self.call(self.ops.statfs, |f, req| f(req, ino))
+    }
+    pub fn setxattr(&mut self, ino: u64, name: &[u8], value: &[u8], flags: i32) -> Reply {
+        let name = c_name(name);
+        self.call(self.ops.setxattr, |f, req| {
+            let value_ptr = value.as_ptr() as *const c_char;
+            f(req, ino, name.as_ptr(), value_ptr, value.len(), flags)
+        })
+    }
+    pub fn getxattr(&mut self, ino: u64, name: &[u8], size: usize) -> Reply {
+        let name = c_name(name);
+        self.call(self.ops.getxattr, |f, req| f(req, ino, name.as_ptr(), size))
+    }
+    pub fn listxattr(&mut self, ino: u64, size: usize) -> Reply {
+        self.call(self.ops.listxattr, |f, req| f(req, ino, size))
+    }
+    pub fn removexattr(&mut self, ino: u64, name: &[u8]) -> Reply {
+        let name = c_name(name);
+        self.call(self.ops.removexattr, |f, req| f(req, ino, name.as_ptr()))
+    }
+    pub fn access(&mut self, ino: u64, mask: i32) -> Reply {
+        self.call(self.ops.access, |f, req| f(req, ino, mask))
+    }
+    pub fn create(&mut self, parent: u64, name: &[u8], mode: u32, fi: &mut FuseFileInfo) -> Reply {
+        let name = c_name(name);
+        self.call(self.ops.create, |f, req| {
+            f(req, parent, name.as_ptr(), mode as mode_t, fi)
+        })
+    }
+    pub fn getlk(&mut self, ino: u64, fi: &mut FuseFileInfo, lock: &FuseLock) -> Reply {
+        let mut lock = lock.convert();
+        self.call(self.ops.getlk, |f, req| f(req, ino, fi, &mut lock))
+    }
+    pub fn setlk(&mut self, ino: u64, fi: &mut FuseFileInfo, lock: &FuseLock, sleep: i32) -> Reply {
+        let mut lock = lock.convert();
+        self.call(self.ops.setlk, |f, req| f(req, ino, fi, &mut lock, sleep))
+    }
+    pub fn bmap(&mut self, ino: u64, blocksize: usize, idx: u64) -> Reply {
+        self.call(self.ops.bmap, |f, req| f(req, ino, blocksize, idx))
+    }
+    pub fn ioctl(&mut self, ino: u64, fi: &mut FuseFileInfo, ioctl: &IoctlRequest) -> Reply {
+        self.call(self.ops.ioctl, |f, req| {
+            let in_buf = ioctl.in_buf.as_ptr() as *const c_void;
+            let arg = ioctl.arg as *mut c_void;
+            f(
+                req,
+                ino,
+                ioctl.cmd,
+                arg,
+                fi,
+                ioctl.flags,
+                in_buf,
+                ioctl.in_buf.len(),
+                ioctl.out_size,
+            )
+        })
+    }
+    /// A `poll` of a caller that doesn't wait, so without a poll handle.
+    pub fn poll(&mut self, ino: u64, fi: &mut FuseFileInfo) -> Reply {
+        self.call(self.ops.poll, |f, req| f(req, ino, fi, null_mut()))
+    }
+    /// A `write_buf` with the data in a single memory buffer. Without `write_buf`,
+    /// libfuse sends the data to `write`.
+    pub fn write_buf(&mut self, ino: u64, buf: &[u8], off: i64, fi: &FuseFileInfo) -> Reply {
+        let mut fi = fi.clone();
+        if self.ops.write_buf.is_none() {
+            return self.write(ino, buf, off, &mut fi);
+        }
+        let mut bufv = FuseBufvec::new(buf.as_ptr() as *mut c_void, buf.len());
+        self.call(self.ops.write_buf, |f, req| {
+            f(req, ino, &mut bufv, off, &mut fi)
+        })
+    }
+    pub fn retrieve_reply(&mut self, cookie: u64, ino: u64, offset: i64, data: &[u8]) -> Reply {
+        if self.ops.retrieve_reply.is_none() {
+            return Reply::None;
+        }
+        let mut bufv = FuseBufvec::new(data.as_ptr() as *mut c_void, data.len());
+        self.call(self.ops.retrieve_reply, |f, req| {
+            f(req, cookie as *mut c_void, ino, offset, &mut bufv)
+        })
+    }
+    /// Without `forget_multi`, libfuse hands the forgets to `forget` one by one.
+    pub fn forget_multi(&mut self, mut forgets: Vec<FuseForgetData>) -> Reply {
+        if self.ops.forget_multi.is_none() {
+            for forget in forgets {
+                self.forget(forget.ino, forget.nlookup);
+            }
+            return Reply::None;
+        }
+        self.call(self.ops.forget_multi, |f, req| {
+            f(req, forgets.len(), forgets.as_mut_ptr())
+        })
+    }
+    pub fn flock(&mut self, ino: u64, fi: &mut FuseFileInfo, op: i32) -> Reply {
+        self.call(self.ops.flock, |f, req| f(req, ino, fi, op))
+    }
+    pub fn fallocate(
+        &mut self,
+        ino: u64,
+        mode: i32,
+        offset: i64,
+        length: i64,
+        fi: &mut FuseFileInfo,
+    ) -> Reply {
+        self.call(self.ops.fallocate, |f, req| {
+            f(req, ino, mode, offset, length, fi)
+        })
+    }
+    pub fn readdirplus(&mut self, ino: u64, size: usize, off: i64, fi: &mut FuseFileInfo) -> Reply {
+        self.call(self.ops.readdirplus, |f, req| f(req, ino, size, off, fi))
+    }
+    #[allow(clippy::too_many_arguments)]
+    pub fn copy_file_range(
+        &mut self,
+        ino_in: u64,
+        off_in: i64,
+        fi_in: &mut FuseFileInfo,
+        ino_out: u64,
+        off_out: i64,
+        fi_out: &mut FuseFileInfo,
+        len: usize,
+        flags: i32,
+    ) -> Reply {
+        self.call(self.ops.copy_file_range, |f, req| {
+            f(
+                req, ino_in, off_in, fi_in, ino_out, off_out, fi_out, len, flags,
+            )
+        })
+    }
+    pub fn lseek(&mut self, ino: u64, off: i64, whence: i32, fi: &mut FuseFileInfo) -> Reply {
+        self.call(self.ops.lseek, |f, req| f(req, ino, off, whence, fi))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{DirEntry, DirEntryPlus, MockSession, Reply};
+    use crate::{
+        DeferredFileSystem, Errno, FileSystem, FuseEntryParam, FuseFileInfo, InMemoryFs,
+        ReplyEntry, Request,
+    };
+
+    #[test]
+    fn entry_and_error_replies() {
+        let mut fs = InMemoryFs::new();
+        let mut session = MockSession::new(&mut fs);
+        let ino = session.mkdir(1, b"d", 0o755).unwrap_entry().ino;
+        assert_eq!(session.lookup(1, b"d").unwrap_entry().ino, ino);
+        assert_eq!(session.lookup(1, b"x").err(), Some(Errno::ENOENT));
+        assert_eq!(session.getattr(ino, None).unwrap_attr().0.nlink, 2);
+        assert!(matches!(session.forget(ino, 2), Reply::None));
+    }
+
+    #[test]
+    fn xattr_sizes() {
+        let mut fs = InMemoryFs::new();
+        let mut session = MockSession::new(&mut fs);
+        assert!(matches!(
+            session.setxattr(1, b"user.a", b"abc", 0),
+            Reply::Ok
+        ));
+        assert_eq!(session.getxattr(1, b"user.a", 0).unwrap_xattr_size(), 3);
+        assert_eq!(session.getxattr(1, b"user.a", 2).err(), Some(Errno::ERANGE));
+        assert_eq!(session.getxattr(1, b"user.a", 3).unwrap_data(), b"abc");
+    }
+
+    #[test]
+    fn write_and_read() {
+        let mut fs = InMemoryFs::new();
+        let mut session = MockSession::new(&mut fs);
+        let mut fi = FuseFileInfo::new();
+        let (entry, mut fi) = session.create(1, b"f", 0o644, &mut fi).unwrap_create();
+        assert_eq!(
+            session
+                .write(entry.ino, b"hello", 0, &mut fi)
+                .unwrap_written(),
+            5
+        );
+        assert_eq!(session.read(entry.ino, 3, 2, &mut fi).unwrap_data(), b"llo");
+        assert!(matches!(session.release(entry.ino, &mut fi), Reply::Ok));
+    }
+
+    // NUL bytes in data and names that aren't UTF-8 survive the trampolines both ways.
+    #[test]
+    fn binary_data() {
+        let data = b"\0a\0\xff\xfe\x89PNG\r\n\x1a\n\0";
+        let name = b"\xff\xfe\xc3\x28";
+        let mut fs = InMemoryFs::new();
+        let mut session = MockSession::new(&mut fs);
+        let mut fi = FuseFileInfo::new();
+        let (entry, mut fi) = session.create(1, name, 0o644, &mut fi).unwrap_create();
+        assert_eq!(
+            session.write(entry.ino, data, 0, &mut fi).unwrap_written(),
+            data.len()
+        );
+        assert_eq!(session.read(entry.ino, 64, 0, &mut fi).unwrap_data(), data);
+        assert_eq!(
+            session.read(entry.ino, 3, 1, &mut fi).unwrap_data(),
+            b"a\0\xff"
+        );
+
+        let xattr = b"user.\xff\xfe";
+        assert!(matches!(
+            session.setxattr(entry.ino, xattr, data, 0),
+            Reply::Ok
+        ));
+        assert!(matches!(
+            session.setxattr(entry.ino, b"user.a", b"", 0),
+            Reply::Ok
+        ));
+        let size = session.getxattr(entry.ino, xattr, 0).unwrap_xattr_size();
+        assert_eq!(size, data.len());
+        assert_eq!(session.getxattr(entry.ino, xattr, size).unwrap_data(), data);
+        assert_eq!(session.getxattr(entry.ino, b"user.a", 8).unwrap_data(), b"");
+        let list = b"user.a\0user.\xff\xfe\0";
+        let size = session.listxattr(entry.ino, 0).unwrap_xattr_size();
+        assert_eq!(size, list.len());
+        assert_eq!(session.listxattr(entry.ino, size).unwrap_data(), list);
+
+        let mut dir = session.opendir(1, &mut FuseFileInfo::new()).unwrap_open();
+        let entries = session.readdir(1, 4096, 0, &mut dir).unwrap_dirents();
+        assert_eq!(entries[2].name, name);
+        assert_eq!(entries[2].ino, entry.ino);
+        let entries = session
+            .readdirplus(1, 4096, 0, &mut dir)
+            .unwrap_direntplus();
+        assert_eq!(entries[2].entry.name, name);
+        assert_eq!(entries[2].size, data.len() as u64);
+        assert_eq!(session.lookup(1, name).unwrap_entry().ino, entry.ino);
+    }
+
+    #[test]
+    fn readdir_pages() {
+        let mut fs = InMemoryFs::new();
+        let mut session = MockSession::new(&mut fs);
+        for name in &[b"a", b"b", b"c"] {
+            session.mkdir(1, *name, 0o755).unwrap_entry();
+        }
+        let mut fi = session.opendir(1, &mut FuseFileInfo::new()).unwrap_open();
+        // Room for two entries with names of up to eight bytes.
+        let page = session.readdir(1, 64, 0, &mut fi).unwrap_dirents();
+        let names: Vec<_> = page.iter().map(|entry| entry.name.clone()).collect();
+        assert_eq!(names, [b".".to_vec(), b"..".to_vec()]);
+        let page = session
+            .readdir(1, 64, page[1].off, &mut fi)
+            .unwrap_dirents();
+        assert_eq!(page[0].name, b"a");
+        assert_eq!(page[0].mode, libc::S_IFDIR);
+
+        let all = session.readdir_all(1, 4096).unwrap();
+        assert_eq!(all.len(), 5);
+        assert_eq!(session.readdir_all(1, 32).unwrap(), all);
+    }
+
+    #[test]
+    fn truncated_dirents() {
+        let mut fs = InMemoryFs::new();
+        let mut session = MockSession::new(&mut fs);
+        session.mkdir(1, b"abcdefghijk", 0o755).unwrap_entry();
+        let mut fi = session.opendir(1, &mut FuseFileInfo::new()).unwrap_open();
+        let data = session.readdir(1, 4096, 0, &mut fi).unwrap_data();
+        assert_eq!(DirEntry::parse(&data).unwrap().len(), 3);
+        // Inside the last name, then inside its header.
+        assert_eq!(DirEntry::parse(&data[..data.len() - 8]), Err(Errno::EIO));
+        assert_eq!(DirEntry::parse(&data[..data.len() - 24]), Err(Errno::EIO));
+        let data = session.readdirplus(1, 4096, 0, &mut fi).unwrap_data();
+        assert_eq!(DirEntryPlus::parse(&data).unwrap().len(), 3);
+        assert_eq!(
+            DirEntryPlus::parse(&data[..data.len() - 8]),
+            Err(Errno::EIO)
+        );
+    }
+
+    struct Parked(Option<ReplyEntry>);
+
+    register_ops! {
+        impl DeferredFileSystem for Parked {
+            fn lookup(&mut self, _ctx: &Request, _parent: u64, _name: &[u8], reply: ReplyEntry) {
+                self.0 = Some(reply);
+            }
+        }
+    }
+
+    #[test]
+    fn pending_reply() {
+        let mut fs = Parked(None);
+        let reply = MockSession::new_deferred(&mut fs).lookup(1, b"a");
+        let pending = match reply {
+            Reply::Pending(pending) => pending,
+            other => panic!("not pending: {:?}", other),
+        };
+        fs.0.take().unwrap().error(Errno::EAGAIN);
+        assert_eq!(pending.wait().err(), Some(Errno::EAGAIN));
+    }
+
+    #[test]
+    fn interrupted_errors_become_eintr() {
+        let mut fs = Parked(None);
+        let pending = match MockSession::new_deferred(&mut fs).lookup(1, b"a") {
+            Reply::Pending(pending) => pending,
+            other => panic!("not pending: {:?}", other),
+        };
+        pending.interrupt();
+        fs.0.take().unwrap().error(Errno::EIO);
+        assert_eq!(pending.wait().err(), Some(Errno::EINTR));
+    }
+
+    struct Slow;
+
+    register_ops! {
+        impl FileSystem for Slow {
+            fn lookup(
+                &mut self,
+                ctx: &Request,
+                _parent: u64,
+                _name: &[u8],
+            ) -> Result<FuseEntryParam, Errno> {
+                ctx.cancellation().check()?;
+                Err(Errno::ENOENT)
+            }
+        }
+    }
+
+    struct Groups(Vec<Vec<libc::gid_t>>);
+
+    register_ops! {
+        impl FileSystem for Groups {
+            fn access(&mut self, req: &Request, _ino: u64, _mask: i32) -> Result<(), Errno> {
+                self.0.push(req.groups()?);
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn request_groups() {
+        let mut fs = Groups(Vec::new());
+        let mut session = MockSession::new(&mut fs).groups(vec![10, 20]);
+        assert!(matches!(session.access(1, 0), Reply::Ok));
+        drop(session);
+        assert_eq!(fs.0, [vec![10, 20]]);
+    }
+
+    struct Uniques(Vec<u64>);
+
+    register_ops! {
+        impl FileSystem for Uniques {
+            fn access(&mut self, req: &Request, _ino: u64, _mask: i32) -> Result<(), Errno> {
+                self.0.push(req.unique());
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn request_uniques() {
+        let mut fs = Uniques(Vec::new());
+        let mut session = MockSession::new(&mut fs);
+        for _ in 0..3 {
+            assert!(matches!(session.access(1, 0), Reply::Ok));
+        }
+        drop(session);
+        assert_eq!(fs.0, [1, 2, 3]);
+        // Each session counts on its own.
+        assert!(matches!(MockSession::new(&mut fs).access(1, 0), Reply::Ok));
+        assert_eq!(fs.0, [1, 2, 3, 1]);
+    }
+
+    #[test]
+    fn interrupt_next_request() {
+        let mut fs = Slow;
+        let mut session = MockSession::new(&mut fs);
+        session.interrupt_next();
+        assert_eq!(session.lookup(1, b"a").err(), Some(Errno::EINTR));
+        assert_eq!(session.lookup(1, b"a").err(), Some(Errno::ENOENT));
+    }
+}
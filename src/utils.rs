@@ -1,9 +1,73 @@
+use std::io;
 use std::mem::size_of;
+use std::os::unix::io::RawFd;
 use std::slice;
 
+use libc::{c_char, c_void};
+
+use crate::errno::Errno;
+
 #[allow(dead_code)]
 pub fn to_bytes<T>(data: &T) -> &[u8] {
     let v = data as *const T as *const u8;
     let s = size_of::<T>();
     return unsafe { slice::from_raw_parts(v, s) };
 }
+
+/// Borrows `size` bytes from a buffer handed over by libfuse.
+///
+/// Unlike `CStr::from_ptr`, the data is not cut at the first NUL byte,
+/// so binary payloads of `write` and `setxattr` reach the filesystem intact.
+pub(crate) unsafe fn bytes_from_raw<'a>(ptr: *const c_char, size: usize) -> &'a [u8] {
+    if ptr.is_null() || size == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(ptr as *const u8, size)
+    }
+}
+
+/// Returns a pointer to be passed with `data.len()` to the `fuse_reply_*` functions.
+pub(crate) fn bytes_as_ptr(data: &[u8]) -> *const c_char {
+    data.as_ptr() as *const c_char
+}
+
+// Reads until `buf` is full or the end of the file, a short read means EOF to the kernel.
+pub(crate) fn pread_full(fd: RawFd, buf: &mut [u8], off: i64) -> Result<usize, Errno> {
+    let mut done = 0;
+    while done < buf.len() {
+        let n = unsafe {
+            libc::pread(
+                fd,
+                buf[done..].as_mut_ptr() as *mut c_void,
+                buf.len() - done,
+                off + done as i64,
+            )
+        };
+        match n {
+            0 => break,
+            n if n < 0 => {
+                let err = Errno::from(io::Error::last_os_error());
+                if err != Errno::EINTR {
+                    return Err(err);
+                }
+            }
+            n => done += n as usize,
+        }
+    }
+    Ok(done)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::bytes_from_raw;
+    use std::ptr::null;
+
+    // A null pointer never reaches `slice::from_raw_parts`, whatever the size.
+    #[test]
+    fn input_data_from_null_pointer_is_empty() {
+        let data = unsafe { bytes_from_raw(null(), 0) };
+        assert!(data.is_empty());
+        let data = unsafe { bytes_from_raw(null(), 16) };
+        assert!(data.is_empty());
+    }
+}
@@ -0,0 +1,94 @@
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
+
+use rusfuse::testing::mount_temp;
+use rusfuse::{Fuse, InMemoryFs, MountError};
+
+#[test]
+fn files_and_directories() {
+    let mount = match mount_temp(InMemoryFs::new()) {
+        Some(mount) => mount,
+        None => return,
+    };
+    let dir = mount.path().join("dir");
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("a"), b"hello world").unwrap();
+    fs::hard_link(dir.join("a"), mount.path().join("b")).unwrap();
+
+    let mut file = fs::File::open(mount.path().join("b")).unwrap();
+    let mut buf = String::new();
+    file.seek(SeekFrom::Start(6)).unwrap();
+    file.read_to_string(&mut buf).unwrap();
+    assert_eq!(buf, "world");
+    assert_eq!(fs::metadata(dir.join("a")).unwrap().nlink(), 2);
+
+    let mut names: Vec<_> = fs::read_dir(mount.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["b", "dir"]);
+
+    fs::rename(mount.path().join("b"), dir.join("c")).unwrap();
+    assert!(fs::remove_dir(&dir).is_err());
+    fs::remove_file(dir.join("a")).unwrap();
+    fs::remove_file(dir.join("c")).unwrap();
+    fs::remove_dir(&dir).unwrap();
+}
+
+#[test]
+fn unmount_returns_the_filesystem() {
+    let mount = match mount_temp(InMemoryFs::new()) {
+        Some(mount) => mount,
+        None => return,
+    };
+    let path = mount.path().to_path_buf();
+    fs::write(path.join("a"), b"").unwrap();
+    mount.unmount();
+    assert!(!path.exists());
+}
+
+#[test]
+fn mountpoint_errors() {
+    let mut file_system = InMemoryFs::new();
+    let missing = std::env::temp_dir().join(format!("rusfuse-missing-{}", std::process::id()));
+    match Fuse::builder(missing.to_str().unwrap()).mount(&mut file_system) {
+        Err(MountError::MissingMountpoint(path)) => assert_eq!(path, missing),
+        other => panic!("unexpected {:?}", other.err()),
+    }
+    let file = std::env::temp_dir().join(format!("rusfuse-file-{}", std::process::id()));
+    fs::write(&file, b"").unwrap();
+    let result = Fuse::builder(file.to_str().unwrap()).mount(&mut file_system);
+    fs::remove_file(&file).unwrap();
+    match result {
+        Err(MountError::NotADirectory(path)) => assert_eq!(path, file),
+        other => panic!("unexpected {:?}", other.err()),
+    }
+
+    // Failures other than a missing mountpoint keep their cause.
+    let dir = std::env::temp_dir().join(format!("rusfuse-errors-{}", std::process::id()));
+    fs::create_dir(&dir).unwrap();
+    let looped = dir.join("loop");
+    symlink(&looped, &looped).unwrap();
+    fs::write(dir.join("file"), b"").unwrap();
+    for path in &[looped.clone(), dir.join("file/sub")] {
+        match Fuse::builder(path.to_str().unwrap()).mount(&mut file_system) {
+            Err(MountError::Io(err_path, _)) => assert_eq!(&err_path, path),
+            other => panic!("unexpected {:?}", other.err()),
+        }
+    }
+    let locked = dir.join("locked");
+    fs::create_dir_all(locked.join("sub")).unwrap();
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+    let result = Fuse::builder(locked.join("sub").to_str().unwrap()).mount(&mut file_system);
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    // Root searches any directory.
+    if unsafe { libc::geteuid() } != 0 {
+        match result {
+            Err(MountError::PermissionDenied(path)) => assert_eq!(path, locked.join("sub")),
+            other => panic!("unexpected {:?}", other.err()),
+        }
+    }
+}